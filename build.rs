@@ -1,3 +1,65 @@
+/// Variables the firmware reads with option_env!(), defaults are used when unset
+#[cfg(feature = "esp32")]
+const OPTIONAL_VARS: &[&str] = &[
+    // Credentials are only stored in NVS on first boot, later boots read NVS
+    "WIFI_SSID",
+    "WIFI_PASS",
+    "MQTT_BROKER",
+    "MQTT_USERNAME",
+    "MQTT_PASSWORD",
+    "STATION_ID",
+    "STATION_LOCATION",
+    "WIFI_COUNTRY",
+    "WIFI_BANDWIDTH",
+    "LOG_LEVEL",
+    "LOG_FORMAT",
+    "WATCHDOG_TIMEOUT_SECS",
+    "LED_GPIO",
+    "BUTTON_GPIO",
+    "STATS_INTERVAL_SECS",
+    "HEAP_ALERT_BYTES",
+    "UNIQUE_WINDOW_SECS",
+    "AGGREGATE_WINDOW_MS",
+    "AGGREGATE_FN",
+    "SEND_RATES",
+    "SKIP_OWN_BSSID",
+    "MIN_PACKET_LEN",
+    "CAPTURE_CONTROL_FRAMES",
+    "PROBES_ONLY",
+    "CAPTURE_JOIN_FRAMES",
+    "FIRST_SEEN_ONLY",
+    "DEPARTURE_TIMEOUT_SECS",
+    "DEVICE_TOPICS",
+    "BEACON_STATS",
+    "ARRIVAL_STATS",
+    "DUTY_ACTIVE_SECS",
+    "DUTY_SLEEP_SECS",
+];
+
+/// Short hash of the checked out commit (`-dirty` with local changes), "unknown" outside git
+#[cfg(feature = "esp32")]
+fn git_hash() -> String {
+    let git = |args: &[&str]| {
+        std::process::Command::new("git")
+            .args(args)
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+    match git(&["rev-parse", "--short", "HEAD"]) {
+        Some(hash) if !hash.is_empty() => {
+            let dirty = git(&["status", "--porcelain"]).is_some_and(|status| !status.is_empty());
+            if dirty {
+                format!("{}-dirty", hash)
+            } else {
+                hash
+            }
+        }
+        _ => "unknown".to_string(),
+    }
+}
+
 fn main() {
     // Only run ESP-IDF build configuration for the esp32 binary
     #[cfg(feature = "esp32")]
@@ -17,27 +79,50 @@ fn main() {
             );
         }
 
-        // Re-export environment variables to make them available to env!() macro
-        // This is necessary because build.rs runs in a separate process
-        if let Ok(ssid) = std::env::var("WIFI_SSID") {
-            println!("cargo:rustc-env=WIFI_SSID={}", ssid);
-        }
-        if let Ok(pass) = std::env::var("WIFI_PASS") {
-            println!("cargo:rustc-env=WIFI_PASS={}", pass);
+        // Without credentials a new board waits for them on the serial console
+        for var in ["WIFI_SSID", "MQTT_BROKER"] {
+            if std::env::var(var).map_or(true, |value| value.is_empty()) {
+                println!(
+                    "cargo:warning={} is not set, boards without stored credentials must be \
+                     provisioned over serial (see README)",
+                    var
+                );
+            }
         }
-        if let Ok(broker) = std::env::var("MQTT_BROKER") {
-            println!("cargo:rustc-env=MQTT_BROKER={}", broker);
-        }
-        if let Ok(username) = std::env::var("MQTT_USERNAME") {
-            println!("cargo:rustc-env=MQTT_USERNAME={}", username);
-        }
-        if let Ok(password) = std::env::var("MQTT_PASSWORD") {
-            println!("cargo:rustc-env=MQTT_PASSWORD={}", password);
+
+        // The broker URL scheme has to match the transport compiled in
+        let no_tls = std::env::var("CARGO_FEATURE_NO_TLS").is_ok();
+        let scheme = if no_tls { "mqtt://" } else { "mqtts://" };
+        let broker = std::env::var("MQTT_BROKER").unwrap_or_default();
+        if !broker.is_empty() && !broker.starts_with(scheme) {
+            eprintln!(
+                "error: MQTT_BROKER must start with {} {} (got {})",
+                scheme,
+                if no_tls { "with the no-tls feature" } else { "unless built with the no-tls feature" },
+                broker
+            );
+            std::process::exit(1);
         }
-        if let Ok(station) = std::env::var("STATION_ID") {
-            println!("cargo:rustc-env=STATION_ID={}", station);
+
+        // Re-export environment variables to make them available to env!() macro
+        // This is necessary because build.rs runs in a separate process
+        for var in OPTIONAL_VARS {
+            println!("cargo:rerun-if-env-changed={}", var);
+            if let Ok(value) = std::env::var(var) {
+                println!("cargo:rustc-env={}={}", var, value);
+            }
         }
 
+        // Build identification, logged at boot and published on the status topic
+        println!("cargo:rerun-if-changed=.git/HEAD");
+        println!("cargo:rerun-if-changed=.git/index");
+        println!("cargo:rustc-env=GIT_HASH={}", git_hash());
+        let build_time = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|since| since.as_secs())
+            .unwrap_or(0);
+        println!("cargo:rustc-env=BUILD_TIME={}", build_time);
+
         embuild::espidf::sysenv::output();
     }
 }
@@ -2,51 +2,128 @@ use anyhow::Result;
 use axum::{
     extract::{
         ws::{Message, WebSocket},
-        State, WebSocketUpgrade,
+        Path as UrlPath, Query, Request, State, WebSocketUpgrade,
     },
-    response::{Html, IntoResponse},
-    routing::get,
-    Router,
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{Html, IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
 };
 use axum_server::tls_rustls::RustlsConfig;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use notify::{RecursiveMode, Watcher};
 use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS, Transport};
 use rustls::crypto::ring::default_provider;
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet},
     fs,
     net::SocketAddr,
-    path::Path,
-    sync::Arc,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncWriteExt, BufWriter},
+    sync::{mpsc, oneshot, watch, RwLock},
 };
-use tokio::sync::RwLock;
-use tower_http::cors::CorsLayer;
+use tower_http::{cors::CorsLayer, services::ServeDir};
+
+// Device events share their wire format with the firmware
+use esp32_wifi_sniffer::event::{GeoLocation, MqttDeviceEvent};
+
+// Randomized MAC grouping from library
+use esp32_wifi_sniffer::grouping::{DeviceGrouper, GrouperConfig, Sighting};
 
 // Import triangulation module from library
 use esp32_wifi_sniffer::triangulate::{
-    CalibrationParams, Position, RssiReading as TriangulateRssiReading, StationLike, PositionTracker,
+    CalibrationParams, DistanceModel, FewStationsMode, LogDistanceModel, LookupTableModel, Position, RssiAggregation, RssiReading as TriangulateRssiReading, SmoothingMode, SolveMode,
+    StationLike, PositionTracker, TriangulatorConfig, Velocity, DEFAULT_FLOOR,
 };
 
 /// Configuration file structure
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 struct Config {
     server: ServerConfig,
     mqtt: MqttConfig,
     room: RoomConfig,
+    /// Additional floors/rooms, stations without a `floor` belong to `room`
+    #[serde(default)]
+    floors: Vec<FloorConfig>,
     stations: Vec<StationConfig>,
+    #[serde(default)]
+    display: DisplayConfig,
+    #[serde(default)]
+    triangulation: TriangulationConfig,
+    #[serde(default)]
+    history: HistoryConfig,
+    #[serde(default)]
+    grouping: GroupingConfig,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 struct ServerConfig {
     host: String,
     port: u16,
+    /// Serve HTTPS (default), `false` serves plain HTTP for local runs behind a reverse proxy
+    #[serde(default = "default_tls")]
+    tls: bool,
     /// Path to TLS certificate file
+    #[serde(default = "default_tls_cert")]
     tls_cert: String,
     /// Path to TLS private key file
+    #[serde(default = "default_tls_key", serialize_with = "redact")]
     tls_key: String,
+    /// HTTP basic auth credentials (optional, server is open if not set)
+    auth: Option<AuthConfig>,
+    /// JSON file where device labels are persisted
+    #[serde(default = "default_labels_file")]
+    labels_file: String,
+    /// JSON file where calibration learned via /api/calibrate is persisted
+    #[serde(default = "default_calibration_file")]
+    calibration_file: String,
 }
 
-#[derive(Debug, Deserialize)]
+/// Hide secrets when the config is served over /api/config
+fn redact<S: serde::Serializer>(_value: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str("<redacted>")
+}
+
+fn default_tls() -> bool {
+    true
+}
+
+fn default_tls_cert() -> String {
+    "certs/server.crt".to_string()
+}
+
+fn default_tls_key() -> String {
+    "certs/server.key".to_string()
+}
+
+fn default_labels_file() -> String {
+    "web/labels.json".to_string()
+}
+
+fn default_calibration_file() -> String {
+    "web/calibration.json".to_string()
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+struct AuthConfig {
+    username: String,
+    /// Hex-encoded SHA-256 of the password (`printf '%s' 'secret' | sha256sum`)
+    #[serde(serialize_with = "redact")]
+    password_sha256: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
 struct MqttConfig {
     host: String,
     port: u16,
@@ -54,6 +131,7 @@ struct MqttConfig {
     /// Authentication username
     username: String,
     /// Authentication password
+    #[serde(serialize_with = "redact")]
     password: String,
     /// Enable TLS for MQTT connection
     use_tls: bool,
@@ -61,21 +139,374 @@ struct MqttConfig {
     ca_cert: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 struct RoomConfig {
     width: f32,
     height: f32,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+/// Append-only JSONL log of computed positions (read at startup only)
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct HistoryConfig {
+    #[serde(default)]
+    enabled: bool,
+    #[serde(default = "default_history_path")]
+    path: String,
+    /// Seconds between flushes of the write buffer
+    #[serde(default = "default_history_flush_interval")]
+    flush_interval_secs: u64,
+    /// Rotate the file once it grows past this size
+    #[serde(default = "default_history_max_file_mb")]
+    max_file_mb: u64,
+}
+
+fn default_history_path() -> String {
+    "web/history/positions.jsonl".to_string()
+}
+
+fn default_history_flush_interval() -> u64 {
+    5
+}
+
+fn default_history_max_file_mb() -> u64 {
+    50
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: default_history_path(),
+            flush_interval_secs: default_history_flush_interval(),
+            max_file_mb: default_history_max_file_mb(),
+        }
+    }
+}
+
+/// Heuristic grouping of randomized MACs into physical devices, unset thresholds use the
+/// `GrouperConfig` defaults
+#[derive(Debug, Deserialize, Serialize)]
+struct GroupingConfig {
+    #[serde(default = "default_grouping_enabled")]
+    enabled: bool,
+    /// Longest gap between one address going quiet and the next appearing
+    window_secs: Option<u64>,
+    /// Largest mean RSSI difference in dB over the stations that heard both addresses
+    max_rssi_difference: Option<f32>,
+    /// Stations that must have heard both addresses
+    min_common_stations: Option<usize>,
+}
+
+fn default_grouping_enabled() -> bool {
+    true
+}
+
+impl Default for GroupingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_grouping_enabled(),
+            window_secs: None,
+            max_rssi_difference: None,
+            min_common_stations: None,
+        }
+    }
+}
+
+impl GroupingConfig {
+    fn to_grouper_config(&self) -> GrouperConfig {
+        let defaults = GrouperConfig::default();
+        GrouperConfig {
+            window_ms: self
+                .window_secs
+                .map_or(defaults.window_ms, |secs| secs.saturating_mul(1000)),
+            max_rssi_difference: self
+                .max_rssi_difference
+                .unwrap_or(defaults.max_rssi_difference),
+            min_common_stations: self
+                .min_common_stations
+                .unwrap_or(defaults.min_common_stations),
+        }
+    }
+}
+
+/// A floor or room with its own geometry, stations refer to it by `id`
+#[derive(Debug, Deserialize, Serialize)]
+struct FloorConfig {
+    id: String,
+    width: f32,
+    height: f32,
+}
+
+impl Config {
+    /// Width and height of a floor, the default floor uses `[room]`
+    fn floor_dimensions(&self, floor: &str) -> Option<(f32, f32)> {
+        if floor == DEFAULT_FLOOR {
+            return Some((self.room.width, self.room.height));
+        }
+        self.floors
+            .iter()
+            .find(|f| f.id == floor)
+            .map(|f| (f.width, f.height))
+    }
+
+    /// Seconds a station's reading stays in a device's state, defaults to `device_timeout`
+    fn reading_window_secs(&self) -> u64 {
+        self.triangulation
+            .reading_window_secs
+            .unwrap_or(self.display.device_timeout)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct DisplayConfig {
+    /// Seconds without updates before a device counts as inactive
+    #[serde(default = "default_device_timeout")]
+    device_timeout: u64,
+    /// Seconds before the UI starts fading a device marker
+    #[serde(default = "default_fade_after")]
+    fade_after: u64,
+    /// Seconds without events before a station is shown as offline
+    #[serde(default = "default_station_timeout")]
+    station_timeout: u64,
+    /// Milliseconds between WebSocket broadcasts (floored at MIN_UPDATE_INTERVAL_MS)
+    #[serde(default = "default_update_interval_ms")]
+    update_interval_ms: u64,
+    /// Glide device markers to each new position instead of jumping, computed per broadcast
+    #[serde(default)]
+    interpolate: bool,
+    /// Milliseconds a glide takes, the velocity estimate carries the device this far ahead
+    #[serde(default = "default_interpolation_ms")]
+    interpolation_ms: u64,
+    /// Canvas size in pixels the room (or floor) is drawn on, set both to broadcast each
+    /// position in pixels as well
+    canvas_width: Option<u32>,
+    canvas_height: Option<u32>,
+    /// Grid cell size in meters, located devices sharing a cell are sent as one cluster
+    #[serde(default = "default_cluster_radius")]
+    cluster_radius: f32,
+    /// Cluster automatically once this many devices have a position (0 only on request)
+    #[serde(default = "default_cluster_threshold")]
+    cluster_threshold: usize,
+}
+
+impl DisplayConfig {
+    /// Canvas size if pixel positions are enabled
+    fn canvas(&self) -> Option<(f32, f32)> {
+        Some((self.canvas_width? as f32, self.canvas_height? as f32))
+    }
+}
+
+/// Lower bound for the WebSocket broadcast interval to avoid pegging the CPU
+const MIN_UPDATE_INTERVAL_MS: u64 = 20;
+
+fn default_device_timeout() -> u64 {
+    30
+}
+
+fn default_fade_after() -> u64 {
+    5
+}
+
+fn default_station_timeout() -> u64 {
+    30
+}
+
+fn default_update_interval_ms() -> u64 {
+    100
+}
+
+fn default_interpolation_ms() -> u64 {
+    500
+}
+
+fn default_cluster_radius() -> f32 {
+    1.0
+}
+
+fn default_cluster_threshold() -> usize {
+    100
+}
+
+impl Default for DisplayConfig {
+    fn default() -> Self {
+        Self {
+            device_timeout: default_device_timeout(),
+            fade_after: default_fade_after(),
+            station_timeout: default_station_timeout(),
+            update_interval_ms: default_update_interval_ms(),
+            interpolate: false,
+            interpolation_ms: default_interpolation_ms(),
+            canvas_width: None,
+            canvas_height: None,
+            cluster_radius: default_cluster_radius(),
+            cluster_threshold: default_cluster_threshold(),
+        }
+    }
+}
+
+/// Tuning for the position tracker, unset fields use the `TriangulatorConfig` defaults
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+struct TriangulationConfig {
+    /// Minimum contributing stations before a device gets a position
+    min_position_stations: Option<usize>,
+    /// Clamp positions into the room/floor rectangle (disable to spot calibration problems)
+    #[serde(default = "default_clamp_to_room")]
+    clamp_to_room: bool,
+    /// Solver for three or more stations (`gradient_descent`, `weighted_least_squares`
+    /// or `gauss_newton`)
+    solve_mode: Option<SolveMode>,
+    /// Position smoothing (`exponential` or `kalman`)
+    smoothing_mode: Option<SmoothingMode>,
+    /// Exponential smoothing weight of the previous position (0.0 = raw solves, must be < 1.0)
+    smoothing_factor: Option<f32>,
+    /// Kalman acceleration noise in m/s², higher follows fast movement more closely
+    kalman_process_noise: Option<f32>,
+    /// Kalman measurement noise in meters, higher trusts individual solves less
+    kalman_measurement_noise: Option<f32>,
+    /// Fastest plausible device speed in m/s, faster velocity estimates are clamped and flagged
+    max_speed: Option<f32>,
+    /// Fallback below `min_position_stations` (`none`, `centroid` or `circle_intersection`)
+    few_stations_mode: Option<FewStationsMode>,
+    /// Ignore readings older than this relative to the device's newest reading
+    max_reading_age_secs: Option<u64>,
+    /// Drop a station's reading from a device once it is this old (defaults to `device_timeout`)
+    reading_window_secs: Option<u64>,
+    /// Combine recent readings per station (`latest`, `mean` or `median`)
+    rssi_aggregation: Option<RssiAggregation>,
+    /// Readings per station kept for mean/median aggregation
+    rssi_history_len: Option<usize>,
+    /// Weight stations by their recent reading count, up to this many (1 disables)
+    reading_count_cap: Option<usize>,
+    /// Drop the most inconsistent reading when more than three stations see a device
+    outlier_rejection: Option<bool>,
+    /// RSSI-to-distance conversion (`log_distance` or `lookup`)
+    #[serde(default)]
+    distance_model: DistanceModelKind,
+    /// Measured `[rssi, distance_m]` pairs used by the `lookup` model
+    #[serde(default)]
+    distance_table: Vec<[f32; 2]>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum DistanceModelKind {
+    /// Log-distance path loss with per-station `rssi_at_1m` and `path_loss_exponent`
+    #[default]
+    LogDistance,
+    /// Interpolate `distance_table`, ignores per-station calibration
+    Lookup,
+}
+
+fn default_clamp_to_room() -> bool {
+    true
+}
+
+impl Default for TriangulationConfig {
+    fn default() -> Self {
+        Self {
+            min_position_stations: None,
+            clamp_to_room: default_clamp_to_room(),
+            solve_mode: None,
+            smoothing_mode: None,
+            smoothing_factor: None,
+            kalman_process_noise: None,
+            kalman_measurement_noise: None,
+            max_speed: None,
+            few_stations_mode: None,
+            max_reading_age_secs: None,
+            reading_window_secs: None,
+            rssi_aggregation: None,
+            rssi_history_len: None,
+            reading_count_cap: None,
+            outlier_rejection: None,
+            distance_model: DistanceModelKind::default(),
+            distance_table: Vec::new(),
+        }
+    }
+}
+
+impl TriangulationConfig {
+    fn build_distance_model(&self) -> Result<Arc<dyn DistanceModel>> {
+        match self.distance_model {
+            DistanceModelKind::LogDistance => Ok(Arc::new(LogDistanceModel)),
+            DistanceModelKind::Lookup => {
+                let points: Vec<(f32, f32)> =
+                    self.distance_table.iter().map(|&[rssi, d]| (rssi, d)).collect();
+                let model = LookupTableModel::new(&points)?;
+                Ok(Arc::new(model))
+            }
+        }
+    }
+
+    fn to_triangulator_config(&self) -> TriangulatorConfig {
+        let defaults = TriangulatorConfig::default();
+        TriangulatorConfig {
+            min_stations_for_position: self
+                .min_position_stations
+                .unwrap_or(defaults.min_stations_for_position),
+            solve_mode: self.solve_mode.unwrap_or(defaults.solve_mode),
+            smoothing_mode: self.smoothing_mode.unwrap_or(defaults.smoothing_mode),
+            smoothing_factor: self.smoothing_factor.unwrap_or(defaults.smoothing_factor),
+            kalman_process_noise: self
+                .kalman_process_noise
+                .unwrap_or(defaults.kalman_process_noise),
+            kalman_measurement_noise: self
+                .kalman_measurement_noise
+                .unwrap_or(defaults.kalman_measurement_noise),
+            max_speed: self.max_speed.unwrap_or(defaults.max_speed),
+            few_stations_mode: self.few_stations_mode.unwrap_or(defaults.few_stations_mode),
+            max_reading_age_secs: self
+                .max_reading_age_secs
+                .unwrap_or(defaults.max_reading_age_secs),
+            outlier_rejection: self.outlier_rejection.unwrap_or(defaults.outlier_rejection),
+            rssi_aggregation: self.rssi_aggregation.unwrap_or(defaults.rssi_aggregation),
+            rssi_history_len: self.rssi_history_len.unwrap_or(defaults.rssi_history_len),
+            reading_count_cap: self.reading_count_cap.unwrap_or(defaults.reading_count_cap),
+            ..defaults
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 struct StationConfig {
     id: String,
     x: f32,
     y: f32,
+    /// Mounting height in meters (optional, defaults to 0.0)
+    z: Option<f32>,
     /// Reference RSSI at 1 meter (optional, defaults to -45.0)
     rssi_at_1m: Option<f32>,
     /// Path loss exponent (optional, defaults to 3.0)
     path_loss_exponent: Option<f32>,
+    /// Added to this station's RSSI readings to correct board/antenna bias (optional, defaults to 0.0)
+    rssi_offset: Option<f32>,
+    /// Floor id from `[[floors]]` (optional, defaults to the main `[room]`)
+    floor: Option<String>,
+}
+
+impl StationConfig {
+    /// Copy of this station with learned calibration applied over the configured values
+    fn with_calibration(&self, calibration: Option<&StationCalibration>) -> StationConfig {
+        let mut station = self.clone();
+        if let Some(exponent) = calibration.and_then(|c| c.path_loss_exponent) {
+            station.path_loss_exponent = Some(exponent);
+        }
+        if let Some(offset) = calibration.and_then(|c| c.rssi_offset) {
+            station.rssi_offset = Some(offset);
+        }
+        station
+    }
+}
+
+/// Calibration learned at runtime for one station, overrides the `[[stations]]` values
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+struct StationCalibration {
+    /// Path loss exponent fitted via /api/calibrate
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    path_loss_exponent: Option<f32>,
+    /// RSSI offset set via /api/stations/:id/rssi_offset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rssi_offset: Option<f32>,
 }
 
 // Implement StationLike trait for StationConfig to use with Triangulator
@@ -89,30 +520,31 @@ impl StationLike for StationConfig {
     fn y(&self) -> f32 {
         self.y
     }
+    fn z(&self) -> f32 {
+        self.z.unwrap_or(0.0)
+    }
     fn calibration(&self) -> CalibrationParams {
         CalibrationParams {
             rssi_at_1m: self.rssi_at_1m.unwrap_or(-45.0),
             path_loss_exponent: self.path_loss_exponent.unwrap_or(3.0),
+            rssi_offset: self.rssi_offset.unwrap_or(0.0),
         }
     }
+    fn floor(&self) -> &str {
+        self.floor.as_deref().unwrap_or(DEFAULT_FLOOR)
+    }
 }
 
 
 
-/// MQTT device event from ESP32
-#[derive(Debug, Deserialize)]
-struct MqttDeviceEvent {
-    mac_hash: String,
-    rssi: i8,
-    timestamp: u64,
-    station: String,
-}
-
 /// RSSI reading from a single station
 #[derive(Debug, Clone, Serialize)]
 struct RssiReading {
     rssi: i8,
+    /// Server receive time in Unix milliseconds, firmware clocks aren't comparable across stations
     timestamp: u64,
+    /// Station clock in microseconds as reported by the firmware
+    device_timestamp: u64,
 }
 
 /// Device state with readings from all stations
@@ -120,20 +552,264 @@ struct RssiReading {
 struct DeviceState {
     mac_hash: String,
     readings: HashMap<String, RssiReading>,
+    /// Server receive time of the last reading in Unix milliseconds
     last_seen: u64,
+    /// Station clock of the last reading in microseconds, only comparable within one station
+    device_timestamp: u64,
     /// Calculated position from triangulation (None if insufficient data)
     position: Option<Position>,
+    /// Number of stations whose readings contributed to the position
+    station_count: usize,
+    /// Weighted RMS distance error of the last solve in meters (lower is a better fit)
+    residual: Option<f32>,
+    /// Estimated velocity in m/s, smoothed
+    velocity: Option<Velocity>,
+    /// Velocity was clamped to `max_speed`, the position is probably jumping around
+    implausible_speed: bool,
+    /// Position is a rough fallback from fewer than three stations
+    low_confidence: bool,
+    /// Estimated position error radius in meters
+    estimated_error: Option<f32>,
+    /// Geometric dilution of precision, high when the stations sit poorly around the device
+    gdop: Option<f32>,
+    /// Share of each station in the last solve, from signal strength and reading count
+    station_weights: HashMap<String, f32>,
+    /// `position` on the configured canvas, set in broadcasts when `display.canvas_width`
+    /// and `canvas_height` are
+    pixel_position: Option<PixelPosition>,
+    /// Human-readable name assigned by an operator
+    label: Option<String>,
+    /// Floor the position was resolved on
+    floor: Option<String>,
+    /// True if the raw solve fell outside the room and was clamped back in
+    clamped: bool,
+    /// Randomized MAC, won't persist across sessions so not a returning visitor
+    randomized: bool,
+    /// Physical device this address probably belongs to: randomized addresses that replaced
+    /// one another share it. The MAC hash of the group's first address
+    group_id: String,
+    /// Server time of the last update, used for activity checks
+    #[serde(skip)]
+    received_at: Instant,
+    /// Interpolated position shown when `position` was computed, the start of the glide
+    #[serde(skip)]
+    interpolate_from: Option<Position>,
+}
+
+impl DeviceState {
+    /// Position to broadcast `elapsed` after the last update when interpolating
+    ///
+    /// The marker glides from where it was shown towards the new position, carried forward
+    /// by the velocity estimate, over `duration`. Every client gets the same result since it
+    /// only depends on the shared state and the time. Past `duration` it holds still until
+    /// the next update rather than extrapolating further on an old estimate.
+    fn interpolated_position(&self, elapsed: Duration, duration: Duration) -> Option<Position> {
+        let target = self.position?;
+        let progress = if duration.is_zero() {
+            1.0
+        } else {
+            (elapsed.as_secs_f32() / duration.as_secs_f32()).min(1.0)
+        };
+        let ahead = elapsed.min(duration).as_secs_f32();
+        let (vx, vy) = self.velocity.map_or((0.0, 0.0), |v| (v.vx * ahead, v.vy * ahead));
+        let predicted = Position { x: target.x + vx, y: target.y + vy, ..target };
+        let from = self.interpolate_from.unwrap_or(predicted);
+        Some(Position {
+            x: from.x + (predicted.x - from.x) * progress,
+            y: from.y + (predicted.y - from.y) * progress,
+            z: from.z + (predicted.z - from.z) * progress,
+        })
+    }
+}
+
+/// Position in canvas pixels, origin top left like the room coordinates
+#[derive(Debug, Clone, Copy, Serialize)]
+struct PixelPosition {
+    x: f32,
+    y: f32,
+}
+
+impl PixelPosition {
+    /// Scale a position in meters on a `width` x `height` floor to a `canvas` sized in pixels
+    fn from_meters(position: &Position, (width, height): (f32, f32), canvas: (f32, f32)) -> Self {
+        Self {
+            x: position.x / width * canvas.0,
+            y: position.y / height * canvas.1,
+        }
+    }
+}
+
+/// Liveness of a single station, derived from its last event
+#[derive(Debug, Clone, Serialize)]
+struct StationHealth {
+    id: String,
+    /// Seconds since the last event from this station (None if never seen)
+    last_seen_age: Option<f64>,
+    online: bool,
+    /// Geographic location the station reports, passed through for multi-site dashboards
+    location: Option<GeoLocation>,
+}
+
+/// Located devices close together, broadcast as one marker in place of the devices
+#[derive(Debug, Clone, Serialize)]
+struct DeviceCluster {
+    /// Mean position of the members
+    position: Position,
+    /// Scaled like `DeviceState::pixel_position`
+    pixel_position: Option<PixelPosition>,
+    floor: String,
+    count: usize,
+    /// Members, most recently seen first
+    mac_hashes: Vec<String>,
+    /// Server receive time of the member seen last in Unix milliseconds
+    last_seen: u64,
+}
+
+/// Group located devices on a grid of `radius` sized cells per floor
+///
+/// Cells holding two or more devices become clusters and their devices are taken out of the
+/// list; lone devices and devices without a position are returned unchanged. A grid is
+/// cheap and stable between ticks, at the cost of splitting neighbours across a cell edge.
+fn cluster_devices(
+    devices: Vec<DeviceState>,
+    radius: f32,
+) -> (Vec<DeviceState>, Vec<DeviceCluster>) {
+    let mut cells: BTreeMap<(String, i64, i64), Vec<DeviceState>> = BTreeMap::new();
+    let mut unclustered = Vec::new();
+    for device in devices {
+        let Some(pos) = device.position else {
+            unclustered.push(device);
+            continue;
+        };
+        let floor = device.floor.clone().unwrap_or_else(|| DEFAULT_FLOOR.to_string());
+        let cell = ((pos.x / radius).floor() as i64, (pos.y / radius).floor() as i64);
+        cells.entry((floor, cell.0, cell.1)).or_default().push(device);
+    }
+
+    let mut clusters = Vec::new();
+    for ((floor, _, _), mut members) in cells {
+        if members.len() < 2 {
+            unclustered.append(&mut members);
+            continue;
+        }
+        members.sort_by_key(|d| std::cmp::Reverse(d.last_seen));
+        let count = members.len();
+        let n = count as f32;
+        let sum = members.iter().filter_map(|d| d.position).fold((0.0, 0.0, 0.0), |acc, p| {
+            (acc.0 + p.x, acc.1 + p.y, acc.2 + p.z)
+        });
+        clusters.push(DeviceCluster {
+            position: Position { x: sum.0 / n, y: sum.1 / n, z: sum.2 / n },
+            pixel_position: None,
+            floor,
+            count,
+            last_seen: members[0].last_seen,
+            mac_hashes: members.into_iter().map(|d| d.mac_hash).collect(),
+        });
+    }
+    (unclustered, clusters)
+}
+
+/// Message broadcast to WebSocket clients on every tick
+#[derive(Debug, Serialize)]
+struct BroadcastPayload {
+    /// Devices not folded into a cluster
+    devices: Vec<DeviceState>,
+    /// Empty unless clustering is active for the connection
+    clusters: Vec<DeviceCluster>,
+    stations: Vec<StationHealth>,
 }
 
 /// Shared application state
 #[derive(Clone)]
 struct AppState {
     devices: Arc<RwLock<HashMap<String, DeviceState>>>,
-    config: Arc<Config>,
+    /// Active configuration, swapped in place when the config file is reloaded
+    config: Arc<RwLock<Arc<Config>>>,
+    /// `[server]` settings read at startup, not reloaded with the rest of the config
+    server: Arc<ServerConfig>,
     /// Position tracker for calculating and smoothing device positions
     position_tracker: Arc<RwLock<PositionTracker>>,
+    /// Counters exposed on /metrics
+    metrics: Arc<Metrics>,
+    /// Operator-assigned device labels by MAC hash, persisted to `server.labels_file`
+    labels: Arc<RwLock<HashMap<String, String>>>,
+    /// Learned calibration by station id, persisted to `server.calibration_file`
+    calibration: Arc<RwLock<HashMap<String, StationCalibration>>>,
+    /// Server time of the last event from each station id
+    station_last_seen: Arc<RwLock<HashMap<String, Instant>>>,
+    /// Location reported by each station id that has STATION_LOCATION set
+    station_locations: Arc<RwLock<HashMap<String, GeoLocation>>>,
+    /// Bumped on every change to broadcast state, lets WebSocket clients skip idle ticks
+    state_version: Arc<AtomicU64>,
+    /// Position history writer (None if history is disabled)
+    history: Option<mpsc::Sender<HistoryCommand>>,
+    /// Groups randomized MACs into physical devices
+    device_groups: Arc<RwLock<DeviceGrouper>>,
+}
+
+impl AppState {
+    /// Snapshot of the currently active configuration
+    async fn config(&self) -> Arc<Config> {
+        self.config.read().await.clone()
+    }
+
+    /// Record that broadcast state changed
+    fn mark_changed(&self) {
+        self.state_version.fetch_add(1, Ordering::Release);
+    }
+
+    /// Health of all configured stations, followed by any unknown station ids that reported
+    async fn station_health(&self) -> Vec<StationHealth> {
+        let config = self.config().await;
+        let timeout = Duration::from_secs(config.display.station_timeout);
+        let last_seen = self.station_last_seen.read().await;
+        let locations = self.station_locations.read().await;
+
+        let health = |id: &str| {
+            let age = last_seen.get(id).map(|t| t.elapsed());
+            StationHealth {
+                id: id.to_string(),
+                last_seen_age: age.map(|a| a.as_secs_f64()),
+                online: age.is_some_and(|a| a <= timeout),
+                location: locations.get(id).copied(),
+            }
+        };
+
+        let mut stations: Vec<StationHealth> =
+            config.stations.iter().map(|s| health(&s.id)).collect();
+        let mut unknown: Vec<&String> = last_seen
+            .keys()
+            .filter(|id| !config.stations.iter().any(|s| &s.id == *id))
+            .collect();
+        unknown.sort();
+        stations.extend(unknown.into_iter().map(|id| health(id)));
+        stations
+    }
+}
+
+/// Operational counters, exported in Prometheus text format
+#[derive(Debug, Default)]
+struct Metrics {
+    /// MQTT publish packets received
+    mqtt_messages: AtomicU64,
+    /// Payloads that were not valid UTF-8 or did not match `MqttDeviceEvent`
+    parse_failures: AtomicU64,
+    /// Successful triangulation results
+    positions_computed: AtomicU64,
+    /// Device events parsed, each one a reading that may yield a position
+    readings_received: AtomicU64,
+    /// Devices seen within the display `device_timeout`, raised as devices (re)appear and
+    /// recounted by the cleanup task so it drops when stations go quiet
+    devices_active: AtomicU64,
 }
 
+/// Path of the configuration file, watched for changes at runtime
+const CONFIG_PATH: &str = "web/config.toml";
+
+/// Directory served under /static (JS, CSS, images)
+const STATIC_DIR: &str = "web/static";
+
 #[tokio::main]
 async fn main() -> Result<()> {
     // Install the ring crypto provider for rustls
@@ -145,233 +821,1139 @@ async fn main() -> Result<()> {
     env_logger::init_from_env(env_logger::Env::default().default_filter_or("info"));
 
     // Load configuration
-    let config_path = Path::new("web/config.toml");
-    let config_str = fs::read_to_string(config_path)?;
-    let config: Config = toml::from_str(&config_str)?;
-
-    log::info!("Loaded configuration:");
-    log::info!(
-        "  Room: {}x{} meters",
-        config.room.width,
-        config.room.height
-    );
-    log::info!("  Stations: {}", config.stations.len());
-    for station in &config.stations {
-        log::info!("    {} at ({}, {})", station.id, station.x, station.y);
-    }
+    let config = load_config(Path::new(CONFIG_PATH))?;
+    log_config(&config);
 
-    // Create position tracker from station configurations
-    let position_tracker = PositionTracker::new(&config.stations);
+    // Create position tracker from station configurations and learned calibration
+    let calibration: HashMap<String, StationCalibration> =
+        load_json_map(Path::new(&config.server.calibration_file), "station calibrations");
+    let position_tracker = build_tracker(&config, &calibration);
     log::info!("Position tracker initialized with {} stations", config.stations.len());
+    let labels = load_json_map(Path::new(&config.server.labels_file), "device labels");
+
+    // Start position history writer
+    let history = config.history.enabled.then(|| {
+        let (tx, rx) = mpsc::channel(HISTORY_CHANNEL_CAPACITY);
+        log::info!("Appending position history to {}", config.history.path);
+        tokio::spawn(history_writer(config.history.clone(), rx));
+        tx
+    });
+    let config = Arc::new(config);
 
     // Create shared state
     let state = AppState {
         devices: Arc::new(RwLock::new(HashMap::new())),
-        config: Arc::new(config),
+        config: Arc::new(RwLock::new(config.clone())),
+        server: Arc::new(config.server.clone()),
         position_tracker: Arc::new(RwLock::new(position_tracker)),
+        metrics: Arc::new(Metrics::default()),
+        labels: Arc::new(RwLock::new(labels)),
+        calibration: Arc::new(RwLock::new(calibration)),
+        station_last_seen: Arc::new(RwLock::new(HashMap::new())),
+        station_locations: Arc::new(RwLock::new(HashMap::new())),
+        state_version: Arc::new(AtomicU64::new(0)),
+        history,
+        device_groups: Arc::new(RwLock::new(DeviceGrouper::new())),
     };
 
     // Start MQTT subscriber
     let mqtt_state = state.clone();
-    tokio::spawn(async move {
+    let mqtt_task = tokio::spawn(async move {
         if let Err(e) = mqtt_subscriber(mqtt_state).await {
             log::error!("MQTT subscriber error: {:?}", e);
         }
     });
 
+    // Reload room, station and display settings when the config file changes
+    let watch_task = tokio::spawn(watch_config(state.clone()));
+
+    // Runs even when no MQTT messages arrive, so the active device gauge follows an outage
+    tokio::spawn(cleanup_task(state.clone()));
+
     // Build web server
     let app = Router::new()
         .route("/", get(index_handler))
         .route("/ws", get(websocket_handler))
+        .route("/api/heatmap", get(heatmap_handler))
+        .route("/metrics", get(metrics_handler))
+        .route("/api/config", get(config_handler))
+        .route("/api/snapshot", get(snapshot_handler))
+        .route("/api/devices/:mac_hash/label", post(label_handler))
+        .route("/api/calibrate", post(calibrate_handler))
+        .route("/api/stations/:station_id/rssi_offset", post(rssi_offset_handler))
+        .nest_service("/static", ServeDir::new(STATIC_DIR))
+        .layer(middleware::from_fn_with_state(state.clone(), require_auth))
         .layer(CorsLayer::permissive())
         .with_state(state.clone());
 
-    let addr: SocketAddr = format!("{}:{}", state.config.server.host, state.config.server.port)
+    if config.server.auth.is_none() {
+        log::warn!("No [server.auth] configured - dashboard is accessible without login");
+    }
+
+    let addr: SocketAddr = format!("{}:{}", config.server.host, config.server.port)
         .parse()
         .expect("Invalid server address");
 
-    // Load TLS configuration
-    let tls_config = RustlsConfig::from_pem_file(
-        &state.config.server.tls_cert,
-        &state.config.server.tls_key,
-    )
-    .await
-    .expect("Failed to load TLS certificates. Run ./genssl.sh first.");
+    // Stop accepting connections on Ctrl-C and give in-flight requests time to finish
+    let handle = axum_server::Handle::new();
+    tokio::spawn(shutdown_on_ctrl_c(handle.clone()));
 
-    log::info!("Starting HTTPS web server on https://{}", addr);
-    log::info!("  TLS cert: {}", state.config.server.tls_cert);
-    log::info!("  TLS key: {}", state.config.server.tls_key);
+    if config.server.tls {
+        // Load TLS configuration
+        let tls_config =
+            RustlsConfig::from_pem_file(&config.server.tls_cert, &config.server.tls_key)
+                .await
+                .expect("Failed to load TLS certificates. Run ./genssl.sh first.");
 
-    axum_server::bind_rustls(addr, tls_config)
-        .serve(app.into_make_service())
-        .await?;
+        log::info!("Starting HTTPS web server on https://{}", addr);
+        log::info!("  TLS cert: {}", config.server.tls_cert);
+        log::info!("  TLS key: {}", config.server.tls_key);
 
-    Ok(())
-}
+        axum_server::bind_rustls(addr, tls_config)
+            .handle(handle)
+            .serve(app.into_make_service())
+            .await?;
+    } else {
+        log::warn!("Starting plain HTTP web server on http://{} (server.tls = false)", addr);
+        if config.server.auth.is_some() {
+            log::warn!("Basic auth credentials travel unencrypted unless a TLS proxy is in front");
+        }
 
-/// Serve the main HTML page
-async fn index_handler(State(_state): State<AppState>) -> impl IntoResponse {
-    let html_path = Path::new("web/index.html");
+        axum_server::bind(addr)
+            .handle(handle)
+            .serve(app.into_make_service())
+            .await?;
+    }
 
-    match fs::read_to_string(html_path) {
-        Ok(html) => Html(html),
-        Err(e) => {
-            log::error!("Failed to read index.html: {:?}", e);
-            Html(format!(
-                "<html><body><h1>Error loading page: {:?}</h1></body></html>",
-                e
-            ))
+    // Server has drained, stop background tasks and flush pending history
+    mqtt_task.abort();
+    watch_task.abort();
+
+    if let Some(history) = &state.history {
+        let (done_tx, done_rx) = oneshot::channel();
+        if history.send(HistoryCommand::Flush(done_tx)).await.is_ok() && done_rx.await.is_ok() {
+            log::info!("Position history flushed");
         }
     }
-}
 
-/// WebSocket handler
-async fn websocket_handler(
-    ws: WebSocketUpgrade,
-    State(state): State<AppState>,
-) -> impl IntoResponse {
-    ws.on_upgrade(|socket| websocket_connection(socket, state))
+    log::info!("Web server stopped cleanly");
+    Ok(())
 }
 
-/// Handle WebSocket connection
-async fn websocket_connection(socket: WebSocket, state: AppState) {
-    let (mut sender, mut receiver) = socket.split();
+/// How long open connections (including WebSockets) get to close on shutdown
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(5);
 
-    log::info!("New WebSocket connection");
+/// Wait for Ctrl-C and trigger a graceful server shutdown
+async fn shutdown_on_ctrl_c(handle: axum_server::Handle) {
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        log::error!("Failed to listen for Ctrl-C: {:?}", e);
+        return;
+    }
 
-    // Spawn a task to broadcast device updates
-    let tx_task = tokio::spawn(async move {
-        loop {
-            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+    log::info!(
+        "Shutdown requested, waiting up to {:?} for connections to close",
+        SHUTDOWN_GRACE_PERIOD
+    );
+    handle.graceful_shutdown(Some(SHUTDOWN_GRACE_PERIOD));
+}
 
-            // Read current device state
-            let devices = state.devices.read().await;
-            let device_list: Vec<DeviceState> = devices.values().cloned().collect();
-            drop(devices);
+/// Read, parse and validate the configuration file
+fn load_config(path: &Path) -> Result<Config> {
+    let config_str = fs::read_to_string(path)?;
+    let mut config: Config = toml::from_str(&config_str)?;
 
-            // Serialize and send
-            if let Ok(json) = serde_json::to_string(&device_list) {
-                if sender.send(Message::Text(json)).await.is_err() {
-                    break;
-                }
-            }
+    if config.room.width <= 0.0 || config.room.height <= 0.0 {
+        anyhow::bail!(
+            "room dimensions must be positive, got {}x{}",
+            config.room.width,
+            config.room.height
+        );
+    }
+    if config.stations.is_empty() {
+        anyhow::bail!("at least one station must be configured");
+    }
+    let mut seen_ids = HashSet::new();
+    for station in &config.stations {
+        if !seen_ids.insert(station.id.as_str()) {
+            anyhow::bail!("duplicate station id '{}'", station.id);
         }
-    });
-
-    // Handle incoming messages (ping/pong, close)
-    while let Some(Ok(msg)) = receiver.next().await {
-        match msg {
-            Message::Close(_) => break,
-            _ => {}
+    }
+    let mut seen_floors = HashSet::new();
+    for floor in &config.floors {
+        if floor.id == DEFAULT_FLOOR || !seen_floors.insert(floor.id.as_str()) {
+            anyhow::bail!("duplicate or reserved floor id '{}'", floor.id);
+        }
+        if floor.width <= 0.0 || floor.height <= 0.0 {
+            anyhow::bail!(
+                "floor '{}' dimensions must be positive, got {}x{}",
+                floor.id,
+                floor.width,
+                floor.height
+            );
+        }
+    }
+    for station in &config.stations {
+        if config.floor_dimensions(station.floor()).is_none() {
+            anyhow::bail!(
+                "station '{}' references unknown floor '{}'",
+                station.id,
+                station.floor()
+            );
         }
     }
 
-    tx_task.abort();
-    log::info!("WebSocket connection closed");
-}
+    config.triangulation.build_distance_model()?;
+    let tracking = config.triangulation.to_triangulator_config();
+    if !(0.0..1.0).contains(&tracking.smoothing_factor) {
+        anyhow::bail!(
+            "triangulation.smoothing_factor must be in [0.0, 1.0), got {}",
+            tracking.smoothing_factor
+        );
+    }
+    if tracking.kalman_process_noise <= 0.0 || tracking.kalman_measurement_noise <= 0.0 {
+        anyhow::bail!("triangulation Kalman noise values must be positive");
+    }
 
-/// MQTT subscriber task
-async fn mqtt_subscriber(state: AppState) -> Result<()> {
-    let host = &state.config.mqtt.host;
-    let port = state.config.mqtt.port;
+    if config.display.canvas_width.is_some() != config.display.canvas_height.is_some() {
+        anyhow::bail!("display.canvas_width and display.canvas_height must be set together");
+    }
 
-    // Load credentials from environment variables or use config fallback
-    let mqtt_username = std::env::var("MQTT_USERNAME").unwrap_or_else(|_| state.config.mqtt.username.clone());
-    let mqtt_password = std::env::var("MQTT_PASSWORD").unwrap_or_else(|_| state.config.mqtt.password.clone());
+    if !(config.display.cluster_radius > 0.0 && config.display.cluster_radius.is_finite()) {
+        anyhow::bail!(
+            "display.cluster_radius must be positive, got {}",
+            config.display.cluster_radius
+        );
+    }
 
-    log::info!("Connecting to MQTT broker at {}:{}", host, port);
-    log::info!("  Using username: {}", mqtt_username);
+    if config.display.update_interval_ms < MIN_UPDATE_INTERVAL_MS {
+        log::warn!(
+            "display.update_interval_ms = {} is below the {}ms minimum, using {}ms",
+            config.display.update_interval_ms,
+            MIN_UPDATE_INTERVAL_MS,
+            MIN_UPDATE_INTERVAL_MS
+        );
+        config.display.update_interval_ms = MIN_UPDATE_INTERVAL_MS;
+    }
 
-    // Configure MQTT client
-    let mut mqtt_options = MqttOptions::new("web-gui", host.clone(), port);
-    mqtt_options.set_keep_alive(std::time::Duration::from_secs(5));
-    
-    // Set authentication credentials
-    mqtt_options.set_credentials(mqtt_username, mqtt_password);
+    Ok(config)
+}
 
-    // Configure TLS if enabled
-    if state.config.mqtt.use_tls {
-        let ca_cert = fs::read(&state.config.mqtt.ca_cert)
-            .expect("Failed to read CA certificate. Run ./genssl.sh first.");
-        
-        log::info!("  MQTT TLS enabled, CA cert: {}", state.config.mqtt.ca_cert);
-        
-        // Use TLS with CA certificate verification
+fn log_config(config: &Config) {
+    log::info!("Loaded configuration:");
+    log::info!(
+        "  Room: {}x{} meters",
+        config.room.width,
+        config.room.height
+    );
+    log::info!("  Update interval: {}ms", config.display.update_interval_ms);
+    for floor in &config.floors {
+        log::info!("  Floor {}: {}x{} meters", floor.id, floor.width, floor.height);
+    }
+    log::info!("  Stations: {}", config.stations.len());
+    for station in &config.stations {
+        log::info!(
+            "    {} at ({}, {}) on {}",
+            station.id,
+            station.x,
+            station.y,
+            station.floor()
+        );
+    }
+}
+
+/// Watch the config file and apply changes in place
+///
+/// Room, station and display settings take effect immediately and tracked devices
+/// are kept. Server, MQTT and history settings are only read at startup and need a restart.
+async fn watch_config(state: AppState) {
+    let config_path = Path::new(CONFIG_PATH);
+    let file_name = config_path.file_name().map(|name| name.to_os_string());
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<()>(1);
+
+    let watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        if let Ok(event) = res {
+            let touches_config = event
+                .paths
+                .iter()
+                .any(|path| path.file_name() == file_name.as_deref());
+            if touches_config && !event.kind.is_access() {
+                // Channel full means a reload is already pending
+                let _ = tx.try_send(());
+            }
+        }
+    });
+    let mut watcher = match watcher {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            log::error!("Failed to create config watcher: {:?}", e);
+            return;
+        }
+    };
+
+    // Watch the directory rather than the file, editors often replace the file on save
+    let watch_dir = config_path.parent().unwrap_or(Path::new("."));
+    if let Err(e) = watcher.watch(watch_dir, RecursiveMode::NonRecursive) {
+        log::error!("Failed to watch {}: {:?}", watch_dir.display(), e);
+        return;
+    }
+    log::info!("Watching {} for changes", CONFIG_PATH);
+
+    while rx.recv().await.is_some() {
+        // A single save emits several events, let them settle before reading
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        while rx.try_recv().is_ok() {}
+
+        match load_config(config_path) {
+            Ok(config) => apply_config(&state, config).await,
+            Err(e) => log::error!("Ignoring invalid change to {}: {:#}", CONFIG_PATH, e),
+        }
+    }
+}
+
+/// Build a position tracker for the configured stations with learned calibration applied
+fn build_tracker(
+    config: &Config,
+    calibration: &HashMap<String, StationCalibration>,
+) -> PositionTracker {
+    let stations: Vec<StationConfig> = config
+        .stations
+        .iter()
+        .map(|s| s.with_calibration(calibration.get(&s.id)))
+        .collect();
+    let distance_model = config.triangulation.build_distance_model().unwrap_or_else(|e| {
+        log::error!("Invalid distance model, using log-distance: {:#}", e);
+        Arc::new(LogDistanceModel)
+    });
+    PositionTracker::with_config(&stations, config.triangulation.to_triangulator_config())
+        .with_distance_model(distance_model)
+}
+
+/// Swap in a new configuration and update the position tracker, keeping tracked devices
+///
+/// When only `[[stations]]` changed, stations are added and removed in place so devices
+/// keep their smoothing state. Other triangulation changes rebuild the tracker.
+async fn apply_config(state: &AppState, mut config: Config) {
+    // Keep the startup `[server]` section so /api/config shows what is in effect
+    if config.server != *state.server {
+        log::warn!("[server] changes take effect after a restart");
+        config.server = (*state.server).clone();
+    }
+    log_config(&config);
+    let previous = state.config().await;
+    let calibration = state.calibration.read().await;
+    let mut tracker = state.position_tracker.write().await;
+    if previous.triangulation == config.triangulation {
+        for station in &previous.stations {
+            if !config.stations.iter().any(|s| s.id == station.id) {
+                log::info!("Removing station {}", station.id);
+                tracker.remove_station(&station.id);
+            }
+        }
+        for station in &config.stations {
+            if !previous.stations.contains(station) {
+                log::info!("Adding or updating station {}", station.id);
+                tracker.add_station(&station.with_calibration(calibration.get(&station.id)));
+            }
+        }
+    } else {
+        *tracker = build_tracker(&config, &calibration);
+    }
+    drop(tracker);
+    drop(calibration);
+    *state.config.write().await = Arc::new(config);
+    state.mark_changed();
+
+    let device_count = state.devices.read().await.len();
+    log::info!("Configuration reloaded, {} tracked devices kept", device_count);
+}
+
+/// Reject requests without valid basic auth credentials (no-op if auth is not configured)
+///
+/// Runs before every route, so unauthenticated WebSocket upgrades are refused
+/// before `on_upgrade` is ever reached.
+async fn require_auth(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let Some(auth) = &state.server.auth else {
+        return next.run(request).await;
+    };
+
+    let authorized = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Basic "))
+        .and_then(|encoded| BASE64.decode(encoded).ok())
+        .and_then(|decoded| String::from_utf8(decoded).ok())
+        .is_some_and(|credentials| check_credentials(auth, &credentials));
+
+    if authorized {
+        next.run(request).await
+    } else {
+        (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Basic realm=\"WiFi Position Tracker\"")],
+        )
+            .into_response()
+    }
+}
+
+/// Check a decoded `username:password` pair against the configured hash
+fn check_credentials(auth: &AuthConfig, credentials: &str) -> bool {
+    let Some((username, password)) = credentials.split_once(':') else {
+        return false;
+    };
+
+    let mut password_hex = String::with_capacity(64);
+    for byte in Sha256::digest(password.as_bytes()) {
+        password_hex.push_str(&format!("{:02x}", byte));
+    }
+
+    // Compare in constant time so the hash can't be probed byte by byte
+    let expected = auth.password_sha256.trim().to_ascii_lowercase();
+    let hash_matches = expected.len() == password_hex.len()
+        && expected
+            .bytes()
+            .zip(password_hex.bytes())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0;
+
+    hash_matches && username == auth.username
+}
+
+/// Serve the main HTML page
+async fn index_handler(State(_state): State<AppState>) -> impl IntoResponse {
+    let html_path = Path::new("web/index.html");
+
+    match fs::read_to_string(html_path) {
+        Ok(html) => Html(html),
+        Err(e) => {
+            log::error!("Failed to read index.html: {:?}", e);
+            Html(format!(
+                "<html><body><h1>Error loading page: {:?}</h1></body></html>",
+                e
+            ))
+        }
+    }
+}
+
+/// Query parameters for the heatmap endpoint
+#[derive(Debug, Deserialize)]
+struct HeatmapQuery {
+    /// Cell size in meters (defaults to 1.0, one cell per grid line in the UI)
+    resolution: Option<f32>,
+    /// Floor to bin (defaults to the main room)
+    floor: Option<String>,
+}
+
+/// Occupancy heatmap over a floor, counts indexed as `cells[row][col]`
+#[derive(Debug, Serialize)]
+struct Heatmap {
+    floor: String,
+    resolution: f32,
+    cols: usize,
+    rows: usize,
+    cells: Vec<Vec<u32>>,
+}
+
+/// Smallest allowed cell size, keeps the grid from exploding on tiny values
+const MIN_HEATMAP_RESOLUTION: f32 = 0.1;
+
+/// Bin current device positions into an occupancy grid
+async fn heatmap_handler(
+    Query(query): Query<HeatmapQuery>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let resolution = query
+        .resolution
+        .filter(|r| r.is_finite())
+        .unwrap_or(1.0)
+        .max(MIN_HEATMAP_RESOLUTION);
+
+    let floor = query.floor.unwrap_or_else(|| DEFAULT_FLOOR.to_string());
+    let config = state.config().await;
+    let Some((width, height)) = config.floor_dimensions(&floor) else {
+        return (StatusCode::NOT_FOUND, format!("Unknown floor '{}'", floor)).into_response();
+    };
+
+    let devices = state.devices.read().await;
+    let positions: Vec<Position> = devices
+        .values()
+        .filter(|d| d.floor.as_deref().unwrap_or(DEFAULT_FLOOR) == floor)
+        .filter_map(|d| d.position)
+        .collect();
+    drop(devices);
+
+    Json(build_heatmap(floor, width, height, &positions, resolution)).into_response()
+}
+
+/// Count positions per cell; positions outside the floor land in the nearest edge cell
+fn build_heatmap(
+    floor: String,
+    width: f32,
+    height: f32,
+    positions: &[Position],
+    resolution: f32,
+) -> Heatmap {
+    let cols = ((width / resolution).ceil() as usize).max(1);
+    let rows = ((height / resolution).ceil() as usize).max(1);
+    let mut cells = vec![vec![0u32; cols]; rows];
+
+    for pos in positions {
+        let col = ((pos.x / resolution).max(0.0) as usize).min(cols - 1);
+        let row = ((pos.y / resolution).max(0.0) as usize).min(rows - 1);
+        cells[row][col] += 1;
+    }
+
+    Heatmap {
+        floor,
+        resolution,
+        cols,
+        rows,
+        cells,
+    }
+}
+
+/// Export counters and gauges in Prometheus text format
+async fn metrics_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let tracked = state.devices.read().await.len();
+    let groups = state.device_groups.read().await.group_count();
+
+    let metrics = &state.metrics;
+    let entries: [(&str, &str, &str, u64); 7] = [
+        ("sniffer_devices_tracked", "gauge", "Devices currently held in memory", tracked as u64),
+        ("sniffer_device_groups", "gauge", "Physical devices behind the tracked MACs, randomized MACs grouped", groups as u64),
+        ("sniffer_devices_active", "gauge", "Devices seen within the display device_timeout", metrics.devices_active.load(Ordering::Relaxed)),
+        ("sniffer_mqtt_messages_total", "counter", "MQTT messages received", metrics.mqtt_messages.load(Ordering::Relaxed)),
+        ("sniffer_mqtt_parse_failures_total", "counter", "MQTT payloads that failed to parse", metrics.parse_failures.load(Ordering::Relaxed)),
+        ("sniffer_readings_received_total", "counter", "Device readings received from the stations", metrics.readings_received.load(Ordering::Relaxed)),
+        ("sniffer_positions_computed_total", "counter", "Device positions computed by triangulation", metrics.positions_computed.load(Ordering::Relaxed)),
+    ];
+
+    let mut body = String::new();
+    for (name, kind, help, value) in entries {
+        body.push_str(&format!(
+            "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n"
+        ));
+    }
+
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body)
+}
+
+/// Loaded configuration plus the calibration actually applied per station
+#[derive(Debug, Serialize)]
+struct EffectiveConfig<'a> {
+    #[serde(flatten)]
+    config: &'a Config,
+    /// Station calibration after defaults are filled in, keyed by station id
+    calibration: BTreeMap<&'a str, CalibrationParams>,
+}
+
+/// Return the active configuration with secrets redacted
+async fn config_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let config = state.config().await;
+    let learned = state.calibration.read().await;
+    let calibration = config
+        .stations
+        .iter()
+        .map(|s| (s.id.as_str(), s.with_calibration(learned.get(&s.id)).calibration()))
+        .collect();
+
+    Json(serde_json::json!(EffectiveConfig {
+        config: &config,
+        calibration,
+    }))
+}
+
+/// State of the room at one moment, downloaded from /api/snapshot
+#[derive(Debug, Serialize)]
+struct Snapshot<'a> {
+    /// Server time the snapshot was taken in Unix milliseconds
+    timestamp: u64,
+    room: &'a RoomConfig,
+    floors: &'a [FloorConfig],
+    stations: &'a [StationConfig],
+    station_health: Vec<StationHealth>,
+    /// Tracked devices sorted by MAC hash, as in the WebSocket payload
+    devices: Vec<DeviceState>,
+}
+
+/// Return the current devices, positions and station health as a JSON file download
+async fn snapshot_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let config = state.config().await;
+    let mut devices: Vec<DeviceState> = state.devices.read().await.values().cloned().collect();
+    devices.sort_by(|a, b| a.mac_hash.cmp(&b.mac_hash));
+    let timestamp = unix_millis();
+
+    let snapshot = Snapshot {
+        timestamp,
+        room: &config.room,
+        floors: &config.floors,
+        stations: &config.stations,
+        station_health: state.station_health().await,
+        devices,
+    };
+    let body = match serde_json::to_string_pretty(&snapshot) {
+        Ok(body) => body,
+        Err(e) => {
+            log::error!("Failed to serialize snapshot: {:?}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let disposition = format!("attachment; filename=\"snapshot-{}.json\"", timestamp / 1000);
+    (
+        [
+            (header::CONTENT_TYPE, "application/json".to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+/// Request body for setting a device label
+#[derive(Debug, Deserialize)]
+struct LabelRequest {
+    /// New label, an empty string removes it
+    label: String,
+}
+
+/// Longest label accepted, keeps the device list readable
+const MAX_LABEL_LEN: usize = 64;
+
+/// Set or clear the label for a device and persist all labels
+async fn label_handler(
+    UrlPath(mac_hash): UrlPath<String>,
+    State(state): State<AppState>,
+    Json(request): Json<LabelRequest>,
+) -> impl IntoResponse {
+    let label = request.label.trim();
+    if label.chars().count() > MAX_LABEL_LEN {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("Label must be at most {} characters", MAX_LABEL_LEN),
+        )
+            .into_response();
+    }
+    let label = (!label.is_empty()).then(|| label.to_string());
+
+    let mut labels = state.labels.write().await;
+    match &label {
+        Some(label) => labels.insert(mac_hash.clone(), label.clone()),
+        None => labels.remove(&mac_hash),
+    };
+
+    let labels_file = &state.server.labels_file;
+    if let Err(e) = save_json(Path::new(labels_file), &*labels) {
+        log::error!("Failed to save labels to {}: {:?}", labels_file, e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to save labels").into_response();
+    }
+    drop(labels);
+
+    if let Some(device) = state.devices.write().await.get_mut(&mac_hash) {
+        device.label = label;
+        state.mark_changed();
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Known position of a reference device used to calibrate the stations that see it
+#[derive(Debug, Deserialize)]
+struct CalibrateRequest {
+    mac_hash: String,
+    x: f32,
+    y: f32,
+    #[serde(default)]
+    z: f32,
+    /// Floor the device is on (defaults to the main `[room]`)
+    floor: Option<String>,
+}
+
+/// Fitted path loss exponents, and why other stations were left alone
+#[derive(Debug, Default, Serialize)]
+struct CalibrateResponse {
+    calibrated: BTreeMap<String, f32>,
+    skipped: BTreeMap<String, String>,
+}
+
+/// Fit each station's path loss exponent from a device held at a known position
+///
+/// Uses the device's latest reading from every station on the given floor. Learned
+/// values are persisted and the position tracker is rebuilt to use them.
+async fn calibrate_handler(
+    State(state): State<AppState>,
+    Json(request): Json<CalibrateRequest>,
+) -> impl IntoResponse {
+    let Some(readings) = state
+        .devices
+        .read()
+        .await
+        .get(&request.mac_hash)
+        .map(|device| device.readings.clone())
+    else {
+        return (StatusCode::NOT_FOUND, "Unknown device").into_response();
+    };
+
+    let config = state.config().await;
+    let floor = request.floor.as_deref().unwrap_or(DEFAULT_FLOOR);
+    let known = Position::new_3d(request.x, request.y, request.z);
+    let max_age_ms = config
+        .triangulation
+        .to_triangulator_config()
+        .max_reading_age_secs
+        .saturating_mul(1000);
+    let now = unix_millis();
+
+    let mut response = CalibrateResponse::default();
+    let mut calibration = state.calibration.write().await;
+
+    for station in config.stations.iter().filter(|s| s.floor() == floor) {
+        let Some(reading) = readings.get(&station.id) else {
+            continue;
+        };
+        if now.saturating_sub(reading.timestamp) > max_age_ms {
+            response.skipped.insert(station.id.clone(), "Reading is stale".to_string());
+            continue;
+        }
+
+        let distance = known.distance_to(&Position::new_3d(station.x, station.y, station.z()));
+        // Fit with the learned calibration, so a live rssi_offset isn't baked into the exponent
+        let params = station.with_calibration(calibration.get(&station.id)).calibration();
+        match params.fit_path_loss_exponent(reading.rssi as f32, distance) {
+            Some(exponent) => {
+                let learned = calibration.entry(station.id.clone()).or_default();
+                learned.path_loss_exponent = Some(exponent);
+                response.calibrated.insert(station.id.clone(), exponent);
+            }
+            None => {
+                let reason = format!(
+                    "No plausible exponent for {} dBm at {:.1}m",
+                    reading.rssi, distance
+                );
+                response.skipped.insert(station.id.clone(), reason);
+            }
+        }
+    }
+
+    if response.calibrated.is_empty() {
+        return (StatusCode::UNPROCESSABLE_ENTITY, Json(response)).into_response();
+    }
+
+    if let Err(e) = save_json(Path::new(&state.server.calibration_file), &*calibration) {
+        log::error!("Failed to save calibration to {}: {:?}", state.server.calibration_file, e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to save calibration").into_response();
+    }
+    *state.position_tracker.write().await = build_tracker(&config, &calibration);
+    drop(calibration);
+
+    for (station, exponent) in &response.calibrated {
+        log::info!("Calibrated {}: path_loss_exponent = {:.2}", station, exponent);
+    }
+
+    Json(response).into_response()
+}
+
+/// Request body for setting a station's RSSI offset
+#[derive(Debug, Deserialize)]
+struct RssiOffsetRequest {
+    rssi_offset: f32,
+}
+
+/// Largest accepted RSSI correction in dB, anything bigger is a broken station
+const MAX_RSSI_OFFSET: f32 = 30.0;
+
+/// Set a station's RSSI offset live, persisted alongside learned calibration
+async fn rssi_offset_handler(
+    UrlPath(station_id): UrlPath<String>,
+    State(state): State<AppState>,
+    Json(request): Json<RssiOffsetRequest>,
+) -> impl IntoResponse {
+    if !(-MAX_RSSI_OFFSET..=MAX_RSSI_OFFSET).contains(&request.rssi_offset) {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("rssi_offset must be within ±{} dB", MAX_RSSI_OFFSET),
+        )
+            .into_response();
+    }
+
+    let config = state.config().await;
+    let Some(station) = config.stations.iter().find(|s| s.id == station_id) else {
+        return (StatusCode::NOT_FOUND, "Unknown station").into_response();
+    };
+
+    let mut calibration = state.calibration.write().await;
+    calibration.entry(station_id.clone()).or_default().rssi_offset = Some(request.rssi_offset);
+
+    if let Err(e) = save_json(Path::new(&state.server.calibration_file), &*calibration) {
+        log::error!("Failed to save calibration to {}: {:?}", state.server.calibration_file, e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to save calibration").into_response();
+    }
+    // Only this station changes, devices keep their smoothing state while the offset is tuned
+    state
+        .position_tracker
+        .write()
+        .await
+        .add_station(&station.with_calibration(calibration.get(&station_id)));
+    drop(calibration);
+
+    log::info!("Set {} rssi_offset = {:.1} dB", station_id, request.rssi_offset);
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Load a persisted JSON map, starting empty if the file is missing or invalid
+fn load_json_map<T: DeserializeOwned>(path: &Path, what: &str) -> HashMap<String, T> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(_) => return HashMap::new(),
+    };
+
+    match serde_json::from_str::<HashMap<String, T>>(&contents) {
+        Ok(map) => {
+            log::info!("Loaded {} {} from {}", map.len(), what, path.display());
+            map
+        }
+        Err(e) => {
+            log::warn!("Ignoring invalid {} file {}: {:?}", what, path.display(), e);
+            HashMap::new()
+        }
+    }
+}
+
+/// Write JSON to a temporary file and rename it over the old one
+fn save_json<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    let tmp_path = path.with_extension("json.tmp");
+    fs::write(&tmp_path, serde_json::to_string_pretty(value)?)?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// WebSocket handler
+async fn websocket_handler(
+    ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    ws.on_upgrade(|socket| websocket_connection(socket, state))
+}
+
+/// Maximum time between WebSocket messages when nothing changes
+const WS_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Handle WebSocket connection
+async fn websocket_connection(socket: WebSocket, state: AppState) {
+    let (mut sender, mut receiver) = socket.split();
+
+    log::info!("New WebSocket connection");
+    let command_state = state.clone();
+
+    // Per-connection device filter, set by the client and applied before serializing
+    let (filter_tx, mut filter_rx) = watch::channel(ClientFilter::default());
+
+    // Spawn a task to broadcast device updates
+    let tx_task = tokio::spawn(async move {
+        let mut sent_version = None;
+        let mut last_sent = Instant::now();
+
+        loop {
+            // Re-read each tick so a config reload applies to open connections
+            let config = state.config().await;
+            let display = &config.display;
+            let interpolation = Duration::from_millis(display.interpolation_ms);
+            tokio::time::sleep(Duration::from_millis(display.update_interval_ms)).await;
+
+            // Markers still gliding need every tick even without new events
+            let gliding = display.interpolate
+                && state
+                    .devices
+                    .read()
+                    .await
+                    .values()
+                    .any(|d| d.position.is_some() && d.received_at.elapsed() < interpolation);
+
+            // Skip idle ticks, but resend periodically as a heartbeat (also refreshes station ages)
+            let version = state.state_version.load(Ordering::Acquire);
+            let filter_changed = filter_rx.has_changed().unwrap_or(false);
+            if !filter_changed
+                && !gliding
+                && sent_version == Some(version)
+                && last_sent.elapsed() < WS_HEARTBEAT_INTERVAL
+            {
+                continue;
+            }
+            let filter = filter_rx.borrow_and_update().clone();
+
+            // Read current device state
+            let devices = state.devices.read().await;
+            let mut device_list: Vec<DeviceState> = devices
+                .values()
+                .filter(|d| filter.matches(d))
+                .cloned()
+                .collect();
+            drop(devices);
+            if display.interpolate {
+                for device in &mut device_list {
+                    device.position =
+                        device.interpolated_position(device.received_at.elapsed(), interpolation);
+                }
+            }
+            let located = device_list.iter().filter(|d| d.position.is_some()).count();
+            let clustered = filter.cluster.unwrap_or(
+                display.cluster_threshold > 0 && located >= display.cluster_threshold,
+            );
+            let (mut device_list, mut clusters) = if clustered {
+                cluster_devices(device_list, display.cluster_radius)
+            } else {
+                (device_list, Vec::new())
+            };
+            if let Some(canvas) = display.canvas() {
+                let to_pixels = |pos: &Position, floor: &str| {
+                    config
+                        .floor_dimensions(floor)
+                        .map(|size| PixelPosition::from_meters(pos, size, canvas))
+                };
+                for device in &mut device_list {
+                    let floor = device.floor.as_deref().unwrap_or(DEFAULT_FLOOR);
+                    device.pixel_position =
+                        device.position.as_ref().and_then(|pos| to_pixels(pos, floor));
+                }
+                for cluster in &mut clusters {
+                    cluster.pixel_position = to_pixels(&cluster.position, &cluster.floor);
+                }
+            }
+
+            let payload = BroadcastPayload {
+                devices: device_list,
+                clusters,
+                stations: state.station_health().await,
+            };
+
+            // Serialize and send
+            if let Ok(json) = serde_json::to_string(&payload) {
+                if sender.send(Message::Text(json)).await.is_err() {
+                    break;
+                }
+            }
+            sent_version = Some(version);
+            last_sent = Instant::now();
+        }
+    });
+
+    // Handle incoming messages (commands, close)
+    while let Some(Ok(msg)) = receiver.next().await {
+        match msg {
+            Message::Text(text) => handle_client_message(&command_state, &filter_tx, &text).await,
+            Message::Close(_) => break,
+            _ => {}
+        }
+    }
+
+    tx_task.abort();
+    log::info!("WebSocket connection closed");
+}
+
+/// Delay before the first reconnect attempt, doubled after each failure
+const MQTT_MIN_BACKOFF: Duration = Duration::from_secs(1);
+/// Upper bound for the reconnect delay
+const MQTT_MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Log one in every N malformed payloads
+const PARSE_FAILURE_LOG_EVERY: u64 = 100;
+/// Characters of a malformed payload included in the warning
+const PAYLOAD_SNIPPET_LEN: usize = 120;
+
+/// Message sent by a WebSocket client
+#[derive(Debug, Deserialize)]
+struct ClientMessage {
+    /// Command to run, currently only `"clear"`
+    cmd: Option<String>,
+    /// Replace this connection's device filter (`{}` removes it)
+    filter: Option<ClientFilter>,
+}
+
+/// Per-connection device filter, all set conditions must match
+#[derive(Debug, Clone, Default, Deserialize)]
+struct ClientFilter {
+    /// Only devices seen by this station
+    station: Option<String>,
+    /// Minimum RSSI, from `station` if set, otherwise the strongest reading
+    min_rssi: Option<i8>,
+    /// Only devices located on this floor
+    floor: Option<String>,
+    /// Force clustering on (`true`) or off (`false`), unset follows `display.cluster_threshold`
+    cluster: Option<bool>,
+}
+
+impl ClientFilter {
+    fn matches(&self, device: &DeviceState) -> bool {
+        let rssi = match &self.station {
+            Some(station) => match device.readings.get(station) {
+                Some(reading) => Some(reading.rssi),
+                None => return false,
+            },
+            None => device.readings.values().map(|r| r.rssi).max(),
+        };
+
+        if let Some(min_rssi) = self.min_rssi {
+            if rssi.map_or(true, |rssi| rssi < min_rssi) {
+                return false;
+            }
+        }
+
+        if let Some(floor) = &self.floor {
+            if device.floor.as_deref().unwrap_or(DEFAULT_FLOOR) != floor {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// Handle a text message from a WebSocket client
+async fn handle_client_message(
+    state: &AppState,
+    filter_tx: &watch::Sender<ClientFilter>,
+    text: &str,
+) {
+    let message = match serde_json::from_str::<ClientMessage>(text) {
+        Ok(message) => message,
+        Err(e) => {
+            log::warn!("Ignoring invalid WebSocket message: {}", e);
+            return;
+        }
+    };
+
+    match message.cmd.as_deref() {
+        Some("clear") => {
+            // Without auth anyone on the network could wipe state, so only allow it behind login
+            if state.server.auth.is_none() {
+                log::warn!("Rejected clear command: requires [server.auth] to be configured");
+            } else {
+                clear_devices(state).await;
+            }
+        }
+        Some(other) => log::warn!("Unknown WebSocket command: {}", other),
+        None => {}
+    }
+
+    if let Some(filter) = message.filter {
+        log::info!("WebSocket client set filter: {:?}", filter);
+        // Fails only if the broadcast task already stopped
+        let _ = filter_tx.send(filter);
+    }
+}
+
+/// Forget all tracked devices and their smoothed positions
+async fn clear_devices(state: &AppState) {
+    let mut devices = state.devices.write().await;
+    let count = devices.len();
+    devices.clear();
+    state.position_tracker.write().await.clear();
+    state.device_groups.write().await.clear();
+    state.metrics.devices_active.store(0, Ordering::Relaxed);
+    drop(devices);
+
+    state.mark_changed();
+    log::info!("Cleared {} tracked devices", count);
+}
+
+/// MQTT subscriber task
+async fn mqtt_subscriber(state: AppState) -> Result<()> {
+    let config = state.config().await;
+    let host = &config.mqtt.host;
+    let port = config.mqtt.port;
+
+    // Load credentials from environment variables or use config fallback
+    let mqtt_username = std::env::var("MQTT_USERNAME").unwrap_or_else(|_| config.mqtt.username.clone());
+    let mqtt_password = std::env::var("MQTT_PASSWORD").unwrap_or_else(|_| config.mqtt.password.clone());
+
+    log::info!("Connecting to MQTT broker at {}:{}", host, port);
+    log::info!("  Using username: {}", mqtt_username);
+
+    // Configure MQTT client
+    let mut mqtt_options = MqttOptions::new("web-gui", host.clone(), port);
+    mqtt_options.set_keep_alive(std::time::Duration::from_secs(5));
+    
+    // Set authentication credentials
+    mqtt_options.set_credentials(mqtt_username, mqtt_password);
+
+    // Configure TLS if enabled
+    if config.mqtt.use_tls {
+        let ca_cert = fs::read(&config.mqtt.ca_cert)
+            .expect("Failed to read CA certificate. Run ./genssl.sh first.");
+        
+        log::info!("  MQTT TLS enabled, CA cert: {}", config.mqtt.ca_cert);
+        
+        // Use TLS with CA certificate verification
         let transport = Transport::tls(ca_cert, None, None);
         mqtt_options.set_transport(transport);
     }
 
     let (client, mut eventloop) = AsyncClient::new(mqtt_options, 10);
 
-    // Subscribe to all device topics
-    let topic = state.config.mqtt.topic.clone();
-    client.subscribe(&topic, QoS::AtMostOnce).await?;
-    log::info!("Subscribed to MQTT topic: {}", topic);
+    // Subscribe to all device topics (re-issued on every ConnAck below)
+    let topic = config.mqtt.topic.clone();
+    let mut connected = false;
+    let mut backoff = MQTT_MIN_BACKOFF;
 
     // Process MQTT events
     loop {
         match eventloop.poll().await {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                log::info!("MQTT connected to {}:{}", host, port);
+                connected = true;
+                backoff = MQTT_MIN_BACKOFF;
+
+                // With a clean session the broker forgets our subscription on reconnect
+                match client.try_subscribe(&topic, QoS::AtMostOnce) {
+                    Ok(()) => log::info!("Subscribed to MQTT topic: {}", topic),
+                    Err(e) => log::error!("Failed to subscribe to {}: {:?}", topic, e),
+                }
+            }
             Ok(Event::Incoming(Packet::Publish(publish))) => {
+                state.metrics.mqtt_messages.fetch_add(1, Ordering::Relaxed);
+
                 // Parse JSON payload
-                if let Ok(payload) = std::str::from_utf8(&publish.payload) {
-                    if let Ok(event) = serde_json::from_str::<MqttDeviceEvent>(payload) {
-                        // Update device state
-                        let mut devices = state.devices.write().await;
-
-                        let device =
-                            devices
-                                .entry(event.mac_hash.clone())
-                                .or_insert_with(|| DeviceState {
-                                    mac_hash: event.mac_hash.clone(),
-                                    readings: HashMap::new(),
-                                    last_seen: event.timestamp,
-                                    position: None,
-                                });
-
-                        device.readings.insert(
-                            event.station.clone(),
-                            RssiReading {
-                                rssi: event.rssi,
-                                timestamp: event.timestamp,
-                            },
-                        );
-                        device.last_seen = event.timestamp;
-
-                        // Calculate smoothed position using position tracker
-                        let readings_for_triangulation: HashMap<String, TriangulateRssiReading> =
-                            device
-                                .readings
-                                .iter()
-                                .map(|(k, v)| {
-                                    (
-                                        k.clone(),
-                                        TriangulateRssiReading {
-                                            rssi: v.rssi,
-                                            timestamp: v.timestamp,
-                                        },
-                                    )
-                                })
-                                .collect();
+                let event = std::str::from_utf8(&publish.payload)
+                    .map_err(|e| e.to_string())
+                    .and_then(|payload| {
+                        serde_json::from_str::<MqttDeviceEvent>(payload).map_err(|e| e.to_string())
+                    });
 
-                        // Update position with smoothing (requires mutable access)
-                        let mut tracker = state.position_tracker.write().await;
-                        device.position =
-                            tracker.update_position(&event.mac_hash, &readings_for_triangulation);
-
-                        log::debug!(
-                            "Device {} seen by {} with RSSI {}, position: {:?}",
-                            event.mac_hash,
-                            event.station,
-                            event.rssi,
-                            device.position
-                        );
+                match event {
+                    Ok(event) => {
+                        state.metrics.readings_received.fetch_add(1, Ordering::Relaxed);
+                        handle_device_event(&state, event).await
+                    }
+                    Err(e) => {
+                        let failures = state.metrics.parse_failures.fetch_add(1, Ordering::Relaxed);
+                        // Log occasionally, don't spam
+                        if failures % PARSE_FAILURE_LOG_EVERY == 0 {
+                            let snippet: String = String::from_utf8_lossy(&publish.payload)
+                                .chars()
+                                .take(PAYLOAD_SNIPPET_LEN)
+                                .collect();
+                            log::warn!(
+                                "Malformed MQTT payload on {} ({} total): {} - payload: {:?}",
+                                publish.topic,
+                                failures + 1,
+                                e,
+                                snippet
+                            );
+                        }
                     }
                 }
             }
             Ok(_) => {}
             Err(e) => {
-                log::error!("MQTT error: {:?}", e);
-                tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+                if connected {
+                    log::warn!("MQTT connection lost: {:?}", e);
+                    connected = false;
+                } else {
+                    log::error!("MQTT connection failed: {:?}", e);
+                }
+                log::info!("Reconnecting to MQTT in {:?}", backoff);
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MQTT_MAX_BACKOFF);
             }
         }
 
@@ -382,11 +1964,497 @@ async fn mqtt_subscriber(state: AppState) -> Result<()> {
     }
 }
 
-/// Remove devices that haven't been seen recently
+/// Apply a device event to the shared state and recompute its position
+async fn handle_device_event(state: &AppState, event: MqttDeviceEvent) {
+    state
+        .station_last_seen
+        .write()
+        .await
+        .insert(event.station.clone(), Instant::now());
+    if let Some(location) = event.location {
+        state
+            .station_locations
+            .write()
+            .await
+            .insert(event.station.clone(), location);
+    }
+
+    let config = state.config().await;
+    let label = state.labels.read().await.get(&event.mac_hash).cloned();
+    let mut devices = state.devices.write().await;
+
+    let now_ms = unix_millis();
+    let timeout = Duration::from_secs(config.display.device_timeout);
+    let was_active = devices
+        .get(&event.mac_hash)
+        .is_some_and(|d| d.received_at.elapsed() <= timeout);
+    if !was_active {
+        state.metrics.devices_active.fetch_add(1, Ordering::Relaxed);
+    }
+    let device = devices
+        .entry(event.mac_hash.clone())
+        .or_insert_with(|| DeviceState {
+            mac_hash: event.mac_hash.clone(),
+            readings: HashMap::new(),
+            last_seen: now_ms,
+            device_timestamp: event.timestamp,
+            position: None,
+            station_count: 0,
+            residual: None,
+            velocity: None,
+            implausible_speed: false,
+            low_confidence: false,
+            estimated_error: None,
+            gdop: None,
+            station_weights: HashMap::new(),
+            pixel_position: None,
+            label,
+            floor: None,
+            clamped: false,
+            randomized: event.randomized,
+            group_id: event.mac_hash.clone(),
+            received_at: Instant::now(),
+            interpolate_from: None,
+        });
+
+    device.readings.insert(
+        event.station.clone(),
+        RssiReading {
+            rssi: event.rssi,
+            timestamp: now_ms,
+            device_timestamp: event.timestamp,
+        },
+    );
+
+    // Forget readings from stations that haven't seen the device recently
+    let window_ms = config.reading_window_secs().saturating_mul(1000);
+    device
+        .readings
+        .retain(|_, reading| now_ms.saturating_sub(reading.timestamp) <= window_ms);
+    device.last_seen = now_ms;
+    device.device_timestamp = event.timestamp;
+    // The glide to the new position starts wherever the marker is shown right now
+    let interpolation = Duration::from_millis(config.display.interpolation_ms);
+    device.interpolate_from = device
+        .interpolated_position(device.received_at.elapsed(), interpolation)
+        .filter(|_| config.display.interpolate);
+    device.received_at = Instant::now();
+    device.randomized = event.randomized;
+    if config.grouping.enabled {
+        let sighting = Sighting {
+            mac_hash: &event.mac_hash,
+            randomized: event.randomized,
+            station: &event.station,
+            rssi: event.rssi,
+            fingerprint: event.fingerprint.as_deref(),
+            timestamp: now_ms,
+        };
+        let mut groups = state.device_groups.write().await;
+        device.group_id = groups
+            .observe(&config.grouping.to_grouper_config(), sighting)
+            .to_string();
+    }
+
+    // Calculate smoothed position using position tracker
+    let readings_for_triangulation: HashMap<String, TriangulateRssiReading> = device
+        .readings
+        .iter()
+        .map(|(k, v)| {
+            (
+                k.clone(),
+                TriangulateRssiReading {
+                    rssi: v.rssi,
+                    timestamp: v.timestamp,
+                },
+            )
+        })
+        .collect();
+
+    // Update position with smoothing (requires mutable access)
+    let mut tracker = state.position_tracker.write().await;
+    device.station_count = tracker.contributing_stations(&readings_for_triangulation);
+    device.position = tracker.update_position(&event.mac_hash, &readings_for_triangulation);
+    let estimate = device
+        .position
+        .and_then(|_| tracker.get_estimate(&event.mac_hash));
+    device.residual = estimate.map(|e| e.residual);
+    device.velocity = estimate.and_then(|e| e.velocity);
+    device.implausible_speed = estimate.is_some_and(|e| e.implausible_speed);
+    device.low_confidence = estimate.is_some_and(|e| e.low_confidence);
+    device.estimated_error = estimate.map(|e| e.estimated_error);
+    device.gdop = estimate.and_then(|e| e.gdop);
+    device.station_weights = device
+        .position
+        .and_then(|_| tracker.get_station_weights(&event.mac_hash))
+        .cloned()
+        .unwrap_or_default();
+    device.floor = tracker.get_floor(&event.mac_hash).map(str::to_string);
+    drop(tracker);
+
+    device.clamped = false;
+    if config.triangulation.clamp_to_room {
+        let floor = device.floor.as_deref().unwrap_or(DEFAULT_FLOOR);
+        if let (Some(pos), Some((width, height))) = (device.position, config.floor_dimensions(floor)) {
+            let clamped = Position {
+                x: pos.x.clamp(0.0, width),
+                y: pos.y.clamp(0.0, height),
+                ..pos
+            };
+            device.clamped = clamped != pos;
+            device.position = Some(clamped);
+        }
+    }
+    if device.position.is_some() {
+        state.metrics.positions_computed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    if let (Some(history), Some(pos)) = (&state.history, device.position) {
+        let record = HistoryRecord {
+            mac_hash: event.mac_hash.clone(),
+            x: pos.x,
+            y: pos.y,
+            ts: unix_millis(),
+        };
+        // Drop the record rather than stall MQTT processing if the writer falls behind
+        if history.try_send(HistoryCommand::Record(record)).is_err() {
+            log::warn!("Position history queue full, dropping record");
+        }
+    }
+
+    state.mark_changed();
+
+    log::debug!(
+        "Device {} seen by {} with RSSI {}, position: {:?}",
+        event.mac_hash,
+        event.station,
+        event.rssi,
+        device.position
+    );
+}
+
+/// One line in the position history file
+#[derive(Debug, Serialize)]
+struct HistoryRecord {
+    mac_hash: String,
+    x: f32,
+    y: f32,
+    /// Server time in Unix milliseconds
+    ts: u64,
+}
+
+/// Messages for the history writer task
+enum HistoryCommand {
+    Record(HistoryRecord),
+    /// Flush buffered records to disk, then acknowledge
+    Flush(oneshot::Sender<()>),
+}
+
+/// Records queued for the history writer before new ones are dropped
+const HISTORY_CHANNEL_CAPACITY: usize = 1024;
+
+/// Current server time in Unix milliseconds
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Open the history file for appending, returning the writer and current file size
+async fn open_history_file(path: &Path) -> std::io::Result<(BufWriter<File>, u64)> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let file = OpenOptions::new().create(true).append(true).open(path).await?;
+    let size = file.metadata().await?.len();
+    Ok((BufWriter::new(file), size))
+}
+
+/// Write position records to the JSONL history file, flushing periodically
+/// and rotating to `<path>.<unix_secs>` once the file exceeds `max_file_mb`
+async fn history_writer(config: HistoryConfig, mut rx: mpsc::Receiver<HistoryCommand>) {
+    let path = PathBuf::from(&config.path);
+    let max_bytes = config.max_file_mb.max(1) * 1024 * 1024;
+
+    let (mut writer, mut size) = match open_history_file(&path).await {
+        Ok(opened) => opened,
+        Err(e) => {
+            log::error!("Failed to open history file {}: {:?}", path.display(), e);
+            return;
+        }
+    };
+
+    let mut flush_timer =
+        tokio::time::interval(Duration::from_secs(config.flush_interval_secs.max(1)));
+
+    loop {
+        tokio::select! {
+            command = rx.recv() => match command {
+                Some(HistoryCommand::Record(record)) => {
+                    let Ok(mut line) = serde_json::to_string(&record) else {
+                        continue;
+                    };
+                    line.push('\n');
+                    if let Err(e) = writer.write_all(line.as_bytes()).await {
+                        log::error!("Failed to write position history: {:?}", e);
+                        continue;
+                    }
+                    size += line.len() as u64;
+
+                    if size >= max_bytes {
+                        match rotate_history_file(&path, &mut writer).await {
+                            Ok(new_size) => size = new_size,
+                            Err(e) => log::error!("Failed to rotate history file: {:?}", e),
+                        }
+                    }
+                }
+                Some(HistoryCommand::Flush(done)) => {
+                    if let Err(e) = writer.flush().await {
+                        log::error!("Failed to flush position history: {:?}", e);
+                    }
+                    let _ = done.send(());
+                }
+                None => break,
+            },
+            _ = flush_timer.tick() => {
+                if let Err(e) = writer.flush().await {
+                    log::error!("Failed to flush position history: {:?}", e);
+                }
+            }
+        }
+    }
+
+    let _ = writer.flush().await;
+}
+
+/// Move the current history file aside and start a fresh one
+async fn rotate_history_file(path: &Path, writer: &mut BufWriter<File>) -> std::io::Result<u64> {
+    writer.flush().await?;
+
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{}", unix_millis() / 1000));
+    tokio::fs::rename(path, &rotated).await?;
+    log::info!("Rotated position history to {}", Path::new(&rotated).display());
+
+    let (new_writer, size) = open_history_file(path).await?;
+    *writer = new_writer;
+    Ok(size)
+}
+
+/// How often the cleanup task runs
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Run `cleanup_old_devices` every `CLEANUP_INTERVAL`
+async fn cleanup_task(state: AppState) {
+    let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+    loop {
+        interval.tick().await;
+        cleanup_old_devices(&state).await;
+    }
+}
+
+/// Recount the devices seen within `device_timeout` for the active device gauge
 /// NOTE: Stale device removal is disabled - all devices are kept indefinitely
-async fn cleanup_old_devices(_state: &AppState) {
-    // Stale device removal disabled - keeping all devices
+async fn cleanup_old_devices(state: &AppState) {
+    let timeout = Duration::from_secs(state.config().await.display.device_timeout);
+    let active = state
+        .devices
+        .read()
+        .await
+        .values()
+        .filter(|d| d.received_at.elapsed() <= timeout)
+        .count();
+    state.metrics.devices_active.store(active as u64, Ordering::Relaxed);
 }
 
 // Import for stream operations
 use futures_util::{SinkExt, StreamExt};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// SHA-256 of "secret"
+    const SECRET_SHA256: &str = "2bb80d537b1da3e38bd30361aa855686bde0eacd7162fef6a25fe97bf527a25b";
+
+    fn auth(password_sha256: &str) -> AuthConfig {
+        AuthConfig {
+            username: "admin".to_string(),
+            password_sha256: password_sha256.to_string(),
+        }
+    }
+
+    /// Device with the given station readings, no position yet
+    fn device(mac_hash: &str, readings: &[(&str, i8)]) -> DeviceState {
+        DeviceState {
+            mac_hash: mac_hash.to_string(),
+            readings: readings
+                .iter()
+                .map(|&(station, rssi)| {
+                    let reading = RssiReading {
+                        rssi,
+                        timestamp: 0,
+                        device_timestamp: 0,
+                    };
+                    (station.to_string(), reading)
+                })
+                .collect(),
+            last_seen: 0,
+            device_timestamp: 0,
+            position: None,
+            station_count: readings.len(),
+            residual: None,
+            velocity: None,
+            implausible_speed: false,
+            low_confidence: false,
+            estimated_error: None,
+            gdop: None,
+            station_weights: HashMap::new(),
+            pixel_position: None,
+            label: None,
+            floor: None,
+            clamped: false,
+            randomized: false,
+            group_id: mac_hash.to_string(),
+            received_at: Instant::now(),
+            interpolate_from: None,
+        }
+    }
+
+    #[test]
+    fn test_check_credentials() {
+        let auth = auth(SECRET_SHA256);
+        assert!(check_credentials(&auth, "admin:secret"));
+        assert!(!check_credentials(&auth, "root:secret"));
+        assert!(!check_credentials(&auth, "admin:Secret"));
+        assert!(!check_credentials(&auth, "admin:"));
+        assert!(!check_credentials(&auth, "adminsecret"));
+        assert!(!check_credentials(&auth, ""));
+    }
+
+    #[test]
+    fn test_check_credentials_normalizes_configured_hash() {
+        let padded = format!("  {}\n", SECRET_SHA256.to_ascii_uppercase());
+        assert!(check_credentials(&auth(&padded), "admin:secret"));
+        assert!(!check_credentials(&auth(&SECRET_SHA256[..63]), "admin:secret"));
+    }
+
+    #[test]
+    fn test_check_credentials_password_with_colon() {
+        // Only the first ':' separates the username, the rest belongs to the password
+        let auth = auth("1507e7f82a2b0181d415933d5c8a9ea43972e705486fcbe936a742f6ab4e17fe");
+        assert!(check_credentials(&auth, "admin:pa:ss"));
+        assert!(!check_credentials(&auth, "admin:pa"));
+        assert!(!check_credentials(&auth, "admin:pa:ss:"));
+    }
+
+    #[test]
+    fn test_build_heatmap() {
+        // 5 x 3 m at 2 m cells rounds up to 3 x 2 cells
+        let positions = [
+            Position::new(0.5, 0.5),
+            Position::new(1.9, 1.9),
+            Position::new(4.5, 2.5),
+            // Outside the floor, clamped into the nearest edge cell
+            Position::new(-1.0, 10.0),
+            Position::new(50.0, -3.0),
+        ];
+        let heatmap = build_heatmap("main".to_string(), 5.0, 3.0, &positions, 2.0);
+        assert_eq!((heatmap.cols, heatmap.rows), (3, 2));
+        assert_eq!(heatmap.cells, vec![vec![2, 0, 1], vec![1, 0, 1]]);
+
+        // A cell larger than the floor still gives one cell holding everything
+        let heatmap = build_heatmap("main".to_string(), 5.0, 3.0, &positions, 10.0);
+        assert_eq!(heatmap.cells, vec![vec![5]]);
+    }
+
+    #[test]
+    fn test_client_filter_station() {
+        let filter = ClientFilter {
+            station: Some("2".to_string()),
+            min_rssi: Some(-70),
+            ..Default::default()
+        };
+        assert!(filter.matches(&device("a", &[("1", -80), ("2", -60)])));
+        // The threshold applies to the chosen station, not the strongest reading
+        assert!(!filter.matches(&device("b", &[("1", -40), ("2", -75)])));
+        assert!(!filter.matches(&device("c", &[("1", -40)])));
+    }
+
+    #[test]
+    fn test_client_filter_min_rssi() {
+        let filter = ClientFilter {
+            min_rssi: Some(-70),
+            ..Default::default()
+        };
+        assert!(filter.matches(&device("a", &[("1", -80), ("2", -70)])));
+        assert!(!filter.matches(&device("b", &[("1", -80), ("2", -71)])));
+        assert!(!filter.matches(&device("c", &[])));
+        assert!(ClientFilter::default().matches(&device("d", &[])));
+    }
+
+    #[test]
+    fn test_client_filter_floor() {
+        let filter = ClientFilter {
+            floor: Some(DEFAULT_FLOOR.to_string()),
+            ..Default::default()
+        };
+        let mut device = device("a", &[("1", -50)]);
+        // Devices without a floor are on the main room
+        assert!(filter.matches(&device));
+        device.floor = Some("upstairs".to_string());
+        assert!(!filter.matches(&device));
+        device.floor = Some(DEFAULT_FLOOR.to_string());
+        assert!(filter.matches(&device));
+    }
+
+    /// Device located at (x, y) on `floor`, last seen at `last_seen`
+    fn located(mac_hash: &str, x: f32, y: f32, floor: Option<&str>, last_seen: u64) -> DeviceState {
+        DeviceState {
+            position: Some(Position::new(x, y)),
+            floor: floor.map(str::to_string),
+            last_seen,
+            ..device(mac_hash, &[])
+        }
+    }
+
+    #[test]
+    fn test_cluster_devices() {
+        let devices = vec![
+            located("a", 1.2, 1.2, None, 10),
+            located("b", 1.8, 1.6, Some(DEFAULT_FLOOR), 30),
+            located("c", 1.5, 1.4, None, 20),
+            // Same spot on another floor
+            located("d", 1.5, 1.5, Some("upstairs"), 40),
+            // Alone in its cell
+            located("e", 5.0, 5.0, None, 50),
+            device("f", &[("1", -50)]),
+        ];
+        let (unclustered, clusters) = cluster_devices(devices, 2.0);
+
+        assert_eq!(clusters.len(), 1);
+        let cluster = &clusters[0];
+        assert_eq!(cluster.floor, DEFAULT_FLOOR);
+        assert_eq!(cluster.count, 3);
+        assert_eq!(cluster.mac_hashes, ["b", "c", "a"]);
+        assert_eq!(cluster.last_seen, 30);
+        assert!((cluster.position.x - 1.5).abs() < 1e-5);
+        assert!((cluster.position.y - 1.4).abs() < 1e-5);
+
+        let mut rest: Vec<&str> = unclustered.iter().map(|d| d.mac_hash.as_str()).collect();
+        rest.sort();
+        assert_eq!(rest, ["d", "e", "f"]);
+    }
+
+    #[test]
+    fn test_cluster_devices_split_at_cell_edge() {
+        // 0.2 m apart but on either side of the 2 m grid line
+        let devices = vec![
+            located("a", 1.9, 1.0, None, 1),
+            located("b", 2.1, 1.0, None, 2),
+        ];
+        let (unclustered, clusters) = cluster_devices(devices, 2.0);
+        assert!(clusters.is_empty());
+        assert_eq!(unclustered.len(), 2);
+    }
+}
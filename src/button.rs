@@ -0,0 +1,67 @@
+use anyhow::Result;
+use esp_idf_svc::hal::gpio::{AnyIOPin, Input, PinDriver, Pull};
+use std::thread;
+use std::time::Duration;
+use crate::sniffer;
+
+/// Default button GPIO (BOOT button on most ESP32 devkits), override with BUTTON_GPIO
+const DEFAULT_BUTTON_GPIO: i32 = 0;
+
+/// Button poll interval
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Consecutive polls the level must hold before a change counts (50ms)
+const DEBOUNCE_POLLS: u32 = 5;
+
+/// Button thread stack size (bytes)
+const BUTTON_STACK_SIZE: usize = 3072;
+
+/// Configured pause button GPIO number
+pub fn gpio() -> i32 {
+    option_env!("BUTTON_GPIO")
+        .and_then(|pin| pin.parse().ok())
+        .unwrap_or(DEFAULT_BUTTON_GPIO)
+}
+
+/// Start the thread that toggles capture on each press of the (active low) button on `pin`
+pub fn start(pin: AnyIOPin) -> Result<()> {
+    let mut button = PinDriver::input(pin)?;
+    button.set_pull(Pull::Up)?;
+    thread::Builder::new()
+        .stack_size(BUTTON_STACK_SIZE)
+        .spawn(move || run(button))?;
+    Ok(())
+}
+
+fn run(button: PinDriver<'static, AnyIOPin, Input>) {
+    let mut pressed = button.is_low();
+    let mut stable_polls = 0;
+
+    loop {
+        thread::sleep(POLL_INTERVAL);
+
+        // Only accept a new level once it has held for the whole debounce window
+        if button.is_low() == pressed {
+            stable_polls = 0;
+            continue;
+        }
+        stable_polls += 1;
+        if stable_polls < DEBOUNCE_POLLS {
+            continue;
+        }
+        stable_polls = 0;
+        pressed = !pressed;
+
+        // Toggle on press, ignore the release
+        if pressed {
+            let result = if sniffer::is_paused() {
+                sniffer::resume_sniffer()
+            } else {
+                sniffer::pause_sniffer()
+            };
+            if let Err(e) = result {
+                log::error!("Button toggle failed: {:?}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use anyhow::{bail, Result};
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use log::{error, info, warn};
+use std::io::{BufRead, ErrorKind};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+/// NVS namespace of the credentials
+const NVS_NAMESPACE: &str = "credentials";
+
+/// Longest value stored, enough for a broker URL
+const MAX_VALUE_LEN: usize = 128;
+
+/// NVS key of each credential and its build-time value, stored once on first boot
+const FIELDS: [(&str, Option<&str>); 5] = [
+    ("wifi_ssid", option_env!("WIFI_SSID")),
+    ("wifi_pass", option_env!("WIFI_PASS")),
+    ("mqtt_broker", option_env!("MQTT_BROKER")),
+    ("mqtt_user", option_env!("MQTT_USERNAME")),
+    ("mqtt_pass", option_env!("MQTT_PASSWORD")),
+];
+
+/// Keys whose values are never echoed on the console
+const SECRET_KEYS: &[&str] = &["wifi_pass", "mqtt_pass"];
+
+/// Console poll interval while no input is waiting
+const CONSOLE_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Console thread stack size (bytes)
+const CONSOLE_STACK_SIZE: usize = 4096;
+
+/// WiFi and MQTT credentials in use, read from NVS at boot
+#[derive(Clone, Default)]
+pub struct Credentials {
+    pub wifi_ssid: String,
+    /// Empty for an open network
+    pub wifi_pass: String,
+    /// mqtts://host:8883, or mqtt://host:1883 with the no-tls feature
+    pub mqtt_broker: String,
+    pub mqtt_username: String,
+    pub mqtt_password: String,
+}
+
+impl Credentials {
+    /// Whether the station has enough to connect to WiFi and the broker
+    pub fn is_complete(&self) -> bool {
+        !self.wifi_ssid.is_empty() && !self.mqtt_broker.is_empty()
+    }
+}
+
+static CREDENTIALS: OnceLock<Credentials> = OnceLock::new();
+
+/// Handle to the stored credentials, opened once NVS is available
+static STORE: Mutex<Option<EspNvs<NvsDefault>>> = Mutex::new(None);
+
+/// Read the credentials from NVS, storing the build-time values for any not yet there
+pub fn load(nvs: EspDefaultNvsPartition) -> Result<&'static Credentials> {
+    let store = EspNvs::new(nvs, NVS_NAMESPACE, true)?;
+    let mut buf = [0u8; MAX_VALUE_LEN + 1];
+    let mut values: [String; 5] = Default::default();
+    for ((key, default), value) in FIELDS.iter().zip(values.iter_mut()) {
+        if let Some(stored) = store.get_str(key, &mut buf)? {
+            *value = stored.to_string();
+        } else if let Some(default) = default.filter(|default| !default.is_empty()) {
+            store.set_str(key, default)?;
+            info!("Stored {} from the build in NVS", key);
+            *value = default.to_string();
+        }
+    }
+    if let Ok(mut guard) = STORE.lock() {
+        *guard = Some(store);
+    }
+
+    let [wifi_ssid, wifi_pass, mqtt_broker, mqtt_username, mqtt_password] = values;
+    if !mqtt_broker.is_empty() {
+        if let Err(e) = check_broker(&mqtt_broker) {
+            error!("{:?}", e);
+        }
+    }
+    Ok(CREDENTIALS.get_or_init(|| Credentials {
+        wifi_ssid,
+        wifi_pass,
+        mqtt_broker,
+        mqtt_username,
+        mqtt_password,
+    }))
+}
+
+/// Credentials loaded at boot
+pub fn get() -> &'static Credentials {
+    CREDENTIALS.get().expect("credentials::load runs at boot")
+}
+
+/// The broker URL scheme has to match the transport compiled in
+fn check_broker(url: &str) -> Result<()> {
+    let scheme = if cfg!(feature = "no-tls") { "mqtt://" } else { "mqtts://" };
+    if !url.starts_with(scheme) {
+        bail!("MQTT broker must start with {} in this build, got {}", scheme, url);
+    }
+    Ok(())
+}
+
+/// Store one credential in NVS, used from the next boot on
+fn set(key: &str, value: &str) -> Result<()> {
+    if !FIELDS.iter().any(|(field, _)| *field == key) {
+        bail!("Unknown credential '{}'", key);
+    }
+    if value.len() > MAX_VALUE_LEN {
+        bail!("Value longer than {} bytes", MAX_VALUE_LEN);
+    }
+    if key == "mqtt_broker" {
+        check_broker(value)?;
+    }
+    let mut guard = STORE.lock().map_err(|_| anyhow::anyhow!("Credential store poisoned"))?;
+    let Some(store) = guard.as_mut() else {
+        bail!("Credential store not open");
+    };
+    store.set_str(key, value)?;
+    Ok(())
+}
+
+/// Start the provisioning console on the serial port
+///
+/// Lines read: `set <key> <value>` (keys `wifi_ssid`, `wifi_pass`, `mqtt_broker`, `mqtt_user`,
+/// `mqtt_pass`; the value is the rest of the line, spaces included), `show` and `restart`.
+pub fn start_console() -> Result<()> {
+    thread::Builder::new()
+        .stack_size(CONSOLE_STACK_SIZE)
+        .spawn(run_console)?;
+    Ok(())
+}
+
+fn run_console() {
+    let stdin = std::io::stdin();
+    let mut line = String::new();
+    loop {
+        // The UART console doesn't block, poll until a whole line has arrived
+        match stdin.lock().read_line(&mut line) {
+            Ok(_) if line.ends_with('\n') => {}
+            Ok(_) => {
+                thread::sleep(CONSOLE_POLL_INTERVAL);
+                continue;
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::Interrupted => {
+                thread::sleep(CONSOLE_POLL_INTERVAL);
+                continue;
+            }
+            Err(e) => {
+                warn!("Serial console read failed: {}", e);
+                line.clear();
+                thread::sleep(CONSOLE_POLL_INTERVAL);
+                continue;
+            }
+        }
+        handle_line(line.trim_end_matches(['\r', '\n']));
+        line.clear();
+    }
+}
+
+fn handle_line(line: &str) {
+    let (command, rest) = line.trim_start().split_once(' ').unwrap_or((line.trim(), ""));
+    match command {
+        "" => {}
+        "set" => {
+            let (key, value) = rest.trim_start().split_once(' ').unwrap_or((rest.trim(), ""));
+            match set(key, value) {
+                Ok(()) if SECRET_KEYS.contains(&key) => {
+                    info!("Stored {} (restart to apply)", key);
+                }
+                Ok(()) => info!("Stored {} = {} (restart to apply)", key, value),
+                Err(e) => error!("Failed to store {}: {:?}", key, e),
+            }
+        }
+        "show" => {
+            let credentials = get();
+            let masked = |secret: &str| if secret.is_empty() { "(empty)" } else { "****" };
+            info!(
+                "wifi_ssid = {}, wifi_pass = {}, mqtt_broker = {}, mqtt_user = {}, mqtt_pass = {}",
+                credentials.wifi_ssid,
+                masked(&credentials.wifi_pass),
+                credentials.mqtt_broker,
+                credentials.mqtt_username,
+                masked(&credentials.mqtt_password)
+            );
+        }
+        "restart" => {
+            info!("Restarting");
+            unsafe { esp_idf_svc::sys::esp_restart() }
+        }
+        other => warn!("Unknown console command '{}' (set <key> <value>, show, restart)", other),
+    }
+}
@@ -0,0 +1,32 @@
+//! Error type of the library APIs.
+//!
+//! The binaries keep using `anyhow`, which wraps `SnifferError` like any other error,
+//! while library users can match on the failure.
+
+use std::fmt;
+
+/// Failure of a library function
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnifferError {
+    /// An ESP-IDF call returned this error code
+    Driver(i32),
+    /// Input that doesn't have the expected format, e.g. a malformed MAC address
+    Parse(String),
+    /// Configuration values that can't be used
+    Config(String),
+}
+
+impl fmt::Display for SnifferError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnifferError::Driver(code) => write!(f, "driver error {}", code),
+            SnifferError::Parse(message) => write!(f, "parse error: {}", message),
+            SnifferError::Config(message) => write!(f, "invalid configuration: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for SnifferError {}
+
+/// Result of a library function
+pub type Result<T> = std::result::Result<T, SnifferError>;
@@ -0,0 +1,155 @@
+//! Device event published by the stations on `sniffer/<station>/device`.
+//!
+//! The firmware serializes `MqttDeviceEvent` and the web dashboard deserializes the same
+//! type, so the wire format has a single definition. Fields added after the first firmware
+//! release default when missing, which keeps older stations readable.
+
+use serde::{Deserialize, Serialize};
+
+/// 802.11 channel width, decoded from the secondary channel field of `rx_ctrl`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Bandwidth {
+    /// 20 MHz, no secondary channel
+    Ht20,
+    /// 40 MHz with the secondary channel above the primary
+    Ht40Plus,
+    /// 40 MHz with the secondary channel below the primary
+    Ht40Minus,
+    /// Reserved value, or not reported by older firmware
+    #[default]
+    Unknown,
+}
+
+impl Bandwidth {
+    /// Decode `rx_ctrl.secondary_channel()` (0 none, 1 above, 2 below)
+    pub fn from_secondary_channel(secondary: u32) -> Self {
+        match secondary {
+            0 => Bandwidth::Ht20,
+            1 => Bandwidth::Ht40Plus,
+            2 => Bandwidth::Ht40Minus,
+            _ => Bandwidth::Unknown,
+        }
+    }
+}
+
+/// WMM access category of a QoS data frame, from the TID in its QoS control field
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessCategory {
+    Background,
+    BestEffort,
+    Video,
+    Voice,
+}
+
+impl AccessCategory {
+    /// Map a TID (user priority 0-7) to its access category
+    pub fn from_tid(tid: u8) -> Self {
+        match tid & 0x07 {
+            1 | 2 => AccessCategory::Background,
+            4 | 5 => AccessCategory::Video,
+            6 | 7 => AccessCategory::Voice,
+            _ => AccessCategory::BestEffort,
+        }
+    }
+}
+
+/// Kind of frame a device sends when joining an AP
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JoinKind {
+    Association,
+    Reassociation,
+    Authentication,
+}
+
+/// Presence change published in first-seen mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Presence {
+    /// First frame from the device
+    Arrived,
+    /// No frames from the device for the departure timeout, carries its last reading
+    Departed,
+}
+
+/// The AP a device is joining, as published
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JoinTarget {
+    #[serde(rename = "type")]
+    pub kind: JoinKind,
+    /// `aa:bb:cc:dd:ee:ff`
+    pub bssid: String,
+}
+
+/// Where a station is installed, lets one broker serve stations on several sites
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GeoLocation {
+    /// Degrees north
+    pub lat: f64,
+    /// Degrees east
+    pub lon: f64,
+}
+
+impl GeoLocation {
+    /// Parse `lat,lon` in decimal degrees, e.g. `55.6761,12.5683`
+    pub fn parse(text: &str) -> Option<Self> {
+        let (lat, lon) = text.split_once(',')?;
+        let location = GeoLocation {
+            lat: lat.trim().parse().ok()?,
+            lon: lon.trim().parse().ok()?,
+        };
+        let valid = (-90.0..=90.0).contains(&location.lat)
+            && (-180.0..=180.0).contains(&location.lon);
+        valid.then_some(location)
+    }
+}
+
+/// One device reading as published by a station
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MqttDeviceEvent {
+    /// SHA-256 of the source MAC, 64 hex digits
+    pub mac_hash: String,
+    pub rssi: i8,
+    /// 0-100% display quality derived from `rssi`
+    #[serde(default)]
+    pub quality: u8,
+    #[serde(default)]
+    pub channel: u8,
+    /// Station clock in microseconds
+    pub timestamp: u64,
+    pub station: String,
+    /// Locally administered MAC, absent from older firmware
+    #[serde(default)]
+    pub randomized: bool,
+    #[serde(default)]
+    pub bandwidth: Bandwidth,
+    /// Set for QoS data frames
+    #[serde(default)]
+    pub access_category: Option<AccessCategory>,
+    /// Probe request fingerprint, 8 hex digits
+    #[serde(default)]
+    pub fingerprint: Option<String>,
+    /// Heuristic device class from the probe request, e.g. `apple`
+    #[serde(default)]
+    pub device_class: Option<String>,
+    /// Vendor-specific IEs as `oui:type`, e.g. `0050f2:04`
+    #[serde(default)]
+    pub vendor_ies: Vec<String>,
+    /// Set for (re)association and authentication frames sent by the device
+    #[serde(default)]
+    pub join: Option<JoinTarget>,
+    /// Set in first-seen mode
+    #[serde(default)]
+    pub presence: Option<Presence>,
+    /// Frames behind this reading, 1 unless the station aggregates
+    #[serde(default)]
+    pub frames: u32,
+    /// How many of them were retransmissions
+    #[serde(default)]
+    pub retries: u32,
+    /// Station location, left out unless the station has STATION_LOCATION set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<GeoLocation>,
+}
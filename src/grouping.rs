@@ -0,0 +1,325 @@
+//! Heuristic grouping of randomized MAC addresses into physical devices
+//!
+//! Phones rotate their randomized MAC, so one device shows up as a series of short-lived
+//! addresses. When a new randomized address appears shortly after another one went quiet,
+//! is heard at about the same RSSI by the same stations and (if both sent probe requests)
+//! carries the same IE fingerprint, it is taken to be the same device and joins its group.
+//!
+//! This is approximate: two similar phones standing together can be merged, and a device
+//! that moves while rotating its address can be split.
+
+use std::collections::{HashMap, HashSet};
+
+/// Thresholds for matching a new randomized address to one that went quiet
+#[derive(Debug, Clone, PartialEq)]
+pub struct GrouperConfig {
+    /// Longest gap in milliseconds between the old address's last reading and the new
+    /// address's first. A new address that found no match within this time keeps its own group
+    pub window_ms: u64,
+    /// Largest mean RSSI difference in dB over the stations that heard both addresses
+    pub max_rssi_difference: f32,
+    /// Stations that must have heard both addresses
+    pub min_common_stations: usize,
+}
+
+impl Default for GrouperConfig {
+    fn default() -> Self {
+        Self {
+            window_ms: 30_000,
+            max_rssi_difference: 6.0,
+            min_common_stations: 2,
+        }
+    }
+}
+
+/// A single reading of an address
+#[derive(Debug, Clone, Copy)]
+pub struct Sighting<'a> {
+    pub mac_hash: &'a str,
+    /// Locally administered address, only these are grouped
+    pub randomized: bool,
+    pub station: &'a str,
+    pub rssi: i8,
+    /// Probe request fingerprint, if the frame carried one
+    pub fingerprint: Option<&'a str>,
+    /// Server receive time in milliseconds
+    pub timestamp: u64,
+}
+
+/// What the grouper remembers about one address
+#[derive(Debug, Clone)]
+struct Member {
+    group_id: String,
+    randomized: bool,
+    first_seen: u64,
+    last_seen: u64,
+    /// Latest RSSI per station
+    rssi: HashMap<String, i8>,
+    fingerprint: Option<String>,
+    /// Match search is over, the group no longer changes
+    settled: bool,
+    /// A newer address has taken over this one's group
+    succeeded: bool,
+}
+
+/// Assigns every address a group id, shared by addresses judged to be one device
+///
+/// A group id is the MAC hash of the group's first address. Fixed addresses and addresses
+/// that are still quiet candidates keep it while they are remembered; a new randomized
+/// address may switch from its own id to an earlier group's once, within `window_ms` of
+/// appearing. Addresses quiet for twice `window_ms` can no longer match and are forgotten,
+/// one heard again after that starts over as a new address.
+#[derive(Debug, Default)]
+pub struct DeviceGrouper {
+    members: HashMap<String, Member>,
+    /// Time of the last pass over the members for ones that can no longer match
+    last_prune: u64,
+}
+
+impl DeviceGrouper {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a reading and return the group id of its address
+    pub fn observe(&mut self, config: &GrouperConfig, sighting: Sighting) -> &str {
+        if sighting.timestamp.saturating_sub(self.last_prune) >= config.window_ms {
+            self.prune(config, sighting.timestamp);
+        }
+        let member = self
+            .members
+            .entry(sighting.mac_hash.to_string())
+            .or_insert_with(|| Member {
+                group_id: sighting.mac_hash.to_string(),
+                randomized: sighting.randomized,
+                first_seen: sighting.timestamp,
+                last_seen: sighting.timestamp,
+                rssi: HashMap::new(),
+                fingerprint: None,
+                settled: !sighting.randomized,
+                succeeded: false,
+            });
+        member.last_seen = member.last_seen.max(sighting.timestamp);
+        member.rssi.insert(sighting.station.to_string(), sighting.rssi);
+        if let Some(fingerprint) = sighting.fingerprint {
+            member.fingerprint = Some(fingerprint.to_string());
+        }
+
+        if !member.settled {
+            self.settle(config, sighting.mac_hash, sighting.timestamp);
+        }
+        &self.members[sighting.mac_hash].group_id
+    }
+
+    /// Forget addresses that can't be matched by a new one any more
+    ///
+    /// Unsettled addresses past their window are settled first. The rest appeared within
+    /// `window_ms`, so an address quiet for longer than twice that is out of their reach.
+    fn prune(&mut self, config: &GrouperConfig, now: u64) {
+        self.last_prune = now;
+        for member in self.members.values_mut() {
+            if now.saturating_sub(member.first_seen) > config.window_ms {
+                member.settled = true;
+            }
+        }
+        let max_quiet = config.window_ms.saturating_mul(2);
+        self.members.retain(|_, member| now.saturating_sub(member.last_seen) <= max_quiet);
+    }
+
+    /// Look for the address this one replaced, give up once the window has passed
+    fn settle(&mut self, config: &GrouperConfig, mac_hash: &str, now: u64) {
+        let member = &self.members[mac_hash];
+        if now.saturating_sub(member.first_seen) > config.window_ms {
+            self.members.get_mut(mac_hash).expect("member exists").settled = true;
+            return;
+        }
+        let predecessor = self
+            .members
+            .iter()
+            .filter(|(other, _)| other.as_str() != mac_hash)
+            .filter_map(|(other, candidate)| {
+                Self::match_score(config, member, candidate).map(|score| (other, score))
+            })
+            // Prefer a matching fingerprint, then the closest RSSI pattern
+            .max_by(|(_, a), (_, b)| a.0.cmp(&b.0).then(b.1.total_cmp(&a.1)))
+            .map(|(other, _)| other.clone());
+
+        if let Some(other) = predecessor {
+            let predecessor = self.members.get_mut(&other).expect("candidate exists");
+            predecessor.succeeded = true;
+            let group_id = predecessor.group_id.clone();
+            let member = self.members.get_mut(mac_hash).expect("member exists");
+            member.group_id = group_id;
+            member.settled = true;
+        }
+    }
+
+    /// How well `candidate` fits as the address `member` replaced, `None` if it doesn't
+    ///
+    /// The score is whether the fingerprints matched and the mean RSSI difference.
+    fn match_score(
+        config: &GrouperConfig,
+        member: &Member,
+        candidate: &Member,
+    ) -> Option<(bool, f32)> {
+        if !candidate.randomized || candidate.succeeded {
+            return None;
+        }
+        // The old address has to have gone quiet before the new one appeared
+        if candidate.last_seen >= member.first_seen
+            || member.first_seen - candidate.last_seen > config.window_ms
+        {
+            return None;
+        }
+
+        let fingerprint_match = match (&member.fingerprint, &candidate.fingerprint) {
+            (Some(a), Some(b)) if a != b => return None,
+            (Some(_), Some(_)) => true,
+            _ => false,
+        };
+
+        let common: Vec<f32> = member
+            .rssi
+            .iter()
+            .filter_map(|(station, &rssi)| {
+                let other = *candidate.rssi.get(station)?;
+                Some((rssi as f32 - other as f32).abs())
+            })
+            .collect();
+        if common.is_empty() || common.len() < config.min_common_stations {
+            return None;
+        }
+        let difference = common.iter().sum::<f32>() / common.len() as f32;
+        (difference <= config.max_rssi_difference).then_some((fingerprint_match, difference))
+    }
+
+    /// Group id of an address seen before
+    pub fn group_id(&self, mac_hash: &str) -> Option<&str> {
+        self.members.get(mac_hash).map(|m| m.group_id.as_str())
+    }
+
+    /// Number of distinct groups among the remembered addresses, an estimate of the physical
+    /// devices recently behind them
+    pub fn group_count(&self) -> usize {
+        self.members
+            .values()
+            .map(|m| m.group_id.as_str())
+            .collect::<HashSet<_>>()
+            .len()
+    }
+
+    /// Forget an address, its group lives on in the other members
+    pub fn remove(&mut self, mac_hash: &str) {
+        self.members.remove(mac_hash);
+    }
+
+    /// Forget all addresses
+    pub fn clear(&mut self) {
+        self.members.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sighting<'a>(mac_hash: &'a str, station: &'a str, rssi: i8, timestamp: u64) -> Sighting<'a> {
+        Sighting {
+            mac_hash,
+            randomized: true,
+            station,
+            rssi,
+            fingerprint: None,
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn test_rotated_address_joins_group() {
+        let config = GrouperConfig::default();
+        let mut grouper = DeviceGrouper::new();
+        grouper.observe(&config, sighting("old", "s1", -60, 1_000));
+        grouper.observe(&config, sighting("old", "s2", -70, 2_000));
+
+        // New address a few seconds later, heard at about the same strength
+        grouper.observe(&config, sighting("new", "s1", -62, 5_000));
+        assert_eq!(grouper.group_id("new"), Some("new"));
+        assert_eq!(grouper.observe(&config, sighting("new", "s2", -68, 5_500)), "old");
+        assert_eq!(grouper.group_count(), 1);
+    }
+
+    #[test]
+    fn test_different_rssi_pattern_keeps_own_group() {
+        let config = GrouperConfig::default();
+        let mut grouper = DeviceGrouper::new();
+        grouper.observe(&config, sighting("old", "s1", -50, 1_000));
+        grouper.observe(&config, sighting("old", "s2", -80, 1_000));
+
+        // Close to s2 instead of s1, somewhere else in the room
+        grouper.observe(&config, sighting("new", "s1", -80, 5_000));
+        assert_eq!(grouper.observe(&config, sighting("new", "s2", -50, 5_000)), "new");
+    }
+
+    #[test]
+    fn test_overlapping_or_late_addresses_are_not_merged() {
+        let config = GrouperConfig::default();
+        let mut grouper = DeviceGrouper::new();
+        for (mac, t) in [("a", 1_000), ("b", 40_000)] {
+            grouper.observe(&config, sighting(mac, "s1", -60, t));
+            grouper.observe(&config, sighting(mac, "s2", -60, t));
+        }
+        // "c" appears while "b" is still heard, and too long after "a" went quiet
+        grouper.observe(&config, sighting("b", "s1", -60, 45_000));
+        grouper.observe(&config, sighting("c", "s1", -60, 44_000));
+        grouper.observe(&config, sighting("c", "s2", -60, 44_000));
+        assert_eq!(grouper.group_id("c"), Some("c"));
+        assert_eq!(grouper.group_count(), 3);
+    }
+
+    #[test]
+    fn test_fingerprint_mismatch_prevents_merge() {
+        let config = GrouperConfig::default();
+        let mut grouper = DeviceGrouper::new();
+        for (mac, fingerprint, t) in [("old", "aaaa", 1_000), ("new", "bbbb", 5_000)] {
+            for station in ["s1", "s2"] {
+                let sighting = Sighting {
+                    fingerprint: Some(fingerprint),
+                    ..sighting(mac, station, -60, t)
+                };
+                grouper.observe(&config, sighting);
+            }
+        }
+        assert_eq!(grouper.group_id("new"), Some("new"));
+    }
+
+    #[test]
+    fn test_stale_addresses_are_forgotten() {
+        let config = GrouperConfig::default();
+        let mut grouper = DeviceGrouper::new();
+        grouper.observe(&config, sighting("old", "s1", -60, 1_000));
+        grouper.observe(&config, sighting("old", "s2", -60, 1_000));
+
+        // Still a candidate one window later, gone once quiet for two
+        grouper.observe(&config, sighting("other", "s1", -80, 31_000));
+        assert_eq!(grouper.group_id("old"), Some("old"));
+        grouper.observe(&config, sighting("other", "s1", -80, 62_000));
+        assert_eq!(grouper.group_id("old"), None);
+        assert_eq!(grouper.group_count(), 1);
+    }
+
+    #[test]
+    fn test_fixed_addresses_are_never_grouped() {
+        let config = GrouperConfig::default();
+        let mut grouper = DeviceGrouper::new();
+        grouper.observe(&config, sighting("old", "s1", -60, 1_000));
+        grouper.observe(&config, sighting("old", "s2", -60, 1_000));
+        for station in ["s1", "s2"] {
+            let fixed = Sighting {
+                randomized: false,
+                ..sighting("fixed", station, -60, 2_000)
+            };
+            grouper.observe(&config, fixed);
+        }
+        assert_eq!(grouper.group_id("fixed"), Some("fixed"));
+    }
+}
@@ -0,0 +1,165 @@
+//! 802.11 information element parsing.
+//!
+//! Kept apart from the promiscuous callback so it builds for the host and can be unit tested.
+
+/// Vendor-specific information element tag
+pub const IE_VENDOR_SPECIFIC: u8 = 221;
+
+/// Microsoft OUI and vendor type of the WPS information element
+pub const WPS_OUI: [u8; 3] = [0x00, 0x50, 0xF2];
+pub const WPS_VENDOR_TYPE: u8 = 4;
+
+/// Vendor IEs kept per event, further ones are ignored
+pub const MAX_VENDOR_IES: usize = 4;
+
+/// Vendor-specific information element (tag 221) identity
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VendorIe {
+    pub oui: [u8; 3],
+    /// First byte after the OUI, e.g. 0x04 for WPS under Microsoft's 00:50:F2
+    pub vendor_type: u8,
+}
+
+/// Bounded list of the vendor IEs in a frame, in frame order
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VendorIes {
+    entries: [VendorIe; MAX_VENDOR_IES],
+    len: u8,
+}
+
+impl VendorIes {
+    /// Add an IE, returns false once the list is full
+    pub fn push(&mut self, ie: VendorIe) -> bool {
+        let Some(slot) = self.entries.get_mut(self.len as usize) else {
+            return false;
+        };
+        *slot = ie;
+        self.len += 1;
+        true
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &VendorIe> {
+        self.entries[..self.len as usize].iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// Bounds-checked walk over (tag, data) information elements, stops at the first truncated IE
+pub struct InformationElements<'a>(pub &'a [u8]);
+
+impl<'a> Iterator for InformationElements<'a> {
+    type Item = (u8, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let [tag, len, tail @ ..] = self.0 else {
+            return None;
+        };
+        let len = *len as usize;
+        if tail.len() < len {
+            self.0 = &[];
+            return None;
+        }
+        let (data, next) = tail.split_at(len);
+        self.0 = next;
+        Some((*tag, data))
+    }
+}
+
+/// Vendor IEs (OUI and type) of a frame, the first MAX_VENDOR_IES only
+pub fn collect_vendor_ies(ies: &[u8]) -> VendorIes {
+    let mut vendor_ies = VendorIes::default();
+    for (_, data) in InformationElements(ies).filter(|&(tag, _)| tag == IE_VENDOR_SPECIFIC) {
+        let [a, b, c, rest @ ..] = data else {
+            continue;
+        };
+        let ie = VendorIe {
+            oui: [*a, *b, *c],
+            vendor_type: rest.first().copied().unwrap_or(0),
+        };
+        if !vendor_ies.push(ie) {
+            break;
+        }
+    }
+    vendor_ies
+}
+
+/// Whether the frame carries a vendor IE with this OUI and vendor type, e.g. WPS
+/// Unlike `collect_vendor_ies` this looks at every vendor IE, not just the first few
+pub fn has_vendor_ie(ies: &[u8], oui: [u8; 3], vendor_type: u8) -> bool {
+    InformationElements(ies)
+        .filter(|&(tag, _)| tag == IE_VENDOR_SPECIFIC)
+        .any(|(_, data)| data.len() >= 4 && data[..3] == oui && data[3] == vendor_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// SSID "test", supported rates, DS parameter set (channel 6)
+    const COMMON_IES: &[u8] = &[
+        0x00, 0x04, b't', b'e', b's', b't',
+        0x01, 0x08, 0x82, 0x84, 0x8b, 0x96, 0x0c, 0x12, 0x18, 0x24,
+        0x03, 0x01, 0x06,
+    ];
+
+    /// WPA IE: Microsoft OUI, vendor type 1, TKIP group and pairwise cipher, PSK
+    const WPA_IE: &[u8] = &[
+        0xdd, 0x16, 0x00, 0x50, 0xf2, 0x01, 0x01, 0x00,
+        0x00, 0x50, 0xf2, 0x02, 0x01, 0x00, 0x00, 0x50, 0xf2, 0x02,
+        0x01, 0x00, 0x00, 0x50, 0xf2, 0x02,
+    ];
+
+    /// WPS IE: Microsoft OUI, vendor type 4, version 1.0 and Wi-Fi Protected Setup state 2
+    const WPS_IE: &[u8] = &[
+        0xdd, 0x0e, 0x00, 0x50, 0xf2, 0x04,
+        0x10, 0x4a, 0x00, 0x01, 0x10,
+        0x10, 0x44, 0x00, 0x01, 0x02,
+    ];
+
+    fn beacon_ies(vendor_ies: &[&[u8]]) -> Vec<u8> {
+        let mut ies = COMMON_IES.to_vec();
+        for ie in vendor_ies {
+            ies.extend_from_slice(ie);
+        }
+        ies
+    }
+
+    #[test]
+    fn test_wps_beacon() {
+        let ies = beacon_ies(&[WPA_IE, WPS_IE]);
+        assert!(has_vendor_ie(&ies, WPS_OUI, WPS_VENDOR_TYPE));
+
+        let vendor_ies: Vec<VendorIe> = collect_vendor_ies(&ies).iter().copied().collect();
+        assert_eq!(
+            vendor_ies,
+            [
+                VendorIe { oui: WPS_OUI, vendor_type: 1 },
+                VendorIe { oui: WPS_OUI, vendor_type: WPS_VENDOR_TYPE },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_wpa_ie_is_not_wps() {
+        // Same OUI as WPS, but vendor type 1
+        let ies = beacon_ies(&[WPA_IE]);
+        assert!(!has_vendor_ie(&ies, WPS_OUI, WPS_VENDOR_TYPE));
+        assert_eq!(collect_vendor_ies(&ies).iter().count(), 1);
+    }
+
+    #[test]
+    fn test_truncated_ie() {
+        // The WPS IE claims 14 bytes but the capture ends after 6
+        let ies = beacon_ies(&[WPA_IE, &WPS_IE[..8]]);
+        assert!(!has_vendor_ie(&ies, WPS_OUI, WPS_VENDOR_TYPE));
+        assert_eq!(InformationElements(&ies).count(), 4);
+
+        // Nothing is read from a truncated IE, even its OUI
+        let ies = beacon_ies(&[&WPA_IE[..10]]);
+        assert!(collect_vendor_ies(&ies).is_empty());
+        assert_eq!(InformationElements(&ies).count(), 3);
+    }
+}
@@ -0,0 +1,102 @@
+use anyhow::Result;
+use esp_idf_svc::hal::gpio::{AnyOutputPin, Output, PinDriver};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::{mqtt, sniffer, wifi};
+
+/// Default status LED GPIO (onboard LED on most ESP32 devkits), override with LED_GPIO
+const DEFAULT_LED_GPIO: i32 = 2;
+
+/// LED update tick, also the length of the per-event flash
+const TICK: Duration = Duration::from_millis(50);
+
+/// Ticks per half period of the slow (connecting) and fast (error) blink
+const SLOW_BLINK_TICKS: u32 = 10;
+const FAST_BLINK_TICKS: u32 = 2;
+
+/// Ticks between the short blips shown while capture is paused
+const PAUSED_BLIP_TICKS: u32 = 40;
+
+/// Keep showing an error this long after the last one
+const ERROR_HOLD: Duration = Duration::from_secs(5);
+
+/// LED thread stack size (bytes)
+const LED_STACK_SIZE: usize = 3072;
+
+/// What the LED currently shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LedState {
+    /// Slow blink - waiting for WiFi or the MQTT broker
+    Connecting,
+    /// Solid, briefly off for each published event
+    Connected,
+    /// Fast blink - recent MQTT errors
+    Error,
+    /// Off with a short blip every two seconds - capture paused with the button
+    Paused,
+}
+
+impl LedState {
+    fn current(recent_error: bool) -> Self {
+        if recent_error {
+            LedState::Error
+        } else if sniffer::is_paused() {
+            LedState::Paused
+        } else if wifi::is_connected() && mqtt::is_connected() {
+            LedState::Connected
+        } else {
+            LedState::Connecting
+        }
+    }
+}
+
+/// Configured status LED GPIO number
+pub fn gpio() -> i32 {
+    option_env!("LED_GPIO")
+        .and_then(|pin| pin.parse().ok())
+        .unwrap_or(DEFAULT_LED_GPIO)
+}
+
+/// Start the status LED thread on `pin`
+pub fn start(pin: AnyOutputPin) -> Result<()> {
+    let led = PinDriver::output(pin)?;
+    thread::Builder::new()
+        .stack_size(LED_STACK_SIZE)
+        .spawn(move || run(led))?;
+    Ok(())
+}
+
+fn run(mut led: PinDriver<'static, AnyOutputPin, Output>) {
+    let mut tick: u32 = 0;
+    let mut last_published = mqtt::get_published_count();
+    let mut last_errors = mqtt::get_error_count();
+    let mut last_error_at: Option<Instant> = None;
+
+    loop {
+        thread::sleep(TICK);
+        tick = tick.wrapping_add(1);
+
+        let errors = mqtt::get_error_count();
+        if errors != last_errors {
+            last_errors = errors;
+            last_error_at = Some(Instant::now());
+        }
+        let published = mqtt::get_published_count();
+        let flash = published != last_published;
+        last_published = published;
+
+        let recent_error = last_error_at.is_some_and(|at| at.elapsed() < ERROR_HOLD);
+        let on = match LedState::current(recent_error) {
+            LedState::Connecting => (tick / SLOW_BLINK_TICKS) % 2 == 0,
+            LedState::Error => (tick / FAST_BLINK_TICKS) % 2 == 0,
+            LedState::Connected => !flash,
+            LedState::Paused => tick % PAUSED_BLIP_TICKS == 0,
+        };
+
+        let result = if on { led.set_high() } else { led.set_low() };
+        if let Err(e) = result {
+            log::error!("Status LED failed, stopping: {:?}", e);
+            return;
+        }
+    }
+}
@@ -2,5 +2,16 @@
 //!
 //! This library provides shared modules for the ESP32 WiFi sniffer project.
 
+pub mod error;
+pub mod ie;
+pub mod mac;
+pub mod signal;
+
+#[cfg(feature = "events")]
+pub mod event;
+
+#[cfg(feature = "web-gui")]
+pub mod grouping;
+
 #[cfg(feature = "web-gui")]
 pub mod triangulate;
@@ -0,0 +1,132 @@
+use anyhow::{bail, Result};
+use esp_idf_svc::log::EspLogger;
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use log::{LevelFilter, Log, Metadata, Record};
+use std::sync::Mutex;
+use crate::power;
+
+/// Logger behind the log crate, kept here so per-module levels can be changed later
+static LOGGER: EspLogger = EspLogger::new();
+
+/// JSON lines logger, filters with `LOGGER`'s per-module levels
+static JSON_LOGGER: JsonLogger = JsonLogger;
+
+/// NVS namespace and key of the stored level spec
+const NVS_NAMESPACE: &str = "logging";
+const NVS_KEY: &str = "level";
+
+/// Longest level spec stored in NVS
+const MAX_SPEC_LEN: usize = 128;
+
+/// Our own modules log under `<crate>::<module>`, specs name just the module
+const TARGET_PREFIX: &str = "esp32_wifi_sniffer::";
+
+/// Handle to the stored spec, opened once NVS is available
+static STORE: Mutex<Option<EspNvs<NvsDefault>>> = Mutex::new(None);
+
+/// Install the logger at the build-time level (LOG_LEVEL, same syntax as `apply`, default info)
+/// in the build-time format (LOG_FORMAT, `plain` by default or `json`)
+pub fn init() {
+    let format = option_env!("LOG_FORMAT").unwrap_or("plain");
+    let logger: &'static dyn Log = if format == "json" { &JSON_LOGGER } else { &LOGGER };
+    if log::set_logger(logger).is_ok() {
+        LOGGER.initialize();
+    }
+    if !matches!(format, "" | "plain" | "json") {
+        log::error!("Unknown LOG_FORMAT '{}' (plain, json), using plain", format);
+    }
+    let spec = option_env!("LOG_LEVEL").unwrap_or("info");
+    if let Err(e) = apply(spec) {
+        log::error!("Invalid LOG_LEVEL '{}': {:?}", spec, e);
+    }
+}
+
+/// Apply the level spec stored in NVS by an earlier `log` command, overriding LOG_LEVEL
+pub fn load(nvs: EspDefaultNvsPartition) -> Result<()> {
+    let store = EspNvs::new(nvs, NVS_NAMESPACE, true)?;
+    let mut buf = [0u8; MAX_SPEC_LEN + 1];
+    if let Some(spec) = store.get_str(NVS_KEY, &mut buf)? {
+        log::info!("Log level from NVS: {}", spec);
+        apply(spec)?;
+    }
+    if let Ok(mut guard) = STORE.lock() {
+        *guard = Some(store);
+    }
+    Ok(())
+}
+
+/// Apply `spec` and store it in NVS so it survives reboots
+pub fn set(spec: &str) -> Result<()> {
+    if spec.len() > MAX_SPEC_LEN {
+        bail!("Log level spec longer than {} bytes", MAX_SPEC_LEN);
+    }
+    apply(spec)?;
+    if let Ok(mut guard) = STORE.lock() {
+        if let Some(store) = guard.as_mut() {
+            store.set_str(NVS_KEY, spec)?;
+        }
+    }
+    log::info!("Log level set to {}", spec);
+    Ok(())
+}
+
+/// Apply a level spec: a default level and per-module overrides, comma separated,
+/// e.g. `info,sniffer=warn,mqtt=debug`. Either part can be left out.
+fn apply(spec: &str) -> Result<()> {
+    let mut default = None;
+    let mut modules = Vec::new();
+    for part in spec.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+        match part.split_once('=') {
+            Some((module, level)) => modules.push((module.trim(), parse_level(level)?)),
+            None => default = Some(parse_level(part)?),
+        }
+    }
+
+    if let Some(level) = default {
+        LOGGER.set_target_level("*", level)?;
+    }
+    for &(module, level) in &modules {
+        LOGGER.set_target_level(format!("{}{}", TARGET_PREFIX, module), level)?;
+    }
+
+    // The log crate filters before the logger sees a record, let the most verbose level through
+    let base = default.unwrap_or_else(log::max_level);
+    let max = modules.iter().map(|&(_, level)| level).fold(base, Ord::max);
+    log::set_max_level(max);
+    Ok(())
+}
+
+/// One JSON object per line: `{"ts":<ms>,"level":"INFO","module":"mqtt","msg":"..."}`
+///
+/// `ts` is milliseconds from the system clock, Unix time once it has been set and time
+/// since boot before that. Only Rust logs are formatted, ESP-IDF's own stay plain.
+struct JsonLogger;
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        LOGGER.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let target = record.target();
+        let line = serde_json::json!({
+            "ts": power::timestamp_us() / 1000,
+            "level": record.level().as_str(),
+            "module": target.strip_prefix(TARGET_PREFIX).unwrap_or(target),
+            "msg": record.args().to_string(),
+        });
+        println!("{}", line);
+    }
+
+    fn flush(&self) {}
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter> {
+    match level.trim().parse() {
+        Ok(level) => Ok(level),
+        Err(_) => bail!("Unknown log level '{}' (off, error, warn, info, debug, trace)", level.trim()),
+    }
+}
@@ -0,0 +1,176 @@
+//! MAC address parsing and classification.
+//!
+//! Kept apart from the promiscuous callback so it builds for the host and can be unit tested.
+
+use crate::error::SnifferError;
+use sha2::{Digest, Sha256};
+
+/// MAC address wrapper for display
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MacAddress(pub [u8; 6]);
+
+impl MacAddress {
+    /// Check if this is a broadcast address (FF:FF:FF:FF:FF:FF)
+    pub fn is_broadcast(&self) -> bool {
+        self.0 == [0xFF; 6]
+    }
+
+    /// Check if this is a multicast address (first byte has LSB set)
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 0x01 != 0
+    }
+
+    /// Check if this is a locally administered (randomized) address (first byte has bit 1 set)
+    pub fn is_randomized(&self) -> bool {
+        self.0[0] & 0x02 != 0
+    }
+
+    /// Hash the MAC address using SHA-256, hex-encoded by whoever publishes it
+    pub fn hash(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(self.0);
+        let result = hasher.finalize();
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(&result);
+        hash
+    }
+
+    /// Parse 12 hex digits without separators, e.g. `aabbccddeeff` (either case)
+    pub fn from_hex(hex: &str) -> Result<Self, SnifferError> {
+        if hex.len() != 12 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(invalid_mac(hex));
+        }
+        let mut mac = [0u8; 6];
+        for (i, byte) in mac.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| invalid_mac(hex))?;
+        }
+        Ok(MacAddress(mac))
+    }
+}
+
+impl core::str::FromStr for MacAddress {
+    type Err = SnifferError;
+
+    /// Parse `AA:BB:CC:DD:EE:FF` or `aa-bb-cc-dd-ee-ff`, the `Display` form round-trips
+    /// Separators can't be mixed and every octet needs both digits
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let separator = match s.as_bytes().get(2) {
+            Some(b':') => ':',
+            Some(b'-') => '-',
+            _ => return Err(invalid_mac(s)),
+        };
+        let mut hex = String::with_capacity(12);
+        let mut octets = 0;
+        for octet in s.split(separator) {
+            if octet.len() != 2 {
+                return Err(invalid_mac(s));
+            }
+            hex.push_str(octet);
+            octets += 1;
+        }
+        if octets != 6 {
+            return Err(invalid_mac(s));
+        }
+        MacAddress::from_hex(&hex).map_err(|_| invalid_mac(s))
+    }
+}
+
+fn invalid_mac(input: &str) -> SnifferError {
+    SnifferError::Parse(format!(
+        "invalid MAC address '{}', expected AA:BB:CC:DD:EE:FF or AA-BB-CC-DD-EE-FF",
+        input
+    ))
+}
+
+impl core::fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X}",
+            self.0[0], self.0[1], self.0[2], self.0[3], self.0[4], self.0[5]
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_broadcast() {
+        let mac = MacAddress([0xFF; 6]);
+        assert!(mac.is_broadcast());
+        // Broadcast is a multicast group with the local bit set as well
+        assert!(mac.is_multicast());
+        assert!(!MacAddress([0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFE]).is_broadcast());
+    }
+
+    #[test]
+    fn test_multicast() {
+        let mac = MacAddress([0x01, 0x00, 0x5E, 0x00, 0x00, 0xFB]);
+        assert!(mac.is_multicast());
+        assert!(!mac.is_broadcast());
+        assert!(!mac.is_randomized());
+    }
+
+    #[test]
+    fn test_randomized() {
+        let mac = MacAddress([0xDA, 0xA1, 0x19, 0x00, 0x00, 0x01]);
+        assert!(mac.is_randomized());
+        assert!(!mac.is_multicast());
+    }
+
+    #[test]
+    fn test_unicast() {
+        let mac = MacAddress([0x00, 0x1A, 0x2B, 0x3C, 0x4D, 0x5E]);
+        assert!(!mac.is_broadcast());
+        assert!(!mac.is_multicast());
+        assert!(!mac.is_randomized());
+    }
+
+    #[test]
+    fn test_parse_separators_and_case() {
+        let expected = MacAddress([0xAA, 0xBB, 0xCC, 0x01, 0x02, 0x03]);
+        assert_eq!("AA:BB:CC:01:02:03".parse::<MacAddress>(), Ok(expected));
+        assert_eq!("aa-bb-cc-01-02-03".parse::<MacAddress>(), Ok(expected));
+        assert_eq!(MacAddress::from_hex("aabbCC010203"), Ok(expected));
+    }
+
+    #[test]
+    fn test_display_round_trips() {
+        let mac = MacAddress([0x00, 0x1A, 0x2B, 0x3C, 0x4D, 0x5E]);
+        assert_eq!(mac.to_string(), "00:1A:2B:3C:4D:5E");
+        assert_eq!(mac.to_string().parse::<MacAddress>(), Ok(mac));
+    }
+
+    #[test]
+    fn test_malformed_input() {
+        for input in [
+            "",
+            "AA:BB:CC:DD:EE",
+            "AA:BB:CC:DD:EE:FF:00",
+            "AA:BB-CC:DD:EE:FF",
+            "A:BB:CC:DD:EE:FFF",
+            "GG:BB:CC:DD:EE:FF",
+            "AA BB CC DD EE FF",
+            "+A:BB:CC:DD:EE:FF",
+        ] {
+            assert!(
+                matches!(input.parse::<MacAddress>(), Err(SnifferError::Parse(_))),
+                "{:?} should not parse",
+                input
+            );
+        }
+        assert!(MacAddress::from_hex("aabbccddeef").is_err());
+        assert!(MacAddress::from_hex("aa:bbccddeef").is_err());
+        assert!(MacAddress::from_hex("aabbccddeeffé").is_err());
+    }
+
+    #[test]
+    fn test_hash_is_stable_and_distinct() {
+        let a = MacAddress([0x00, 0x1A, 0x2B, 0x3C, 0x4D, 0x5E]);
+        let b = MacAddress([0x00, 0x1A, 0x2B, 0x3C, 0x4D, 0x5F]);
+        assert_eq!(a.hash(), a.hash());
+        assert_ne!(a.hash(), b.hash());
+    }
+}
@@ -1,17 +1,51 @@
+mod button;
+mod credentials;
+mod led;
+mod logging;
+mod memory;
 mod mqtt;
+mod ota;
+#[cfg(feature = "pcap")]
+mod pcap;
+mod power;
 mod sniffer;
+mod watchdog;
 mod wifi;
 
 use esp_idf_svc::{
     eventloop::EspSystemEventLoop,
-    hal::peripherals::Peripherals,
+    hal::{
+        cpu::{self, Core},
+        gpio::{AnyIOPin, AnyOutputPin},
+        peripherals::Peripherals,
+        task::thread::ThreadSpawnConfiguration,
+    },
     nvs::EspDefaultNvsPartition,
 };
+use mqtt::{Build, Command, Stats, Status};
+use std::sync::mpsc::{self, RecvTimeoutError};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-/// Station identifier (from environment)
-const STATION_ID: &str = env!("STATION_ID");
+
+/// Firmware build, from build.rs
+const BUILD: Build = Build {
+    version: env!("CARGO_PKG_VERSION"),
+    git_hash: env!("GIT_HASH"),
+    build_time: env!("BUILD_TIME"),
+};
+
+/// Default interval between statistics reports, override with STATS_INTERVAL_SECS
+const DEFAULT_STATS_INTERVAL_SECS: u64 = 10;
+
+/// Default unique device counting window, override with UNIQUE_WINDOW_SECS
+const DEFAULT_UNIQUE_WINDOW_SECS: u64 = 300;
+
+/// How often the WiFi link is checked, and a lost one reconnected
+const WIFI_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Time after a reconnect by which new frames should have been captured
+const RESUME_CHECK_DELAY: Duration = Duration::from_secs(10);
 
 fn main() -> anyhow::Result<()> {
     // It is necessary to call this function once. Otherwise, some patches to the runtime
@@ -19,18 +53,55 @@ fn main() -> anyhow::Result<()> {
     esp_idf_svc::sys::link_patches();
 
     // Bind the log crate to the ESP Logging facilities
-    esp_idf_svc::log::EspLogger::initialize_default();
+    logging::init();
 
     log::info!("=== ESP32 WiFi Sniffer ===");
-    log::info!("Station ID: {}", STATION_ID);
+    log::info!(
+        "Firmware {} ({}), built at {} (unix)",
+        BUILD.version,
+        BUILD.git_hash,
+        BUILD.build_time
+    );
+    let station_id = station_id();
+    log::info!("Station ID: {}", station_id);
+    watchdog::log_reset_reason();
+    if let Err(e) = watchdog::init() {
+        log::error!("{:?}", e);
+    }
 
     // Initialize hardware peripherals
     let peripherals = Peripherals::take()?;
     let sys_loop = EspSystemEventLoop::take()?;
     let nvs = EspDefaultNvsPartition::take()?;
+    if let Err(e) = logging::load(nvs.clone()) {
+        log::error!("Failed to load the stored log level: {:?}", e);
+    }
+
+    // Credentials live in NVS, seeded from the build on first boot and changed over serial
+    let credentials = credentials::load(nvs.clone())?;
+    if let Err(e) = credentials::start_console() {
+        log::error!("Failed to start the serial console: {:?}", e);
+    }
+    if !credentials.is_complete() {
+        log::error!(
+            "No WiFi SSID or MQTT broker configured. Provision over serial with \
+             `set <key> <value>` (wifi_ssid, wifi_pass, mqtt_broker, mqtt_user, mqtt_pass), \
+             then `restart`"
+        );
+        loop {
+            thread::sleep(Duration::from_secs(1));
+        }
+    }
+
+    // Status LED, blinks slowly until WiFi and MQTT are up
+    // SAFETY: no other driver uses this pin, the rest of `peripherals.pins` stays untouched
+    let led_pin = unsafe { AnyOutputPin::new(led::gpio()) };
+    if let Err(e) = led::start(led_pin) {
+        log::error!("Failed to start status LED on GPIO{}: {:?}", led::gpio(), e);
+    }
 
     // Connect to WiFi network (needed for MQTT)
-    let _wifi = wifi::initialize_wifi_connected(peripherals.modem, sys_loop, nvs)?;
+    let mut wifi = wifi::initialize_wifi_connected(peripherals.modem, sys_loop, nvs)?;
 
     // Create event channel for sniffer -> MQTT communication
     let (tx, rx) = mqtt::create_event_channel();
@@ -38,14 +109,31 @@ fn main() -> anyhow::Result<()> {
     // Set the event sender in the sniffer module
     sniffer::set_event_sender(tx);
 
-    // Start MQTT publisher in a separate thread
-    let station_id = STATION_ID.to_string();
-    thread::spawn(move || {
-        match mqtt::MqttPublisher::new(&station_id, rx) {
+    // Status reports go out through the publisher, commands come back from it
+    let (status_tx, status_rx) = mqtt::create_status_channel();
+    let (command_tx, command_rx) = mpsc::channel();
+    let _ = status_tx.try_send(Status::Boot(BUILD));
+
+    // Start MQTT publisher in a separate thread, pinned to core 1 so its TLS work
+    // doesn't compete with the WiFi driver task (and the sniffer callback) on core 0
+    ThreadSpawnConfiguration {
+        name: Some(b"mqtt-publisher\0"),
+        pin_to_core: Some(Core::Core1),
+        ..Default::default()
+    }
+    .set()?;
+    let publisher = thread::spawn(move || {
+        log::info!("MQTT publisher running on {:?}", cpu::core());
+        match mqtt::MqttPublisher::new(&station_id, rx, status_rx, command_tx) {
             Ok(mut publisher) => {
+                if let Err(e) = watchdog::subscribe() {
+                    log::error!("MQTT publisher not watched: {:?}", e);
+                }
                 if let Err(e) = publisher.run() {
                     log::error!("MQTT publisher error: {:?}", e);
                 }
+                // An exited task that is still subscribed would trip the watchdog
+                watchdog::unsubscribe();
             }
             Err(e) => {
                 log::error!("Failed to create MQTT publisher: {:?}", e);
@@ -53,20 +141,279 @@ fn main() -> anyhow::Result<()> {
         }
     });
 
+    // Threads spawned from here on use the default configuration again
+    ThreadSpawnConfiguration::default().set()?;
+
     // Give MQTT a moment to connect
     thread::sleep(Duration::from_secs(1));
 
+    skip_own_bssid();
+
+    // Aggregate each device's readings per window instead of sampling packets
+    if let Some(window) = sniffer::aggregate_window() {
+        if let Err(e) = sniffer::start_aggregation(window, sniffer::Aggregation::configured()) {
+            log::error!("Failed to start RSSI aggregation, sampling instead: {:?}", e);
+        }
+    }
+
+    // Raw frame dump on the serial console, logging stops here
+    #[cfg(feature = "pcap")]
+    pcap::start()?;
+
     // Start promiscuous mode sniffer (uses AP's channel when connected)
-    sniffer::start_sniffer()?;
+    let sniffer_config = sniffer::SnifferConfig::from_env();
+    if option_env!("PROBES_ONLY") == Some("true") {
+        sniffer::start_probe_sniffer(&sniffer_config)?;
+    } else {
+        sniffer::start_sniffer(&sniffer_config)?;
+    }
 
     log::info!("Sniffer running. Publishing to MQTT...");
 
-    // Main loop - report statistics periodically
-    loop {
-        thread::sleep(Duration::from_secs(10));
-        let count = sniffer::get_packet_count();
-        let sent = sniffer::get_sent_count();
-        let dropped = sniffer::get_dropped_count();
-        log::info!("Packets: {} captured, {} sent to MQTT, {} dropped", count, sent, dropped);
+    // Pause/resume button, started after the sniffer so a press can't enable capture early
+    // SAFETY: as with the LED pin, nothing else drives this GPIO
+    let button_pin = unsafe { AnyIOPin::new(button::gpio()) };
+    if let Err(e) = button::start(button_pin) {
+        log::error!("Failed to start pause button on GPIO{}: {:?}", button::gpio(), e);
+    }
+
+    // Startup succeeded, keep this firmware (a new OTA image is rolled back otherwise)
+    if let Err(e) = ota::mark_boot_valid() {
+        log::error!("Failed to mark firmware valid: {:?}", e);
+    }
+
+    // Watch the main loop, it wakes at least once a second
+    if let Err(e) = watchdog::subscribe() {
+        log::error!("Main loop not watched: {:?}", e);
+    }
+
+    let duty_cycle = power::duty_cycle();
+    if let Some(duty) = duty_cycle {
+        log::info!(
+            "Duty cycling: {}s active, {}s deep sleep",
+            duty.active.as_secs(),
+            duty.sleep.as_secs()
+        );
+    }
+
+    // Main loop - report statistics periodically until a reset, update or sleep is due
+    // Breaks with how long to deep-sleep, `None` to restart
+    let stats_interval = Duration::from_secs(
+        option_env!("STATS_INTERVAL_SECS")
+            .and_then(|secs| secs.parse().ok())
+            .filter(|&secs| secs > 0)
+            .unwrap_or(DEFAULT_STATS_INTERVAL_SECS),
+    );
+    let unique_window = Duration::from_secs(
+        option_env!("UNIQUE_WINDOW_SECS")
+            .and_then(|secs| secs.parse().ok())
+            .filter(|&secs| secs > 0)
+            .unwrap_or(DEFAULT_UNIQUE_WINDOW_SECS),
+    );
+    let started = Instant::now();
+    let mut last_stats = Instant::now();
+    let mut unique_window_start = Instant::now();
+    let mut last_wifi_check = Instant::now();
+    // Frame count and time of the last reconnect, until capture is seen to resume
+    let mut resume_check: Option<(u32, Instant)> = None;
+    let sleep = loop {
+        watchdog::feed();
+
+        // Reconnect a lost link and bring capture back, the driver may have dropped promiscuous mode
+        if last_wifi_check.elapsed() >= WIFI_CHECK_INTERVAL {
+            last_wifi_check = Instant::now();
+            match wifi::ensure_connected(&mut wifi) {
+                Ok(true) => {
+                    // Sent now that the link is back, the status topic keeps the last one
+                    if let Some(reason) = wifi::last_disconnect_reason() {
+                        let description = wifi::describe_disconnect_reason(reason);
+                        let _ = status_tx.try_send(Status::WifiReconnected { reason, description });
+                    }
+                    if let Err(e) = sniffer::restore_sniffer() {
+                        log::error!("Failed to restore the sniffer after reconnecting: {:?}", e);
+                    }
+                    skip_own_bssid();
+                    resume_check = Some((sniffer::get_packet_count(), Instant::now()));
+                }
+                Ok(false) => {}
+                Err(e) => log::error!("WiFi reconnect failed, retrying: {:?}", e),
+            }
+            watchdog::feed();
+        }
+        let resume_due = resume_check.filter(|(_, since)| since.elapsed() >= RESUME_CHECK_DELAY);
+        if let Some((count, since)) = resume_due {
+            resume_check = None;
+            let captured = sniffer::get_packet_count().wrapping_sub(count);
+            if captured > 0 {
+                log::info!(
+                    "Capture resumed after reconnect: {} frames in {:?}",
+                    captured,
+                    since.elapsed()
+                );
+            } else if !sniffer::is_paused() {
+                log::warn!("No frames captured since the WiFi reconnect, capture may be stuck");
+            }
+        }
+
+        sniffer::sweep_departures();
+        sniffer::flush_events();
+        match command_rx.recv_timeout(Duration::from_secs(1)) {
+            Ok(Command::Reset) => {
+                log::info!("Reset requested, shutting down");
+                break None;
+            }
+            Ok(Command::Ota { url }) => {
+                let report = |status| {
+                    let _ = status_tx.try_send(status);
+                };
+                match ota::update(&url, &report) {
+                    Ok(()) => {
+                        report(Status::OtaSuccess);
+                        log::info!("OTA update installed, shutting down");
+                        break None;
+                    }
+                    Err(e) => {
+                        log::error!("OTA update failed: {:?}", e);
+                        report(Status::OtaFailed(e.to_string()));
+                    }
+                }
+            }
+            Ok(Command::LogLevel { spec }) => {
+                if let Err(e) = logging::set(&spec) {
+                    log::error!("Failed to set log level '{}': {:?}", spec, e);
+                }
+            }
+            Ok(Command::SendRate { kind: Some(kind), rate }) => {
+                sniffer::set_send_rate(kind, (rate > 0).then_some(rate));
+            }
+            Ok(Command::SendRate { kind: None, rate }) => sniffer::set_default_send_rate(rate),
+            Ok(Command::ResetCounters) => {
+                let cleared = sniffer::reset_all_counters();
+                log::info!(
+                    "Counters reset, cleared {} captured ({} retries), {} sent, {} dropped",
+                    cleared.captured,
+                    cleared.retries,
+                    cleared.sent,
+                    cleared.dropped
+                );
+            }
+            Ok(Command::Disconnect) => {
+                // The periodic check reconnects, exercising the same path as a real drop
+                log::warn!("Dropping the WiFi connection on request");
+                if let Err(e) = wifi.disconnect() {
+                    log::error!("Failed to disconnect WiFi: {:?}", e);
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            // Publisher is gone, no more commands can arrive
+            Err(RecvTimeoutError::Disconnected) => thread::sleep(Duration::from_secs(1)),
+        }
+
+        // Log and publish statistics and memory usage periodically
+        if last_stats.elapsed() >= stats_interval {
+            last_stats = Instant::now();
+            let snapshot = sniffer::stats_snapshot();
+            let stats = Stats {
+                captured: snapshot.captured,
+                sent: snapshot.sent,
+                dropped: snapshot.dropped,
+                retries: snapshot.retries,
+                channel_utilization: snapshot.channel_utilization.round() as u8,
+                uptime_secs: started.elapsed().as_secs(),
+                unique_devices: sniffer::estimated_unique_devices(),
+                outbox_pending: mqtt::outbox_pending(),
+            };
+            log::info!(
+                "Packets: {} captured ({} retries), {} sent to MQTT, {} dropped, ~{} unique devices, channel {}% busy",
+                stats.captured,
+                stats.retries,
+                stats.sent,
+                stats.dropped,
+                stats.unique_devices,
+                stats.channel_utilization
+            );
+            log::info!(
+                "Rates: {:.1} captured/s, {:.1} sent/s, {:.1} dropped/s, {} messages awaiting broker ack",
+                snapshot.captured_per_sec, snapshot.sent_per_sec, snapshot.dropped_per_sec,
+                stats.outbox_pending
+            );
+            let _ = status_tx.try_send(Status::Stats(stats));
+
+            let types = snapshot.types;
+            log::info!(
+                "Frame types: {} management, {} data, {} control",
+                types.mgmt, types.data, types.ctrl
+            );
+
+            // The last report of a window carries its final count
+            if unique_window_start.elapsed() >= unique_window {
+                unique_window_start = Instant::now();
+                sniffer::reset_unique_devices();
+            }
+
+            let memory = memory::snapshot();
+            memory::log(&memory);
+            let _ = status_tx.try_send(Status::Memory(memory));
+
+            if sniffer::beacon_stats_enabled() {
+                let _ = status_tx.try_send(Status::Beacons(sniffer::beacon_stats()));
+            }
+            if sniffer::arrival_stats_enabled() {
+                let _ = status_tx.try_send(Status::Arrivals(sniffer::arrival_stats()));
+            }
+        }
+
+        if let Some(duty) = duty_cycle.filter(|duty| started.elapsed() >= duty.active) {
+            log::info!("Active window over, shutting down");
+            break Some(duty.sleep);
+        }
+    };
+
+    // Unregister the callback before anything else is torn down
+    if let Err(e) = sniffer::stop_sniffer() {
+        log::error!("Failed to stop sniffer: {:?}", e);
+    }
+
+    // The publisher returns once it has flushed the events and status left in its channels
+    if publisher.join().is_err() {
+        log::error!("MQTT publisher thread panicked");
+    }
+
+    // Disconnect cleanly, the next boot connects again from scratch
+    drop(wifi);
+
+    match sleep {
+        Some(duration) => power::deep_sleep(duration),
+        None => {
+            log::info!("Restarting");
+            unsafe { esp_idf_svc::sys::esp_restart() }
+        }
+    }
+}
+
+/// Skip our uplink AP's traffic, it isn't interesting for tracking (SKIP_OWN_BSSID=false keeps it)
+fn skip_own_bssid() {
+    if option_env!("SKIP_OWN_BSSID") != Some("false") {
+        match wifi::connected_bssid() {
+            Some(bssid) => sniffer::set_own_bssid(Some(bssid)),
+            None => log::warn!("Could not read the connected BSSID, capturing its traffic"),
+        }
+    }
+}
+
+/// Station identifier from STATION_ID at build time, or derived from the factory MAC
+/// (`station-<last 3 octets>`) so boards flashed with the same image still get distinct ids
+fn station_id() -> String {
+    if let Some(id) = option_env!("STATION_ID").filter(|id| !id.is_empty()) {
+        return id.to_string();
+    }
+
+    let mut mac = [0u8; 6];
+    let ret = unsafe { esp_idf_svc::sys::esp_efuse_mac_get_default(mac.as_mut_ptr()) };
+    if ret != esp_idf_svc::sys::ESP_OK {
+        log::error!("Failed to read factory MAC: {}", ret);
     }
+    let id = format!("station-{:02x}{:02x}{:02x}", mac[3], mac[4], mac[5]);
+    log::info!("STATION_ID not set, derived {} from the factory MAC", id);
+    id
 }
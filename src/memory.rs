@@ -0,0 +1,53 @@
+use esp_idf_svc::sys::{
+    esp_get_free_heap_size,
+    esp_get_minimum_free_heap_size,
+    heap_caps_get_largest_free_block,
+    uxTaskGetStackHighWaterMark,
+    MALLOC_CAP_DEFAULT,
+};
+use crate::mqtt::Memory;
+
+/// Default free heap (bytes) below which a warning is logged, override with HEAP_ALERT_BYTES
+const DEFAULT_HEAP_ALERT_BYTES: u32 = 20 * 1024;
+
+/// Configured low heap threshold in bytes
+pub fn alert_threshold() -> u32 {
+    option_env!("HEAP_ALERT_BYTES")
+        .and_then(|bytes| bytes.parse().ok())
+        .unwrap_or(DEFAULT_HEAP_ALERT_BYTES)
+}
+
+/// Current heap usage and the calling task's stack high-water mark
+/// Call from the main task, the stack figure is for whichever task calls it
+pub fn snapshot() -> Memory {
+    let free_heap = unsafe { esp_get_free_heap_size() };
+    Memory {
+        free_heap,
+        min_free_heap: unsafe { esp_get_minimum_free_heap_size() },
+        largest_free_block: unsafe { heap_caps_get_largest_free_block(MALLOC_CAP_DEFAULT) } as u32,
+        // On ESP-IDF stack sizes are in bytes
+        main_stack_high_water: unsafe { uxTaskGetStackHighWaterMark(core::ptr::null_mut()) },
+        low: free_heap < alert_threshold(),
+    }
+}
+
+/// Log a memory snapshot, as a warning when the heap is running low
+pub fn log(memory: &Memory) {
+    if memory.low {
+        log::warn!(
+            "Low heap: {} bytes free (alert below {}), min {}, largest block {}",
+            memory.free_heap,
+            alert_threshold(),
+            memory.min_free_heap,
+            memory.largest_free_block
+        );
+    } else {
+        log::info!(
+            "Heap: {} bytes free, min {}, largest block {}, main stack high water {}",
+            memory.free_heap,
+            memory.min_free_heap,
+            memory.largest_free_block,
+            memory.main_stack_high_water
+        );
+    }
+}
@@ -1,24 +1,104 @@
 use anyhow::Result;
+use esp32_wifi_sniffer::event::{GeoLocation, JoinTarget, MqttDeviceEvent};
+use esp32_wifi_sniffer::ie::VendorIes;
+use esp32_wifi_sniffer::signal::rssi_to_quality;
 use esp_idf_svc::mqtt::client::{EspMqttClient, EventPayload, MqttClientConfiguration, QoS};
+#[cfg(not(feature = "no-tls"))]
 use esp_idf_svc::tls::X509;
 use log::{error, info};
-use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::thread;
 use std::time::Duration;
+use crate::credentials;
+use crate::sniffer::FrameKind;
+
+// Frame details are part of the wire format shared with the dashboard
+pub use esp32_wifi_sniffer::event::{AccessCategory, Bandwidth, JoinKind, Presence};
 
 /// MQTT topic prefix
 const MQTT_TOPIC_PREFIX: &str = "sniffer";
 
-/// MQTT broker configuration (mqtts://host:8883 for TLS)
-const MQTT_BROKER: &str = env!("MQTT_BROKER");
-const MQTT_USERNAME: &str = env!("MQTT_USERNAME");
-const MQTT_PASSWORD: &str = env!("MQTT_PASSWORD");
-
 /// CA certificate for TLS verification (embedded at compile time)
 /// The certificate must be null-terminated for esp-idf
-const CA_CERT: &[u8] = concat!(include_str!("../certs/ca.crt"), "\0").as_bytes();
+#[cfg(not(feature = "no-tls"))]
+pub const CA_CERT: &[u8] = concat!(include_str!("../certs/ca.crt"), "\0").as_bytes();
+
+/// Transport named in the logs
+const TRANSPORT: &str = if cfg!(feature = "no-tls") { "plaintext" } else { "TLS" };
+
+/// Bounded channel capacity in batches - prevents memory exhaustion
+const CHANNEL_CAPACITY: usize = 4;
+
+/// Events the sniffer collects before handing them to the publisher as one message
+pub const EVENT_BATCH_SIZE: usize = 8;
+
+/// Status channel capacity - status messages are rare, extras are dropped
+const STATUS_CHANNEL_CAPACITY: usize = 8;
+
+/// Time given to the MQTT client to send queued messages before shutting down
+const FLUSH_DELAY: Duration = Duration::from_millis(500);
+
+/// Hex digits of the MAC hash in per-device topics, bounds them to 16^4 topics per station
+const DEVICE_TOPIC_PREFIX_LEN: usize = 4;
+
+/// Where device events are published
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceTopics {
+    /// `sniffer/<station>/device` only
+    Shared,
+    /// `sniffer/<station>/device/<mac_hash_prefix>` only
+    PerDevice,
+    /// Both, each event is published twice
+    Both,
+}
+
+impl DeviceTopics {
+    /// Topics from DEVICE_TOPICS (`shared`, `device` or `both`), defaults to shared
+    pub fn configured() -> Self {
+        match option_env!("DEVICE_TOPICS") {
+            Some("device") => DeviceTopics::PerDevice,
+            Some("both") => DeviceTopics::Both,
+            _ => DeviceTopics::Shared,
+        }
+    }
+}
+
+/// Station location from STATION_LOCATION (`lat,lon`), published with every event
+fn station_location() -> Option<GeoLocation> {
+    let text = option_env!("STATION_LOCATION").filter(|text| !text.is_empty())?;
+    let location = GeoLocation::parse(text);
+    if location.is_none() {
+        log::warn!("Ignoring invalid STATION_LOCATION '{}', expected lat,lon", text);
+    }
+    location
+}
+
+/// Broker connection state, set from the client callback
+static CONNECTED: AtomicBool = AtomicBool::new(false);
+/// Event messages handed to the MQTT client (two per event when publishing to both topics)
+static PUBLISHED_COUNT: AtomicU32 = AtomicU32::new(0);
+/// Client errors and failed enqueues
+static ERROR_COUNT: AtomicU32 = AtomicU32::new(0);
+/// QoS 1 messages enqueued and not yet acked by the broker (or expired from the outbox)
+static OUTBOX_PENDING: AtomicU32 = AtomicU32::new(0);
+
+/// A device joining an AP, from its (re)association request or authentication frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Join {
+    pub kind: JoinKind,
+    /// BSSID of the AP being joined
+    pub bssid: [u8; 6],
+}
 
-/// Bounded channel capacity - prevents memory exhaustion
-const CHANNEL_CAPACITY: usize = 32;
+/// Probe request fingerprint, stable for a device model/OS across MAC randomization
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fingerprint {
+    /// Hash of the ordered information element tags (and a few stable IE contents)
+    pub id: u32,
+    /// Heuristic device class, e.g. "apple"
+    pub device_class: Option<&'static str>,
+}
 
 /// Device detection event to publish (fixed size, no heap allocation)
 /// MAC address is stored as a SHA-256 hash for privacy
@@ -28,79 +108,443 @@ pub struct DeviceEvent {
     pub rssi: i8,
     pub channel: u8,
     pub timestamp: u64,
+    /// Source MAC is locally administered (likely randomized)
+    pub randomized: bool,
+    pub bandwidth: Bandwidth,
+    /// Set for QoS data frames
+    pub access_category: Option<AccessCategory>,
+    /// Set for probe requests
+    pub fingerprint: Option<Fingerprint>,
+    /// Vendor IEs of probe requests, probe responses and beacons
+    pub vendor_ies: VendorIes,
+    /// Set for association, reassociation and authentication frames sent by the device
+    pub join: Option<Join>,
+    /// Set in first-seen mode, which publishes only arrivals and departures
+    pub presence: Option<Presence>,
+    /// Frames behind this reading, 1 unless aggregating
+    pub frames: u32,
+    /// How many of them were retransmissions (retry bit set)
+    pub retries: u32,
+}
+
+/// Up to `EVENT_BATCH_SIZE` device events sent through the channel as one message
+/// (fixed size, no heap allocation)
+#[derive(Debug, Clone)]
+pub struct EventBatch {
+    events: [Option<DeviceEvent>; EVENT_BATCH_SIZE],
+    len: usize,
+}
+
+impl EventBatch {
+    pub const fn new() -> Self {
+        Self { events: [None; EVENT_BATCH_SIZE], len: 0 }
+    }
+
+    /// Add an event, returns false when the batch is already full
+    pub fn push(&mut self, event: DeviceEvent) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        self.events[self.len] = Some(event);
+        self.len += 1;
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == EVENT_BATCH_SIZE
+    }
+
+    /// Drop all events, in place
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Events in the order they were pushed
+    pub fn iter(&self) -> impl Iterator<Item = &DeviceEvent> {
+        self.events[..self.len].iter().flatten()
+    }
+}
+
+impl Default for EventBatch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Command received on `sniffer/<station>/cmd`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// `reset` - stop the sniffer, flush MQTT and reboot
+    Reset,
+    /// `ota <url>` - download firmware from an HTTPS URL, install it and reboot
+    Ota { url: String },
+    /// `log <spec>` - set the log level, e.g. `info,sniffer=warn`, kept across reboots
+    LogLevel { spec: String },
+    /// `rate <kind> <n>` - send 1 in every n frames of a kind, `default` for the other kinds,
+    /// n = 0 puts a kind back on the default rate
+    SendRate { kind: Option<FrameKind>, rate: u32 },
+    /// `disconnect` - drop the WiFi connection to check that the station reconnects
+    Disconnect,
+    /// `reset-counters` - zero the capture counters without rebooting
+    ResetCounters,
+}
+
+impl Command {
+    /// Parse a command payload, `None` if it isn't a known command
+    pub fn parse(payload: &[u8]) -> Option<Self> {
+        let text = core::str::from_utf8(payload).ok()?.trim();
+        let (name, arg) = text.split_once(' ').unwrap_or((text, ""));
+        match (name, arg.trim()) {
+            ("reset", "") => Some(Command::Reset),
+            ("disconnect", "") => Some(Command::Disconnect),
+            ("reset-counters", "") => Some(Command::ResetCounters),
+            ("ota", url) if url.starts_with("https://") => Some(Command::Ota { url: url.to_string() }),
+            ("log", spec) if !spec.is_empty() => Some(Command::LogLevel { spec: spec.to_string() }),
+            ("rate", arg) => {
+                let (kind, rate) = arg.split_once(' ')?;
+                let rate = rate.trim().parse().ok()?;
+                match kind {
+                    "default" if rate > 0 => Some(Command::SendRate { kind: None, rate }),
+                    "default" => None,
+                    kind => Some(Command::SendRate { kind: Some(FrameKind::from_name(kind)?), rate }),
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Capture counters published on `sniffer/<station>/stats`
+/// Counts are cumulative since boot or the last `reset-counters`, a lower value than before
+/// means the station restarted or was reset
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    pub captured: u32,
+    pub sent: u32,
+    pub dropped: u32,
+    /// Captured frames that were retransmissions
+    pub retries: u32,
+    /// Estimated channel busy percentage since the previous report
+    pub channel_utilization: u8,
+    pub uptime_secs: u64,
+    /// Estimated distinct source MACs in the current unique device window
+    pub unique_devices: u64,
+    /// QoS 1 messages enqueued but not yet acked by the broker
+    pub outbox_pending: u32,
+}
+
+/// Heap and stack usage, published on `sniffer/<station>/status`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Memory {
+    pub free_heap: u32,
+    /// Lowest free heap since boot
+    pub min_free_heap: u32,
+    /// Largest allocatable block, low values mean fragmentation
+    pub largest_free_block: u32,
+    /// Least free stack the main task has had, in bytes
+    pub main_stack_high_water: u32,
+    /// Free heap is below the alert threshold
+    pub low: bool,
+}
+
+/// Firmware build, published once at boot on `sniffer/<station>/status`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Build {
+    pub version: &'static str,
+    pub git_hash: &'static str,
+    /// Unix seconds
+    pub build_time: &'static str,
+}
+
+/// Beacons heard from one AP (or a probe response, then `beacons` can be 0), published on `sniffer/<station>/beacons`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ApBeacons {
+    pub bssid: [u8; 6],
+    /// Beacons since boot (or since the AP entered the table)
+    pub beacons: u32,
+    /// Timestamp of the latest beacon, same clock as device events
+    pub last_seen: u64,
+    /// RSSI of the latest beacon
+    pub rssi: i8,
+    /// The latest beacon or probe response advertised WPS
+    pub wps_enabled: bool,
+}
+
+/// Inter-arrival summary of one device, published on `sniffer/<station>/arrivals`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceArrivals {
+    /// Same hash as in device events, hex-encoded when published
+    pub mac_hash: [u8; 32],
+    /// Gaps measured (frames seen minus one)
+    pub gaps: u32,
+    /// Mean time between frames in milliseconds
+    pub mean_ms: u32,
+    /// Standard deviation of the time between frames in milliseconds
+    pub stddev_ms: u32,
+}
+
+/// Station status published on `sniffer/<station>/status` (stats on `sniffer/<station>/stats`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    /// Firmware build, sent once after boot
+    Boot(Build),
+    /// Periodic capture counters
+    Stats(Stats),
+    /// Periodic memory usage
+    Memory(Memory),
+    /// Periodic per-AP beacon survey
+    Beacons(Vec<ApBeacons>),
+    /// Periodic per-device inter-arrival summary
+    Arrivals(Vec<DeviceArrivals>),
+    /// Firmware download progress in percent
+    OtaProgress(u8),
+    /// New firmware written and verified, rebooting into it
+    OtaSuccess,
+    /// Update aborted, the station keeps running the current firmware
+    OtaFailed(String),
+    /// WiFi link was lost and is back, with the driver's reason code for the drop
+    WifiReconnected { reason: u16, description: &'static str },
+}
+
+impl Status {
+    /// Topic suffix and whether the message is retained (so new subscribers see the last state)
+    fn topic(&self) -> (&'static str, bool) {
+        match self {
+            Status::Stats(_) => ("stats", false),
+            Status::Beacons(_) => ("beacons", false),
+            Status::Arrivals(_) => ("arrivals", false),
+            _ => ("status", true),
+        }
+    }
+
+    fn to_json(&self) -> String {
+        match self {
+            Status::Boot(build) => format!(
+                r#"{{"boot":{{"version":"{}","git_hash":"{}","build_time":{}}}}}"#,
+                build.version, build.git_hash, build.build_time
+            ),
+            Status::Stats(stats) => format!(
+                r#"{{"captured":{},"sent":{},"dropped":{},"retries":{},"channel_utilization":{},"uptime_secs":{},"unique_devices":{},"outbox_pending":{}}}"#,
+                stats.captured,
+                stats.sent,
+                stats.dropped,
+                stats.retries,
+                stats.channel_utilization,
+                stats.uptime_secs,
+                stats.unique_devices,
+                stats.outbox_pending
+            ),
+            Status::Memory(memory) => format!(
+                r#"{{"memory":{{"free_heap":{},"min_free_heap":{},"largest_free_block":{},"main_stack_high_water":{},"low":{}}}}}"#,
+                memory.free_heap,
+                memory.min_free_heap,
+                memory.largest_free_block,
+                memory.main_stack_high_water,
+                memory.low
+            ),
+            Status::Beacons(aps) => {
+                let aps: Vec<String> = aps
+                    .iter()
+                    .map(|ap| {
+                        let b = ap.bssid;
+                        format!(
+                            concat!(
+                                r#"{{"bssid":"{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}","#,
+                                r#""beacons":{},"last_seen":{},"rssi":{},"wps_enabled":{}}}"#
+                            ),
+                            b[0], b[1], b[2], b[3], b[4], b[5],
+                            ap.beacons, ap.last_seen, ap.rssi, ap.wps_enabled
+                        )
+                    })
+                    .collect();
+                format!("[{}]", aps.join(","))
+            }
+            Status::Arrivals(devices) => {
+                let devices: Vec<String> = devices
+                    .iter()
+                    .map(|device| {
+                        let mac_hex: String =
+                            device.mac_hash.iter().map(|byte| format!("{:02x}", byte)).collect();
+                        format!(
+                            r#"{{"mac_hash":"{}","gaps":{},"mean_ms":{},"stddev_ms":{}}}"#,
+                            mac_hex, device.gaps, device.mean_ms, device.stddev_ms
+                        )
+                    })
+                    .collect();
+                format!("[{}]", devices.join(","))
+            }
+            Status::OtaProgress(percent) => format!(r#"{{"ota":"progress","percent":{}}}"#, percent),
+            Status::OtaSuccess => r#"{"ota":"success"}"#.to_string(),
+            Status::OtaFailed(error) => {
+                format!(r#"{{"ota":"failed","error":"{}"}}"#, error.replace(['"', '\\'], "'"))
+            }
+            Status::WifiReconnected { reason, description } => format!(
+                r#"{{"wifi":{{"reconnected":true,"reason":{},"description":"{}"}}}}"#,
+                reason, description
+            ),
+        }
+    }
 }
 
 /// MQTT publisher that receives events from a channel and publishes them
 pub struct MqttPublisher {
     client: EspMqttClient<'static>,
-    rx: Receiver<DeviceEvent>,
+    rx: Receiver<EventBatch>,
+    status_rx: Receiver<Status>,
     station_id: String,
+    device_topics: DeviceTopics,
+    location: Option<GeoLocation>,
 }
 
 impl MqttPublisher {
-    /// Create new MQTT publisher with TLS
-    pub fn new(station_id: &str, rx: Receiver<DeviceEvent>) -> Result<Self> {
-        info!("Connecting to MQTT broker: {}", MQTT_BROKER);
-        info!("TLS enabled with embedded CA certificate");
-
-        // Parse CA certificate for TLS verification
-        let server_cert = X509::pem_until_nul(CA_CERT);
-
-        let mqtt_config = MqttClientConfiguration {
-            client_id: Some(station_id),
-            username: Some(MQTT_USERNAME),
-            password: Some(MQTT_PASSWORD),
-            // TLS configuration
-            server_certificate: Some(server_cert),
-            // Skip CN check since we use IP address in certificate
-            // The CA signature is still verified
-            skip_cert_common_name_check: true,
-            ..Default::default()
+    /// Create new MQTT publisher with TLS (plaintext with the no-tls feature)
+    /// Commands received from the broker are forwarded to `commands`
+    pub fn new(
+        station_id: &str,
+        rx: Receiver<EventBatch>,
+        status_rx: Receiver<Status>,
+        commands: Sender<Command>,
+    ) -> Result<Self> {
+        let credentials = credentials::get();
+        info!("Connecting to MQTT broker: {}", credentials.mqtt_broker);
+
+        #[cfg(not(feature = "no-tls"))]
+        let mqtt_config = {
+            info!("TLS enabled with embedded CA certificate");
+
+            // Parse CA certificate for TLS verification
+            let server_cert = X509::pem_until_nul(CA_CERT);
+
+            MqttClientConfiguration {
+                client_id: Some(station_id),
+                username: Some(credentials.mqtt_username.as_str()),
+                password: Some(credentials.mqtt_password.as_str()),
+                // TLS configuration
+                server_certificate: Some(server_cert),
+                // Skip CN check since we use IP address in certificate
+                // The CA signature is still verified
+                skip_cert_common_name_check: true,
+                ..Default::default()
+            }
         };
 
+        #[cfg(feature = "no-tls")]
+        let mqtt_config = {
+            log::warn!("TLS disabled (no-tls build), credentials and events are sent in plaintext");
+            MqttClientConfiguration {
+                client_id: Some(station_id),
+                username: Some(credentials.mqtt_username.as_str()),
+                password: Some(credentials.mqtt_password.as_str()),
+                ..Default::default()
+            }
+        };
+
+        let command_topic = command_topic(station_id);
+
         let client = EspMqttClient::new_cb(
-            MQTT_BROKER, // mqtts:// URL triggers TLS
+            // mqtts:// URL triggers TLS, the credentials module checks it matches the feature
+            &credentials.mqtt_broker,
             &mqtt_config,
             move |event| {
                 match event.payload() {
                     EventPayload::Connected(_) => {
-                        info!("MQTT connected (TLS)");
+                        info!("MQTT connected ({})", TRANSPORT);
+                        CONNECTED.store(true, Ordering::Relaxed);
                     }
                     EventPayload::Disconnected => {
                         info!("MQTT disconnected");
+                        CONNECTED.store(false, Ordering::Relaxed);
+                    }
+                    EventPayload::Received { topic, data, .. } => {
+                        if topic != Some(command_topic.as_str()) {
+                            return;
+                        }
+                        match Command::parse(data) {
+                            Some(command) => {
+                                info!("Command received: {:?}", command);
+                                let _ = commands.send(command);
+                            }
+                            None => error!("Unknown command: {}", String::from_utf8_lossy(data)),
+                        }
                     }
                     EventPayload::Error(e) => {
+                        ERROR_COUNT.fetch_add(1, Ordering::Relaxed);
                         error!("MQTT error: {:?}", e);
                     }
+                    // A QoS 1 message left the outbox, acked or given up on
+                    EventPayload::Published(_) | EventPayload::Deleted(_) => {
+                        let _ = OUTBOX_PENDING
+                            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1));
+                    }
                     _ => {}
                 }
             },
         )?;
 
-        info!("MQTT client created for station: {} (TLS enabled)", station_id);
+        info!("MQTT client created for station: {} ({})", station_id, TRANSPORT);
 
         Ok(Self {
             client,
             rx,
+            status_rx,
             station_id: station_id.to_string(),
+            device_topics: DeviceTopics::configured(),
+            location: station_location(),
         })
     }
 
     /// Run the publisher loop - receives events and publishes to MQTT
+    /// Feeds the task watchdog at least once a second. Returns once the sniffer drops its event sender, after flushing queued events
     pub fn run(&mut self) -> Result<()> {
         info!("MQTT publisher running...");
 
+        let mut subscribed = false;
         loop {
-            // Block waiting for events with timeout
-            match self.rx.recv_timeout(Duration::from_secs(5)) {
-                Ok(event) => {
-                    self.publish_event(&event)?;
+            crate::watchdog::feed();
+            // Subscribe to the command topic on every (re)connect
+            if !is_connected() {
+                subscribed = false;
+            } else if !subscribed {
+                let topic = command_topic(&self.station_id);
+                match self.client.subscribe(&topic, QoS::AtLeastOnce) {
+                    Ok(_) => {
+                        info!("Subscribed to {}", topic);
+                        subscribed = true;
+                    }
+                    Err(e) => error!("Failed to subscribe to {}: {:?}", topic, e),
+                }
+            }
+
+            // Status messages are rare, publish whatever is waiting
+            while let Ok(status) = self.status_rx.try_recv() {
+                self.publish_status(&status);
+            }
+
+            // Block waiting for event batches with timeout
+            match self.rx.recv_timeout(Duration::from_secs(1)) {
+                Ok(batch) => {
+                    for event in batch.iter() {
+                        self.publish_event(event)?;
+                    }
                 }
                 Err(mpsc::RecvTimeoutError::Timeout) => {
                     // No events, just continue
                 }
                 Err(mpsc::RecvTimeoutError::Disconnected) => {
-                    error!("Event channel disconnected");
+                    // All events sent before the sender was dropped have been received
+                    info!("Event channel closed, flushing MQTT outbox");
+                    while let Ok(status) = self.status_rx.try_recv() {
+                        self.publish_status(&status);
+                    }
+                    thread::sleep(FLUSH_DELAY);
                     break;
                 }
             }
@@ -117,42 +561,126 @@ impl MqttPublisher {
             mac_hex.push_str(&format!("{:02x}", byte));
         }
 
-        // Use a fixed-size buffer to avoid heap allocation
-        let mut payload = [0u8; 200];  // Increased size for longer hash
-        let payload_str = format!(
-            r#"{{"mac_hash":"{}","rssi":{},"channel":{},"timestamp":{},"station":"{}"}}"#,
-            mac_hex, event.rssi, event.channel, event.timestamp, self.station_id
-        );
-
-        let len = payload_str.len().min(payload.len());
-        payload[..len].copy_from_slice(&payload_str.as_bytes()[..len]);
-
-        // Use static topic to avoid allocation
-        let topic = format!("{}/{}/device", MQTT_TOPIC_PREFIX, self.station_id);
-
-        // Try to enqueue, ignore errors (MQTT outbox full)
-        if let Err(e) = self.client.enqueue(
-            &topic,
-            QoS::AtMostOnce,
-            false,
-            &payload[..len],
-        ) {
+        let message = MqttDeviceEvent {
+            mac_hash: mac_hex.clone(),
+            rssi: event.rssi,
+            quality: rssi_to_quality(event.rssi),
+            channel: event.channel,
+            timestamp: event.timestamp,
+            station: self.station_id.clone(),
+            randomized: event.randomized,
+            bandwidth: event.bandwidth,
+            access_category: event.access_category,
+            fingerprint: event.fingerprint.map(|fp| format!("{:08x}", fp.id)),
+            device_class: event
+                .fingerprint
+                .and_then(|fp| fp.device_class)
+                .map(str::to_string),
+            vendor_ies: event
+                .vendor_ies
+                .iter()
+                .map(|ie| {
+                    format!(
+                        "{:02x}{:02x}{:02x}:{:02x}",
+                        ie.oui[0], ie.oui[1], ie.oui[2], ie.vendor_type
+                    )
+                })
+                .collect(),
+            join: event.join.map(|join| {
+                let b = join.bssid;
+                JoinTarget {
+                    kind: join.kind,
+                    bssid: format!(
+                        "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+                        b[0], b[1], b[2], b[3], b[4], b[5]
+                    ),
+                }
+            }),
+            presence: event.presence,
+            frames: event.frames,
+            retries: event.retries,
+            location: self.location,
+        };
+        let payload = serde_json::to_vec(&message)?;
+
+        let shared_topic = format!("{}/{}/device", MQTT_TOPIC_PREFIX, self.station_id);
+        let device_topic = || format!("{}/{}", shared_topic, &mac_hex[..DEVICE_TOPIC_PREFIX_LEN]);
+        let topics = match self.device_topics {
+            DeviceTopics::Shared => vec![shared_topic.clone()],
+            DeviceTopics::PerDevice => vec![device_topic()],
+            DeviceTopics::Both => vec![shared_topic.clone(), device_topic()],
+        };
+
+        for topic in &topics {
+            self.enqueue_event(topic, &payload);
+        }
+
+        Ok(())
+    }
+
+    /// Enqueue one event message, errors (MQTT outbox full) are counted and logged occasionally
+    fn enqueue_event(&mut self, topic: &str, payload: &[u8]) {
+        if let Err(e) = self.client.enqueue(topic, QoS::AtMostOnce, false, payload) {
+            ERROR_COUNT.fetch_add(1, Ordering::Relaxed);
             // Log occasionally, don't spam
-            static SKIP_COUNT: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(0);
-            let skipped = SKIP_COUNT.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            static SKIP_COUNT: AtomicU32 = AtomicU32::new(0);
+            let skipped = SKIP_COUNT.fetch_add(1, Ordering::Relaxed);
             if skipped % 100 == 0 {
                 error!("MQTT enqueue failed ({}): {:?}", skipped, e);
             }
+        } else {
+            PUBLISHED_COUNT.fetch_add(1, Ordering::Relaxed);
         }
-
-        Ok(())
     }
 
+    /// Publish a status or stats message
+    fn publish_status(&mut self, status: &Status) {
+        let (suffix, retain) = status.topic();
+        let topic = format!("{}/{}/{}", MQTT_TOPIC_PREFIX, self.station_id, suffix);
+        let payload = status.to_json();
+        match self.client.enqueue(&topic, QoS::AtLeastOnce, retain, payload.as_bytes()) {
+            Ok(_) => {
+                OUTBOX_PENDING.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) => error!("MQTT status enqueue failed: {:?}", e),
+        }
+    }
+}
 
+/// Topic the station listens on for commands (`sniffer/<station>/cmd`)
+fn command_topic(station_id: &str) -> String {
+    format!("{}/{}/cmd", MQTT_TOPIC_PREFIX, station_id)
 }
 
 /// Create bounded event channel for passing device detections
-/// Returns a SyncSender that will drop events when channel is full
-pub fn create_event_channel() -> (SyncSender<DeviceEvent>, Receiver<DeviceEvent>) {
+/// Returns a SyncSender that will drop batches when channel is full
+pub fn create_event_channel() -> (SyncSender<EventBatch>, Receiver<EventBatch>) {
     mpsc::sync_channel(CHANNEL_CAPACITY)
 }
+
+/// Whether the client is connected to the broker
+pub fn is_connected() -> bool {
+    CONNECTED.load(Ordering::Relaxed)
+}
+
+/// Get count of events handed to the MQTT client
+pub fn get_published_count() -> u32 {
+    PUBLISHED_COUNT.load(Ordering::Relaxed)
+}
+
+/// Get count of MQTT errors (client errors and failed enqueues)
+pub fn get_error_count() -> u32 {
+    ERROR_COUNT.load(Ordering::Relaxed)
+}
+
+/// QoS 1 messages waiting in the client outbox for the broker's ack
+/// Counted from enqueues and acks, esp-mqtt doesn't report its outbox by message count.
+/// A value that keeps growing means the broker link can't keep up.
+pub fn outbox_pending() -> u32 {
+    OUTBOX_PENDING.load(Ordering::Relaxed)
+}
+
+/// Create bounded status channel, senders should use `try_send` and drop on full
+pub fn create_status_channel() -> (SyncSender<Status>, Receiver<Status>) {
+    mpsc::sync_channel(STATUS_CHANNEL_CAPACITY)
+}
@@ -0,0 +1,110 @@
+use anyhow::{bail, Result};
+use esp_idf_svc::http::client::{Configuration, EspHttpConnection};
+use esp_idf_svc::http::Method;
+use esp_idf_svc::ota::EspOta;
+#[cfg(not(feature = "no-tls"))]
+use esp_idf_svc::sys::{esp_tls_set_global_ca_store, ESP_OK};
+use log::info;
+use std::time::Duration;
+use crate::mqtt::Status;
+#[cfg(not(feature = "no-tls"))]
+use crate::mqtt::CA_CERT;
+
+/// Download chunk size, also the HTTP client buffer size
+const OTA_CHUNK_SIZE: usize = 4096;
+
+/// Give up if the server stops sending for this long
+const OTA_HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Report download progress every N percent
+const OTA_PROGRESS_STEP: u8 = 10;
+
+/// Mark the running firmware as good so the bootloader keeps it
+/// A freshly installed image that resets before this is called is rolled back on the next boot
+pub fn mark_boot_valid() -> Result<()> {
+    let mut ota = EspOta::new()?;
+    let slot = ota.get_running_slot()?;
+    ota.mark_running_slot_valid()?;
+    info!("Running firmware slot '{}' marked valid", slot.label);
+    Ok(())
+}
+
+/// Download firmware from `url` over HTTPS and write it to the inactive OTA slot
+/// The server certificate must be signed by the embedded CA. On success the new image
+/// is verified and set as the boot slot, the caller is responsible for rebooting.
+/// Progress is passed to `report` as it goes.
+pub fn update(url: &str, report: impl Fn(Status)) -> Result<()> {
+    info!("Starting OTA update from {}", url);
+
+    trust_ca()?;
+
+    let mut conn = EspHttpConnection::new(&Configuration {
+        buffer_size: Some(OTA_CHUNK_SIZE),
+        timeout: Some(OTA_HTTP_TIMEOUT),
+        use_global_ca_store: true,
+        ..Default::default()
+    })?;
+    conn.initiate_request(Method::Get, url, &[])?;
+    conn.initiate_response()?;
+
+    let status = conn.status();
+    if status != 200 {
+        bail!("Firmware download failed with HTTP status {}", status);
+    }
+    let total = conn.header("Content-Length").and_then(|len| len.parse::<usize>().ok());
+
+    let mut ota = EspOta::new()?;
+    // Dropping the update without completing it aborts it and leaves the current slot in place
+    let mut update = ota.initiate_update()?;
+
+    let mut buf = vec![0u8; OTA_CHUNK_SIZE];
+    let mut written = 0;
+    let mut reported = 0;
+    loop {
+        let len = conn.read(&mut buf)?;
+        if len == 0 {
+            break;
+        }
+        update.write(&buf[..len])?;
+        written += len;
+        crate::watchdog::feed();
+
+        if let Some(total) = total.filter(|&total| total > 0) {
+            let percent = (written * 100 / total).min(100) as u8;
+            if percent >= reported + OTA_PROGRESS_STEP {
+                reported = percent - percent % OTA_PROGRESS_STEP;
+                report(Status::OtaProgress(reported));
+            }
+        }
+    }
+
+    if written == 0 {
+        bail!("Firmware download was empty");
+    }
+    if let Some(total) = total {
+        if written != total {
+            bail!("Firmware download incomplete: {} of {} bytes", written, total);
+        }
+    }
+
+    // Validates the image and switches the boot slot
+    update.complete()?;
+    info!("OTA update written ({} bytes)", written);
+    Ok(())
+}
+
+/// Trust the same CA as the MQTT connection for the download
+#[cfg(not(feature = "no-tls"))]
+fn trust_ca() -> Result<()> {
+    let ret = unsafe { esp_tls_set_global_ca_store(CA_CERT.as_ptr(), CA_CERT.len() as u32) };
+    if ret != ESP_OK {
+        bail!("Failed to set CA store: {}", ret);
+    }
+    Ok(())
+}
+
+/// Without the embedded CA the server can't be verified, so no-tls builds refuse OTA
+#[cfg(feature = "no-tls")]
+fn trust_ca() -> Result<()> {
+    bail!("OTA is disabled in no-tls builds, there is no CA to verify the server with")
+}
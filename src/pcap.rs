@@ -0,0 +1,94 @@
+use anyhow::Result;
+use esp_idf_svc::sys::{esp_log_level_set, esp_log_level_t_ESP_LOG_NONE};
+use std::io::Write;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Mutex;
+use std::thread;
+
+/// pcap magic number, microsecond timestamps
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+
+/// LINKTYPE_IEEE802_11: raw 802.11 frames without radiotap header or FCS
+const LINKTYPE_IEEE802_11: u32 = 105;
+
+/// Bytes kept per frame, longer frames are truncated (their original length is kept)
+const SNAPLEN: usize = 512;
+
+/// Frames queued for the writer, the rest is dropped (the UART is far slower than the radio)
+const PCAP_CHANNEL_CAPACITY: usize = 16;
+
+/// Writer thread stack size (bytes)
+const PCAP_STACK_SIZE: usize = 4096;
+
+/// One captured frame
+struct Record {
+    /// `rx_ctrl` timestamp in microseconds since WiFi started
+    timestamp: u32,
+    /// Frame length before truncation
+    orig_len: u32,
+    data: Vec<u8>,
+}
+
+/// Frame sender, set while the pcap dump runs
+static SENDER: Mutex<Option<SyncSender<Record>>> = Mutex::new(None);
+
+/// Start dumping captured frames as pcap on the serial console
+/// All logging is switched off first, anything else on the UART would corrupt the stream
+pub fn start() -> Result<()> {
+    log::warn!("pcap dump enabled, switching off logging - the serial console is binary from here");
+    log::set_max_level(log::LevelFilter::Off);
+    unsafe {
+        esp_log_level_set(c"*".as_ptr(), esp_log_level_t_ESP_LOG_NONE);
+    }
+
+    let (tx, rx) = mpsc::sync_channel(PCAP_CHANNEL_CAPACITY);
+    thread::Builder::new()
+        .stack_size(PCAP_STACK_SIZE)
+        .spawn(move || run(rx))?;
+
+    if let Ok(mut guard) = SENDER.lock() {
+        *guard = Some(tx);
+    }
+    Ok(())
+}
+
+/// Queue a raw frame (without FCS) for the dump, drops it if the writer is behind
+/// Called from the WiFi callback, so it never blocks
+pub fn capture(timestamp: u32, frame: &[u8]) {
+    let Ok(guard) = SENDER.try_lock() else {
+        return;
+    };
+    if let Some(tx) = guard.as_ref() {
+        let data = frame[..frame.len().min(SNAPLEN)].to_vec();
+        let _ = tx.try_send(Record { timestamp, orig_len: frame.len() as u32, data });
+    }
+}
+
+fn run(rx: Receiver<Record>) {
+    let mut out = std::io::stdout().lock();
+
+    // Global header: magic, version 2.4, UTC offset, accuracy, snaplen, link type
+    let mut header = Vec::with_capacity(24);
+    header.extend_from_slice(&PCAP_MAGIC.to_le_bytes());
+    header.extend_from_slice(&2u16.to_le_bytes());
+    header.extend_from_slice(&4u16.to_le_bytes());
+    header.extend_from_slice(&0i32.to_le_bytes());
+    header.extend_from_slice(&0u32.to_le_bytes());
+    header.extend_from_slice(&(SNAPLEN as u32).to_le_bytes());
+    header.extend_from_slice(&LINKTYPE_IEEE802_11.to_le_bytes());
+    if out.write_all(&header).and_then(|_| out.flush()).is_err() {
+        return;
+    }
+
+    for record in rx {
+        let mut buf = Vec::with_capacity(16 + record.data.len());
+        buf.extend_from_slice(&(record.timestamp / 1_000_000).to_le_bytes());
+        buf.extend_from_slice(&(record.timestamp % 1_000_000).to_le_bytes());
+        buf.extend_from_slice(&(record.data.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&record.orig_len.to_le_bytes());
+        buf.extend_from_slice(&record.data);
+        if out.write_all(&buf).and_then(|_| out.flush()).is_err() {
+            return;
+        }
+    }
+}
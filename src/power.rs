@@ -0,0 +1,41 @@
+use esp_idf_svc::sys::esp_deep_sleep;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Sniff/sleep duty cycle for battery-powered stations
+/// Set both DUTY_ACTIVE_SECS and DUTY_SLEEP_SECS at build time to enable it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DutyCycle {
+    /// How long to sniff and publish after each wake
+    pub active: Duration,
+    /// How long to deep-sleep in between
+    pub sleep: Duration,
+}
+
+/// Configured duty cycle, `None` to stay awake
+pub fn duty_cycle() -> Option<DutyCycle> {
+    let secs = |value: Option<&str>| {
+        value
+            .and_then(|secs| secs.parse::<u64>().ok())
+            .filter(|&secs| secs > 0)
+            .map(Duration::from_secs)
+    };
+    Some(DutyCycle {
+        active: secs(option_env!("DUTY_ACTIVE_SECS"))?,
+        sleep: secs(option_env!("DUTY_SLEEP_SECS"))?,
+    })
+}
+
+/// Microseconds from the system clock
+/// Backed by the RTC timer, so unlike `esp_timer_get_time` it keeps counting through deep sleep
+pub fn timestamp_us() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_micros() as u64)
+        .unwrap_or(0)
+}
+
+/// Enter deep sleep, the station boots from scratch when the timer wakes it
+pub fn deep_sleep(duration: Duration) -> ! {
+    log::info!("Deep sleep for {}s", duration.as_secs());
+    unsafe { esp_deep_sleep(duration.as_micros() as u64) }
+}
@@ -0,0 +1,52 @@
+//! Signal strength helpers shared by the firmware and the web dashboard.
+//!
+//! Pure functions without dependencies, so they build for the ESP32 and run in host tests.
+
+/// RSSI at and above which the signal quality is 100%
+pub const QUALITY_MAX_RSSI: i8 = -50;
+
+/// RSSI at and below which the signal quality is 0%
+pub const QUALITY_MIN_RSSI: i8 = -100;
+
+/// Map an RSSI in dBm to a 0-100% signal quality for display
+///
+/// The common linear curve: -50 dBm and stronger is 100%, -100 dBm and weaker is 0%,
+/// and every dB in between is worth 2%. It is a presentation aid only, positioning keeps
+/// using the raw dBm.
+pub fn rssi_to_quality(rssi: i8) -> u8 {
+    let clamped = rssi.clamp(QUALITY_MIN_RSSI, QUALITY_MAX_RSSI) as i16;
+    (2 * (clamped - QUALITY_MIN_RSSI as i16)) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quality_endpoints() {
+        assert_eq!(rssi_to_quality(-50), 100);
+        assert_eq!(rssi_to_quality(-100), 0);
+    }
+
+    #[test]
+    fn test_quality_is_linear_in_between() {
+        assert_eq!(rssi_to_quality(-75), 50);
+        assert_eq!(rssi_to_quality(-60), 80);
+        assert_eq!(rssi_to_quality(-99), 2);
+    }
+
+    #[test]
+    fn test_quality_clamps_out_of_range() {
+        assert_eq!(rssi_to_quality(-30), 100);
+        assert_eq!(rssi_to_quality(i8::MAX), 100);
+        assert_eq!(rssi_to_quality(-110), 0);
+        assert_eq!(rssi_to_quality(i8::MIN), 0);
+    }
+
+    #[test]
+    fn test_quality_is_monotonic() {
+        for rssi in i8::MIN..i8::MAX {
+            assert!(rssi_to_quality(rssi) <= rssi_to_quality(rssi + 1));
+        }
+    }
+}
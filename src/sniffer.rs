@@ -2,39 +2,458 @@ use esp_idf_svc::sys::{
     esp_wifi_set_promiscuous,
     esp_wifi_set_promiscuous_rx_cb,
     esp_wifi_set_promiscuous_filter,
-    esp_timer_get_time,
     wifi_promiscuous_pkt_t,
     wifi_promiscuous_pkt_type_t,
     wifi_promiscuous_filter_t,
     WIFI_PROMIS_FILTER_MASK_MGMT,
     WIFI_PROMIS_FILTER_MASK_DATA,
+    WIFI_PROMIS_FILTER_MASK_CTRL,
+    uxTaskGetStackHighWaterMark,
     ESP_OK,
 };
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU32, AtomicU64, AtomicU8, Ordering};
 use std::sync::Mutex;
 use std::sync::mpsc::SyncSender;
-use sha2::{Sha256, Digest};
-use crate::mqtt::DeviceEvent;
+use std::thread;
+use std::time::{Duration, Instant};
+use esp32_wifi_sniffer::ie::{
+    collect_vendor_ies, has_vendor_ie, InformationElements, VendorIes, IE_VENDOR_SPECIFIC,
+    WPS_OUI, WPS_VENDOR_TYPE,
+};
+use esp32_wifi_sniffer::mac::MacAddress;
+use crate::mqtt::{
+    AccessCategory, ApBeacons, Bandwidth, DeviceArrivals, DeviceEvent, EventBatch, Fingerprint,
+    Join, JoinKind, Presence,
+};
 
 /// Packet counter for statistics
 static PACKET_COUNT: AtomicU32 = AtomicU32::new(0);
 static DROPPED_COUNT: AtomicU32 = AtomicU32::new(0);
 static SENT_COUNT: AtomicU32 = AtomicU32::new(0);
+/// Captured frames with the retry bit set
+static RETRY_COUNT: AtomicU32 = AtomicU32::new(0);
+
+/// Captured frames per frame type, before any filtering
+static MGMT_COUNT: AtomicU32 = AtomicU32::new(0);
+static DATA_COUNT: AtomicU32 = AtomicU32::new(0);
+static CTRL_COUNT: AtomicU32 = AtomicU32::new(0);
+
+/// Captured frames per frame type since boot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameTypeCounts {
+    pub mgmt: u32,
+    pub data: u32,
+    pub ctrl: u32,
+}
+
+/// Counter values cleared by `reset_all_counters`, the totals of the interval that ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnifferCounters {
+    pub captured: u32,
+    pub sent: u32,
+    pub dropped: u32,
+    pub retries: u32,
+    pub types: FrameTypeCounts,
+}
+
+/// All sniffer counters read back-to-back, with rates since the previous snapshot
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SnifferStats {
+    pub captured: u32,
+    pub sent: u32,
+    pub dropped: u32,
+    pub retries: u32,
+    pub types: FrameTypeCounts,
+    /// Channel busy percentage since the previous snapshot
+    pub channel_utilization: f32,
+    /// Per second since the previous snapshot, 0 for the first one
+    pub captured_per_sec: f32,
+    pub sent_per_sec: f32,
+    pub dropped_per_sec: f32,
+}
+
+/// Previous snapshot, the base for the rates
+static LAST_SNAPSHOT: Mutex<Option<(Instant, SnifferStats)>> = Mutex::new(None);
+
+/// Estimated airtime of the frames captured since the last utilization reading (microseconds)
+static BUSY_US: AtomicU32 = AtomicU32::new(0);
+/// When the current utilization window started (`timestamp_us`)
+static BUSY_SINCE: AtomicU64 = AtomicU64::new(0);
+
+/// Rate assumed by the length-based airtime estimate, the lowest mandatory OFDM rate
+const NOMINAL_RATE_MBPS: u32 = 6;
+
+/// Promiscuous filter in use, 0 until the sniffer is started
+static FILTER_MASK: AtomicU32 = AtomicU32::new(0);
+
+/// Capture paused (promiscuous mode off, callback still registered)
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+/// APs tracked for beacon stats, the least recently heard one makes room for a new AP
+const BEACON_TABLE_SIZE: usize = 32;
+
+/// Beacon counting enabled, set from `SnifferConfig`
+static BEACON_STATS: AtomicBool = AtomicBool::new(false);
+
+/// Per-AP beacon counts, filled by the callback
+static BEACONS: Mutex<[Option<ApBeacons>; BEACON_TABLE_SIZE]> =
+    Mutex::new([None; BEACON_TABLE_SIZE]);
+
+/// Devices tracked for inter-arrival stats, the least recently heard one makes room for a new one
+const ARRIVAL_TABLE_SIZE: usize = 32;
+
+/// Devices tracked in first-seen mode, the least recently heard one departs to make room
+const PRESENCE_TABLE_SIZE: usize = 128;
+
+/// Default silence after which a device departs in first-seen mode, override with DEPARTURE_TIMEOUT_SECS
+const DEFAULT_DEPARTURE_TIMEOUT_SECS: u64 = 300;
+
+/// First-seen mode enabled, set from `SnifferConfig`
+static FIRST_SEEN_ONLY: AtomicBool = AtomicBool::new(false);
+
+/// Silence before a departure event in microseconds, 0 for no departure events
+static DEPARTURE_TIMEOUT_US: AtomicU64 = AtomicU64::new(0);
+
+/// A device present in first-seen mode
+#[derive(Clone, Copy)]
+struct Sighting {
+    mac: MacAddress,
+    /// Latest frame, its reading goes into the departure event
+    last_seen: u64,
+    rssi: i8,
+    channel: u8,
+    bandwidth: Bandwidth,
+}
+
+/// Devices currently present in first-seen mode
+static PRESENCE: Mutex<[Option<Sighting>; PRESENCE_TABLE_SIZE]> =
+    Mutex::new([None; PRESENCE_TABLE_SIZE]);
+
+/// Join frames forwarded as join events, set from `SnifferConfig`
+static CAPTURE_JOIN_FRAMES: AtomicBool = AtomicBool::new(false);
+
+/// Only probe requests are forwarded, set by `start_probe_sniffer`
+static PROBES_ONLY: AtomicBool = AtomicBool::new(false);
+
+/// Inter-arrival stats enabled, set from `SnifferConfig`
+static ARRIVAL_STATS: AtomicBool = AtomicBool::new(false);
+
+/// Running inter-arrival statistics of one device (Welford's algorithm)
+#[derive(Clone, Copy)]
+struct Arrivals {
+    mac: MacAddress,
+    /// Latest frame, also the LRU key
+    last_seen: u64,
+    /// Inter-arrival gaps seen so far (one less than the frames)
+    gaps: u32,
+    /// Mean gap in milliseconds
+    mean_ms: f32,
+    /// Sum of squared differences from the mean, variance is `m2 / gaps`
+    m2: f32,
+}
+
+/// Per-device inter-arrival stats, filled by the callback
+static ARRIVALS: Mutex<[Option<Arrivals>; ARRIVAL_TABLE_SIZE]> =
+    Mutex::new([None; ARRIVAL_TABLE_SIZE]);
+
+/// Frames shorter than this are dropped before any parsing, set from `SnifferConfig`
+static MIN_PACKET_LEN: AtomicU16 = AtomicU16::new(DEFAULT_MIN_PACKET_LEN);
+
+/// Default capture threshold, a full management/data MAC header
+const DEFAULT_MIN_PACKET_LEN: u16 = 24;
+
+/// Capture settings, read from the build environment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnifferConfig {
+    /// Drop frames shorter than this (MIN_PACKET_LEN, default 24)
+    /// Parsing still needs enough bytes for each frame type's header, this only decides what
+    /// is captured at all. Lower it (e.g. to 16) together with `capture_control_frames`.
+    pub min_packet_len: u16,
+    /// Also capture control frames (CAPTURE_CONTROL_FRAMES=true), e.g. RTS and block acks
+    pub capture_control_frames: bool,
+    /// Count beacons per AP for RF surveys (BEACON_STATS=true)
+    pub beacon_stats: bool,
+    /// Track per-device inter-arrival times (ARRIVAL_STATS=true)
+    pub arrival_stats: bool,
+    /// Forward every (re)association and authentication frame (CAPTURE_JOIN_FRAMES=true)
+    pub capture_join_frames: bool,
+    /// Publish one event when a device appears instead of a stream of readings (FIRST_SEEN_ONLY=true)
+    pub first_seen_only: bool,
+    /// Silence after which a device departs in first-seen mode (DEPARTURE_TIMEOUT_SECS), `None`
+    /// for no departure events
+    pub departure_timeout: Option<Duration>,
+    /// Sampling rate per frame kind (SEND_RATES, e.g. `probe_request=1,data=100`), `None` for
+    /// the default rate
+    pub send_rates: [Option<u32>; FrameKind::COUNT],
+    /// Sampling rate of the other frame kinds (`default=` in SEND_RATES, default 10)
+    pub default_send_rate: u32,
+}
+
+impl SnifferConfig {
+    pub fn from_env() -> Self {
+        let send_rates = parse_send_rates(option_env!("SEND_RATES").unwrap_or(""));
+        Self {
+            min_packet_len: option_env!("MIN_PACKET_LEN")
+                .and_then(|len| len.parse().ok())
+                .unwrap_or(DEFAULT_MIN_PACKET_LEN),
+            capture_control_frames: option_env!("CAPTURE_CONTROL_FRAMES") == Some("true"),
+            beacon_stats: option_env!("BEACON_STATS") == Some("true"),
+            arrival_stats: option_env!("ARRIVAL_STATS") == Some("true"),
+            capture_join_frames: option_env!("CAPTURE_JOIN_FRAMES") == Some("true"),
+            first_seen_only: option_env!("FIRST_SEEN_ONLY") == Some("true"),
+            departure_timeout: Some(
+                option_env!("DEPARTURE_TIMEOUT_SECS")
+                    .and_then(|secs| secs.parse().ok())
+                    .unwrap_or(DEFAULT_DEPARTURE_TIMEOUT_SECS),
+            )
+            .filter(|&secs| secs > 0)
+            .map(Duration::from_secs),
+            send_rates: send_rates.0,
+            default_send_rate: send_rates.1,
+        }
+    }
+}
+
+/// Parse SEND_RATES, `kind=rate` pairs separated by commas with `default` for unlisted kinds
+/// Invalid pairs are logged and skipped
+fn parse_send_rates(spec: &str) -> ([Option<u32>; FrameKind::COUNT], u32) {
+    let mut rates = [None; FrameKind::COUNT];
+    let mut default = SEND_RATE;
+    for pair in spec.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+        let parsed = pair.split_once('=').and_then(|(name, rate)| {
+            Some((name.trim(), rate.trim().parse::<u32>().ok().filter(|&rate| rate > 0)?))
+        });
+        match parsed {
+            Some(("default", rate)) => default = rate,
+            Some((name, rate)) => match FrameKind::from_name(name) {
+                Some(kind) => rates[kind as usize] = Some(rate),
+                None => log::warn!("Ignoring SEND_RATES entry with unknown frame kind '{}'", pair),
+            },
+            None => log::warn!("Ignoring invalid SEND_RATES entry '{}'", pair),
+        }
+    }
+    (rates, default)
+}
 
 /// Rate limit: only send 1 event per N packets to avoid overwhelming MQTT
 /// Lower value = faster updates (more MQTT messages)
+/// Default for frame kinds without their own rate, see SEND_RATES
 const SEND_RATE: u32 = 10;
 
+/// Frame kinds the sampling rate can be set for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameKind {
+    ProbeRequest,
+    /// Management frames other than probe requests
+    Management,
+    Data,
+    Control,
+}
+
+impl FrameKind {
+    pub const COUNT: usize = 4;
+    pub const ALL: [FrameKind; FrameKind::COUNT] =
+        [FrameKind::ProbeRequest, FrameKind::Management, FrameKind::Data, FrameKind::Control];
+
+    /// Kind of a frame from its header
+    fn of(header: &Ieee80211MacHeader) -> Self {
+        match header.frame_type() {
+            FRAME_TYPE_MGMT if header.subtype() == SUBTYPE_PROBE_REQUEST => FrameKind::ProbeRequest,
+            FRAME_TYPE_MGMT => FrameKind::Management,
+            FRAME_TYPE_CTRL => FrameKind::Control,
+            _ => FrameKind::Data,
+        }
+    }
+
+    /// Parse the name used in SEND_RATES and the `rate` command
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "probe_request" => Some(FrameKind::ProbeRequest),
+            "management" => Some(FrameKind::Management),
+            "data" => Some(FrameKind::Data),
+            "control" => Some(FrameKind::Control),
+            _ => None,
+        }
+    }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ATOMIC_ZERO: AtomicU32 = AtomicU32::new(0);
+
+/// Sampling rate per frame kind, 0 for the default rate
+static SEND_RATES: [AtomicU32; FrameKind::COUNT] = [ATOMIC_ZERO; FrameKind::COUNT];
+/// Sampling rate of frame kinds without their own rate
+static DEFAULT_SEND_RATE: AtomicU32 = AtomicU32::new(SEND_RATE);
+/// Frames seen per kind, the sampling counters
+static KIND_COUNTS: [AtomicU32; FrameKind::COUNT] = [ATOMIC_ZERO; FrameKind::COUNT];
+
+/// Send 1 in every `rate` frames of `kind`, `None` to go back to the default rate
+pub fn set_send_rate(kind: FrameKind, rate: Option<u32>) {
+    let rate = rate.map(|rate| rate.max(1));
+    SEND_RATES[kind as usize].store(rate.unwrap_or(0), Ordering::Relaxed);
+    match rate {
+        Some(rate) => log::info!("Send rate for {:?}: 1 in {}", kind, rate),
+        None => log::info!("Send rate for {:?}: default", kind),
+    }
+}
+
+/// Send 1 in every `rate` frames of the kinds without their own rate
+pub fn set_default_send_rate(rate: u32) {
+    DEFAULT_SEND_RATE.store(rate.max(1), Ordering::Relaxed);
+    log::info!("Default send rate: 1 in {}", rate.max(1));
+}
+
+/// Count a frame of `kind` and decide whether it is the 1 in N that gets sent
+fn sample(kind: FrameKind) -> bool {
+    let rate = match SEND_RATES[kind as usize].load(Ordering::Relaxed) {
+        0 => DEFAULT_SEND_RATE.load(Ordering::Relaxed),
+        rate => rate,
+    };
+    KIND_COUNTS[kind as usize].fetch_add(1, Ordering::Relaxed) % rate == 0
+}
+
+/// Default RSSI aggregation window, override with AGGREGATE_WINDOW_MS (0 falls back to SEND_RATE sampling)
+const DEFAULT_AGGREGATE_WINDOW_MS: u64 = 2000;
+
+/// Devices tracked per aggregation window, sources beyond this are dropped until the next flush
+const AGGREGATE_TABLE_SIZE: usize = 64;
+
+/// Aggregation flush thread stack size (bytes): a base for SHA-256 hashing, logging and the
+/// thread itself, plus the accumulator and event it copies at a time, so it follows their size
+const AGGREGATE_STACK_SIZE: usize =
+    3072 + core::mem::size_of::<Accumulator>() + 2 * core::mem::size_of::<DeviceEvent>();
+
+/// Free stack (bytes) on the aggregation thread below which a warning is logged
+const AGGREGATE_STACK_ALERT_BYTES: u32 = 512;
+
+/// How a device's readings within one window are combined into the published RSSI
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregation {
+    /// Strongest reading (closest approach)
+    Max,
+    /// Average reading
+    Mean,
+}
+
+impl Aggregation {
+    /// Aggregation function from AGGREGATE_FN (`max` or `mean`), defaults to max
+    pub fn configured() -> Self {
+        match option_env!("AGGREGATE_FN") {
+            Some("mean") => Aggregation::Mean,
+            _ => Aggregation::Max,
+        }
+    }
+}
+
+/// Configured aggregation window, `None` to sample every SEND_RATE-th packet instead
+pub fn aggregate_window() -> Option<Duration> {
+    let ms = option_env!("AGGREGATE_WINDOW_MS")
+        .and_then(|ms| ms.parse().ok())
+        .unwrap_or(DEFAULT_AGGREGATE_WINDOW_MS);
+    (ms > 0).then(|| Duration::from_millis(ms))
+}
+
+/// Per-frame details published alongside the RSSI
+#[derive(Debug, Clone, Copy)]
+struct FrameDetails {
+    bandwidth: Bandwidth,
+    access_category: Option<AccessCategory>,
+    fingerprint: Option<Fingerprint>,
+    vendor_ies: VendorIes,
+}
+
+impl FrameDetails {
+    /// Take a newer frame's details, keeping older values the newer frame doesn't carry
+    fn update(&mut self, newer: FrameDetails) {
+        self.bandwidth = newer.bandwidth;
+        self.access_category = newer.access_category.or(self.access_category);
+        self.fingerprint = newer.fingerprint.or(self.fingerprint);
+        if !newer.vendor_ies.is_empty() {
+            self.vendor_ies = newer.vendor_ies;
+        }
+    }
+}
+
+/// Readings from one device during the current aggregation window
+#[derive(Debug, Clone, Copy)]
+struct Accumulator {
+    mac: MacAddress,
+    max_rssi: i8,
+    rssi_sum: i32,
+    count: u32,
+    /// Readings from retransmitted frames
+    retries: u32,
+    channel: u8,
+    details: FrameDetails,
+    /// Latest reading
+    timestamp: u64,
+}
+
+/// Aggregation in use: 0 off (sampling), 1 max, 2 mean
+static AGGREGATION: AtomicU8 = AtomicU8::new(0);
+
+/// Per-device accumulators for the current window, filled by the callback and drained by the flush thread
+static AGGREGATES: Mutex<[Option<Accumulator>; AGGREGATE_TABLE_SIZE]> =
+    Mutex::new([None; AGGREGATE_TABLE_SIZE]);
+
+/// Unique device sketch precision, 2^10 one-byte registers (1 KiB) for about 3% standard error
+const HLL_PRECISION: u32 = 10;
+const HLL_REGISTERS: usize = 1 << HLL_PRECISION;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const HLL_EMPTY: AtomicU8 = AtomicU8::new(0);
+
+/// HyperLogLog registers, each holds the highest rank seen for its bucket
+static HLL: [AtomicU8; HLL_REGISTERS] = [HLL_EMPTY; HLL_REGISTERS];
+
+/// BSSID of our own uplink AP packed into the low 48 bits, frames in its BSS are skipped
+static OWN_BSSID: AtomicU64 = AtomicU64::new(NO_BSSID);
+const NO_BSSID: u64 = u64::MAX;
+
 /// Global event sender for the callback
-static EVENT_SENDER: Mutex<Option<SyncSender<DeviceEvent>>> = Mutex::new(None);
+static EVENT_SENDER: Mutex<Option<SyncSender<EventBatch>>> = Mutex::new(None);
+
+/// Events waiting to fill a batch, sent when full and by `flush_events`
+static PENDING_EVENTS: Mutex<EventBatch> = Mutex::new(EventBatch::new());
 
 /// Set the event sender for publishing device detections
-pub fn set_event_sender(sender: SyncSender<DeviceEvent>) {
+pub fn set_event_sender(sender: SyncSender<EventBatch>) {
     if let Ok(mut guard) = EVENT_SENDER.lock() {
         *guard = Some(sender);
     }
 }
 
+/// 802.11 frame types and the probe request subtype
+const FRAME_TYPE_MGMT: u16 = 0;
+const FRAME_TYPE_CTRL: u16 = 1;
+const FRAME_TYPE_DATA: u16 = 2;
+const SUBTYPE_ASSOC_REQUEST: u16 = 0;
+const SUBTYPE_REASSOC_REQUEST: u16 = 2;
+const SUBTYPE_PROBE_REQUEST: u16 = 4;
+const SUBTYPE_PROBE_RESPONSE: u16 = 5;
+const SUBTYPE_BEACON: u16 = 8;
+const SUBTYPE_AUTHENTICATION: u16 = 11;
+
+/// Fixed fields (timestamp, beacon interval, capabilities) before the IEs of beacons and probe responses
+const BEACON_FIXED_LEN: usize = 12;
+
+/// Frame check sequence at the end of every captured frame
+const FCS_LEN: usize = 4;
+
+/// Information elements whose contents go into the fingerprint (SSIDs and channels never do)
+const IE_HT_CAPABILITIES: u8 = 45;
+const IE_EXTENDED_CAPABILITIES: u8 = 127;
+
+/// Vendor IE OUI only Apple devices send in probe requests
+const APPLE_OUI: [u8; 3] = [0x00, 0x17, 0xF2];
+
+/// FNV-1a parameters for the fingerprint hash
+const FNV_OFFSET: u32 = 0x811c_9dc5;
+const FNV_PRIME: u32 = 0x0100_0193;
+
+/// Control frames with a transmitter address (RTS, block ack) end their header after addr2
+const CTRL_HEADER_LEN: usize = 16;
+
 /// IEEE 802.11 MAC Header (simplified)
 /// Offsets: addr1 @ 4, addr2 @ 10, addr3 @ 16
 #[repr(C, packed)]
@@ -47,43 +466,82 @@ pub struct Ieee80211MacHeader {
     pub seq_ctrl: u16,
 }
 
-/// MAC address wrapper for display
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub struct MacAddress(pub [u8; 6]);
+impl Ieee80211MacHeader {
+    /// Frame type (0 management, 1 control, 2 data)
+    pub fn frame_type(&self) -> u16 {
+        let frame_control = self.frame_control;
+        (frame_control >> 2) & 0x03
+    }
 
-impl MacAddress {
-    /// Check if this is a broadcast address (FF:FF:FF:FF:FF:FF)
-    pub fn is_broadcast(&self) -> bool {
-        self.0 == [0xFF; 6]
+    /// Frame subtype
+    pub fn subtype(&self) -> u16 {
+        let frame_control = self.frame_control;
+        (frame_control >> 4) & 0x0F
     }
 
-    /// Check if this is a multicast address (first byte has LSB set)
-    pub fn is_multicast(&self) -> bool {
-        self.0[0] & 0x01 != 0
+    /// Retry bit (bit 11), the frame is a retransmission
+    pub fn is_retry(&self) -> bool {
+        let frame_control = self.frame_control;
+        frame_control & 0x0800 != 0
     }
 
-    /// Hash the MAC address using SHA-256 and return as hex string
-    pub fn hash(&self) -> [u8; 32] {
-        let mut hasher = Sha256::new();
-        hasher.update(&self.0);
-        let result = hasher.finalize();
-        let mut hash = [0u8; 32];
-        hash.copy_from_slice(&result);
-        hash
+    /// Medium time reserved by the frame (NAV) in microseconds, `None` if the field holds an
+    /// association ID (bit 15 set, PS-Poll) or is zero
+    pub fn duration_us(&self) -> Option<u32> {
+        let duration = self.duration;
+        (duration != 0 && duration & 0x8000 == 0).then_some(duration as u32)
     }
-}
 
-impl core::fmt::Display for MacAddress {
-    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        write!(
-            f,
-            "{:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X}",
-            self.0[0], self.0[1], self.0[2], self.0[3], self.0[4], self.0[5]
-        )
+    /// QoS data frames (data subtypes 8-15) carry a QoS control field
+    pub fn is_qos_data(&self) -> bool {
+        self.frame_type() == FRAME_TYPE_DATA && self.subtype() & 0x08 != 0
     }
-}
 
+    /// Both ToDS and FromDS set, a fourth address follows the sequence control field
+    fn has_addr4(&self) -> bool {
+        let frame_control = self.frame_control;
+        (frame_control >> 8) & 0x03 == 0x03
+    }
 
+    /// Offset of the QoS control field, `None` for non-QoS frames
+    pub fn qos_control_offset(&self) -> Option<usize> {
+        self.is_qos_data().then(|| if self.has_addr4() { 30 } else { 24 })
+    }
+
+    /// Full MAC header length, where the frame body starts
+    /// 24 bytes, plus 6 for addr4, 2 for QoS control and 4 for HT control (Order bit).
+    /// Control frames count up to addr2, so ACK/CTS (no transmitter address) are too short.
+    pub fn header_len(&self) -> usize {
+        if self.frame_type() == FRAME_TYPE_CTRL {
+            return CTRL_HEADER_LEN;
+        }
+        let frame_control = self.frame_control;
+        let mut len = match self.qos_control_offset() {
+            Some(offset) => offset + 2,
+            None if self.has_addr4() => 30,
+            None => 24,
+        };
+        let order = (frame_control >> 8) & 0x80 != 0;
+        if order && (self.is_qos_data() || self.frame_type() == FRAME_TYPE_MGMT) {
+            len += 4;
+        }
+        len
+    }
+
+    /// BSSID of the frame, picked by the ToDS/FromDS flags (`None` for WDS and control frames)
+    pub fn bssid(&self) -> Option<[u8; 6]> {
+        if self.frame_type() == FRAME_TYPE_CTRL {
+            return None;
+        }
+        let frame_control = self.frame_control;
+        match (frame_control >> 8) & 0x03 {
+            0b00 => Some(self.addr3),  // Management and IBSS: addr3
+            0b01 => Some(self.addr1),  // ToDS (station -> AP): addr1
+            0b10 => Some(self.addr2),  // FromDS (AP -> station): addr2
+            _ => None,
+        }
+    }
+}
 
 /// Promiscuous mode RX callback
 /// WARNING: Called directly in WiFi driver task - keep it minimal!
@@ -108,51 +566,180 @@ unsafe extern "C" fn promiscuous_rx_callback(
     // Get channel
     let channel = rx_ctrl.channel() as u8;
 
-    // Skip if payload too small for MAC header (minimum 24 bytes)
-    if sig_len < 24 {
+    // Get channel width (HT20/HT40+/HT40-)
+    let bandwidth = Bandwidth::from_secondary_channel(rx_ctrl.secondary_channel());
+
+    // Capture threshold (configurable, 24 bytes by default)
+    if sig_len < MIN_PACKET_LEN.load(Ordering::Relaxed) as u32 {
         return;
     }
 
     // Get pointer to payload (IEEE 802.11 frame)
     let payload_ptr = (*pkt).payload.as_ptr();
 
+    // Raw frame dump for Wireshark, before any parsing so parser bugs show up in it
+    #[cfg(feature = "pcap")]
+    {
+        let frame_len = (sig_len as usize).saturating_sub(FCS_LEN);
+        let frame = core::slice::from_raw_parts(payload_ptr, frame_len);
+        crate::pcap::capture(rx_ctrl.timestamp(), frame);
+    }
+
+    // Copy the fixed header out (zero-padded) so short frames never read past their end
+    let mut header_bytes = [0u8; core::mem::size_of::<Ieee80211MacHeader>()];
+    let copy_len = (sig_len as usize).min(header_bytes.len());
+    core::ptr::copy_nonoverlapping(payload_ptr, header_bytes.as_mut_ptr(), copy_len);
+    let mac_header = header_bytes.as_ptr() as *const Ieee80211MacHeader;
+
+    // Airtime for the channel utilization estimate, every captured frame counts
+    BUSY_US.fetch_add(airtime_us(&*mac_header, sig_len), Ordering::Relaxed);
+
+    // Frame type mix, counted before any filtering
+    match (*mac_header).frame_type() {
+        FRAME_TYPE_MGMT => MGMT_COUNT.fetch_add(1, Ordering::Relaxed),
+        FRAME_TYPE_DATA => DATA_COUNT.fetch_add(1, Ordering::Relaxed),
+        FRAME_TYPE_CTRL => CTRL_COUNT.fetch_add(1, Ordering::Relaxed),
+        _ => 0,
+    };
+
+    // Parse threshold: the frame type's header (with the transmitter address) must be complete
+    if (sig_len as usize) < (*mac_header).header_len() {
+        return;
+    }
+
     // Parse MAC header
-    let mac_header = payload_ptr as *const Ieee80211MacHeader;
     let source_mac = MacAddress((*mac_header).addr2);
 
+    // Get timestamp in microseconds (RTC-backed, survives deep sleep)
+    let timestamp = crate::power::timestamp_us();
+
+    // Information elements of probe requests, probe responses and beacons
+    let ies = management_ies(&*mac_header, payload_ptr, sig_len as usize);
+
+    // Survey APs before any filtering, our own AP's beacon rate is interesting too
+    // Beacons are counted, probe responses only add the AP and its WPS flag
+    if let Some(ies) = ies.filter(|_| BEACON_STATS.load(Ordering::Relaxed)) {
+        let subtype = (*mac_header).subtype();
+        if subtype == SUBTYPE_BEACON || subtype == SUBTYPE_PROBE_RESPONSE {
+            let beacons = (subtype == SUBTYPE_BEACON) as u32;
+            let wps_enabled = has_vendor_ie(ies, WPS_OUI, WPS_VENDOR_TYPE);
+            record_ap((*mac_header).addr3, rssi, timestamp, beacons, wps_enabled);
+        }
+    }
+
+    // Probe-only mode: devices actively scanning, nothing else gets forwarded
+    if PROBES_ONLY.load(Ordering::Relaxed)
+        && ((*mac_header).frame_type() != FRAME_TYPE_MGMT
+            || (*mac_header).subtype() != SUBTYPE_PROBE_REQUEST)
+    {
+        return;
+    }
+
+    // Skip traffic within our own uplink AP's BSS
+    let own_bssid = OWN_BSSID.load(Ordering::Relaxed);
+    if own_bssid != NO_BSSID
+        && (*mac_header).bssid().is_some_and(|bssid| pack_mac(&bssid) == own_bssid)
+    {
+        return;
+    }
+
     // Skip broadcast/multicast for device tracking
     if source_mac.is_broadcast() || source_mac.is_multicast() {
         return;
     }
 
+    // Traffic priority of QoS data frames, the TID is the low bits of the QoS control field
+    let access_category = (*mac_header)
+        .qos_control_offset()
+        .map(|offset| AccessCategory::from_tid(*payload_ptr.add(offset) & 0x0F));
+
+    // Probe requests identify the device model/OS through their information elements
+    let fingerprint = ies
+        .filter(|_| (*mac_header).subtype() == SUBTYPE_PROBE_REQUEST)
+        .map(fingerprint_ies);
+    let vendor_ies = ies.map(collect_vendor_ies).unwrap_or_default();
+
+    // A device joining an AP, always forwarded when enabled (they are rare and mark presence)
+    let join = CAPTURE_JOIN_FRAMES
+        .load(Ordering::Relaxed)
+        .then(|| join_kind(&*mac_header))
+        .flatten()
+        .map(|kind| Join { kind, bssid: (*mac_header).addr3 });
+
+    // Count every source for the unique device estimate, not just the sampled ones
+    record_unique(&source_mac);
+    if ARRIVAL_STATS.load(Ordering::Relaxed) {
+        record_arrival(source_mac, timestamp);
+    }
+
     // Increment packet counter
     let count = PACKET_COUNT.fetch_add(1, Ordering::SeqCst);
+    let retry = (*mac_header).is_retry();
+    if retry {
+        RETRY_COUNT.fetch_add(1, Ordering::Relaxed);
+    }
+    if count == 0 {
+        log::info!("Sniffer callback running on {:?}", esp_idf_svc::hal::cpu::core());
+    }
 
-    // Get timestamp in microseconds
-    let timestamp = esp_timer_get_time() as u64;
-
-    // Rate limit: only send 1 in every SEND_RATE packets
-    if count % SEND_RATE == 0 {
-        // Send event to MQTT publisher (non-blocking, drops if full)
-        if let Ok(guard) = EVENT_SENDER.try_lock() {
-            if let Some(sender) = guard.as_ref() {
-                // Hash the MAC address for privacy
-                let mac_hash = source_mac.hash();
-                
-                let event = DeviceEvent {
-                    mac_hash,
-                    rssi,
-                    channel,
-                    timestamp,
-                };
-                // Use try_send to avoid blocking - drop event if channel full
-                if sender.try_send(event).is_ok() {
-                    SENT_COUNT.fetch_add(1, Ordering::Relaxed);
-                } else {
-                    DROPPED_COUNT.fetch_add(1, Ordering::Relaxed);
-                }
-            }
+    if join.is_some() {
+        send_event(DeviceEvent {
+            mac_hash: source_mac.hash(),
+            rssi,
+            channel,
+            timestamp,
+            randomized: source_mac.is_randomized(),
+            bandwidth,
+            access_category,
+            fingerprint,
+            vendor_ies,
+            join,
+            presence: None,
+            frames: 1,
+            retries: retry as u32,
+        });
+    } else if FIRST_SEEN_ONLY.load(Ordering::Relaxed) {
+        // Only a device's first frame is published, later ones just keep it present
+        let sighting = Sighting { mac: source_mac, last_seen: timestamp, rssi, channel, bandwidth };
+        if record_sighting(sighting) {
+            send_event(DeviceEvent {
+                mac_hash: source_mac.hash(),
+                rssi,
+                channel,
+                timestamp,
+                randomized: source_mac.is_randomized(),
+                bandwidth,
+                access_category,
+                fingerprint,
+                vendor_ies,
+                join: None,
+                presence: Some(Presence::Arrived),
+                frames: 1,
+                retries: retry as u32,
+            });
         }
+    } else if AGGREGATION.load(Ordering::Relaxed) != 0 {
+        // Collect into the device's accumulator, the flush thread publishes once per window
+        let details = FrameDetails { bandwidth, access_category, fingerprint, vendor_ies };
+        accumulate(source_mac, rssi, channel, retry, details, timestamp);
+    } else if sample(FrameKind::of(&*mac_header)) {
+        // Rate limit: only send 1 in every N packets of this frame kind
+        send_event(DeviceEvent {
+            // Hash the MAC address for privacy
+            mac_hash: source_mac.hash(),
+            rssi,
+            channel,
+            timestamp,
+            randomized: source_mac.is_randomized(),
+            bandwidth,
+            access_category,
+            fingerprint,
+            vendor_ies,
+            join: None,
+            presence: None,
+            frames: 1,
+            retries: retry as u32,
+        });
     }
 
     // Log every 100th packet to avoid flooding
@@ -164,19 +751,509 @@ unsafe extern "C" fn promiscuous_rx_callback(
     }
 }
 
+/// Fold bytes into an FNV-1a hash
+fn fnv1a(hash: u32, bytes: &[u8]) -> u32 {
+    bytes.iter().fold(hash, |hash, &byte| (hash ^ byte as u32).wrapping_mul(FNV_PRIME))
+}
+
+/// Information elements of probe requests, probe responses and beacons, `None` for other frames
+/// `payload` must point at a captured frame of `sig_len` bytes (including the FCS)
+unsafe fn management_ies<'a>(
+    header: &Ieee80211MacHeader,
+    payload: *const u8,
+    sig_len: usize,
+) -> Option<&'a [u8]> {
+    if header.frame_type() != FRAME_TYPE_MGMT {
+        return None;
+    }
+    let fixed_len = match header.subtype() {
+        SUBTYPE_PROBE_REQUEST => 0,
+        SUBTYPE_PROBE_RESPONSE | SUBTYPE_BEACON => BEACON_FIXED_LEN,
+        _ => return None,
+    };
+    let start = header.header_len() + fixed_len;
+    let len = sig_len.saturating_sub(start + FCS_LEN);
+    Some(core::slice::from_raw_parts(payload.add(start), len))
+}
+
+/// Join frame sent by a device to an AP, `None` for other frames
+/// Authentication frames from the AP (transmitter is the BSSID) are the AP's reply, not a join
+fn join_kind(header: &Ieee80211MacHeader) -> Option<JoinKind> {
+    if header.frame_type() != FRAME_TYPE_MGMT {
+        return None;
+    }
+    let (addr2, addr3) = (header.addr2, header.addr3);
+    match header.subtype() {
+        SUBTYPE_ASSOC_REQUEST => Some(JoinKind::Association),
+        SUBTYPE_REASSOC_REQUEST => Some(JoinKind::Reassociation),
+        SUBTYPE_AUTHENTICATION if addr2 != addr3 => Some(JoinKind::Authentication),
+        _ => None,
+    }
+}
+
+/// Fingerprint a probe request body from the order of its information elements
+/// HT capability info, extended capabilities and vendor OUIs are hashed as well, they differ
+/// between OSes but not between probes of one device. Stops at the first truncated IE.
+fn fingerprint_ies(ies: &[u8]) -> Fingerprint {
+    let mut hash = FNV_OFFSET;
+    let mut apple = false;
+    for (tag, data) in InformationElements(ies) {
+        hash = fnv1a(hash, &[tag]);
+        match tag {
+            IE_HT_CAPABILITIES => hash = fnv1a(hash, &data[..data.len().min(2)]),
+            IE_EXTENDED_CAPABILITIES => hash = fnv1a(hash, data),
+            IE_VENDOR_SPECIFIC => {
+                // OUI and vendor type
+                let vendor = &data[..data.len().min(4)];
+                hash = fnv1a(hash, vendor);
+                apple |= vendor.starts_with(&APPLE_OUI);
+            }
+            _ => {}
+        }
+    }
+    Fingerprint {
+        id: hash,
+        device_class: apple.then_some("apple"),
+    }
+}
+
+/// Pack a MAC into the low 48 bits of a u64
+fn pack_mac(mac: &[u8; 6]) -> u64 {
+    mac.iter().fold(0u64, |acc, &byte| (acc << 8) | byte as u64)
+}
+
+/// Skip frames to, from and within this BSS (our uplink AP), `None` to capture them again
+pub fn set_own_bssid(bssid: Option<[u8; 6]>) {
+    OWN_BSSID.store(bssid.as_ref().map_or(NO_BSSID, pack_mac), Ordering::Relaxed);
+    if let Some(bssid) = bssid {
+        log::info!("Skipping frames in our own BSS {}", MacAddress(bssid));
+    }
+}
+
+/// Add `beacons` (0 for a probe response) to the AP `bssid`, never blocks the WiFi task
+fn record_ap(bssid: [u8; 6], rssi: i8, timestamp: u64, beacons: u32, wps_enabled: bool) {
+    let Ok(mut table) = BEACONS.try_lock() else {
+        return;
+    };
+
+    if let Some(ap) = table.iter_mut().flatten().find(|ap| ap.bssid == bssid) {
+        ap.beacons = ap.beacons.saturating_add(beacons);
+        ap.last_seen = timestamp;
+        ap.rssi = rssi;
+        ap.wps_enabled = wps_enabled;
+        return;
+    }
+
+    let entry = ApBeacons { bssid, beacons, last_seen: timestamp, rssi, wps_enabled };
+    if let Some(slot) = table.iter_mut().find(|slot| slot.is_none()) {
+        *slot = Some(entry);
+    } else if let Some(oldest) = table
+        .iter_mut()
+        .min_by_key(|slot| slot.map_or(0, |ap| ap.last_seen))
+    {
+        *oldest = Some(entry);
+    }
+}
+
+/// Beacon counts per AP, strongest first (empty unless beacon stats are enabled)
+pub fn beacon_stats() -> Vec<ApBeacons> {
+    let mut aps: Vec<ApBeacons> = match BEACONS.lock() {
+        Ok(table) => table.iter().flatten().copied().collect(),
+        Err(_) => Vec::new(),
+    };
+    aps.sort_by_key(|ap| core::cmp::Reverse(ap.rssi));
+    aps
+}
+
+/// Whether beacon stats are being collected
+pub fn beacon_stats_enabled() -> bool {
+    BEACON_STATS.load(Ordering::Relaxed)
+}
+
+/// Update the inter-arrival stats of `mac` with a frame at `timestamp`, never blocks the WiFi task
+fn record_arrival(mac: MacAddress, timestamp: u64) {
+    let Ok(mut table) = ARRIVALS.try_lock() else {
+        return;
+    };
+
+    if let Some(device) = table.iter_mut().flatten().find(|device| device.mac == mac) {
+        let gap_ms = timestamp.saturating_sub(device.last_seen) as f32 / 1000.0;
+        device.last_seen = timestamp;
+        device.gaps = device.gaps.saturating_add(1);
+        let delta = gap_ms - device.mean_ms;
+        device.mean_ms += delta / device.gaps as f32;
+        device.m2 += delta * (gap_ms - device.mean_ms);
+        return;
+    }
+
+    let entry = Arrivals { mac, last_seen: timestamp, gaps: 0, mean_ms: 0.0, m2: 0.0 };
+    if let Some(slot) = table.iter_mut().find(|slot| slot.is_none()) {
+        *slot = Some(entry);
+    } else if let Some(oldest) = table
+        .iter_mut()
+        .min_by_key(|slot| slot.map_or(0, |device| device.last_seen))
+    {
+        *oldest = Some(entry);
+    }
+}
+
+/// Inter-arrival stats of the tracked devices that sent at least two frames, busiest first
+pub fn arrival_stats() -> Vec<DeviceArrivals> {
+    // Copy out and release the lock quickly, hashing happens outside it
+    let table = match ARRIVALS.lock() {
+        Ok(table) => *table,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut devices: Vec<DeviceArrivals> = table
+        .iter()
+        .flatten()
+        .filter(|device| device.gaps > 0)
+        .map(|device| DeviceArrivals {
+            mac_hash: device.mac.hash(),
+            gaps: device.gaps,
+            mean_ms: device.mean_ms as u32,
+            stddev_ms: (device.m2 / device.gaps as f32).sqrt() as u32,
+        })
+        .collect();
+    devices.sort_by_key(|device| device.mean_ms);
+    devices
+}
+
+/// Whether inter-arrival stats are being collected
+pub fn arrival_stats_enabled() -> bool {
+    ARRIVAL_STATS.load(Ordering::Relaxed)
+}
+
+/// Mark a device present, `true` if it wasn't (it just arrived)
+/// A full table evicts the least recently heard device, which departs early. If the table
+/// is busy being swept the frame is ignored, the device's next frame counts instead.
+fn record_sighting(sighting: Sighting) -> bool {
+    let Ok(mut table) = PRESENCE.try_lock() else {
+        return false;
+    };
+
+    if let Some(present) = table.iter_mut().flatten().find(|present| present.mac == sighting.mac) {
+        *present = sighting;
+        return false;
+    }
+
+    if let Some(slot) = table.iter_mut().find(|slot| slot.is_none()) {
+        *slot = Some(sighting);
+    } else if let Some(oldest) = table
+        .iter_mut()
+        .min_by_key(|slot| slot.map_or(0, |present| present.last_seen))
+    {
+        if let Some(evicted) = oldest.replace(sighting) {
+            send_departure(&evicted);
+        }
+    }
+    true
+}
+
+/// Publish departure events for devices silent longer than the departure timeout
+/// Called periodically from the main loop, does nothing outside first-seen mode
+pub fn sweep_departures() {
+    let timeout = DEPARTURE_TIMEOUT_US.load(Ordering::Relaxed);
+    if !FIRST_SEEN_ONLY.load(Ordering::Relaxed) || timeout == 0 {
+        return;
+    }
+    let now = crate::power::timestamp_us();
+
+    // Take the departed devices out under the lock, hashing happens outside it
+    let mut departed = Vec::new();
+    if let Ok(mut table) = PRESENCE.lock() {
+        for slot in table.iter_mut() {
+            if slot.is_some_and(|present| now.saturating_sub(present.last_seen) > timeout) {
+                departed.extend(slot.take());
+            }
+        }
+    }
+
+    for sighting in &departed {
+        send_departure(sighting);
+    }
+}
+
+/// Publish that a device left, with its last reading
+fn send_departure(sighting: &Sighting) {
+    send_event(DeviceEvent {
+        mac_hash: sighting.mac.hash(),
+        rssi: sighting.rssi,
+        channel: sighting.channel,
+        timestamp: sighting.last_seen,
+        randomized: sighting.mac.is_randomized(),
+        bandwidth: sighting.bandwidth,
+        access_category: None,
+        fingerprint: None,
+        vendor_ies: VendorIes::default(),
+        join: None,
+        presence: Some(Presence::Departed),
+        frames: 0,
+        retries: 0,
+    });
+}
+
+/// Send event to MQTT publisher (non-blocking, drops if full)
+fn send_event(event: DeviceEvent) {
+    let Ok(mut pending) = PENDING_EVENTS.try_lock() else {
+        // Being flushed right now, the event is lost
+        DROPPED_COUNT.fetch_add(1, Ordering::Relaxed);
+        return;
+    };
+    pending.push(event);
+    if pending.is_full() {
+        send_pending(&mut pending);
+    }
+}
+
+/// Send the events collected so far without waiting for the batch to fill,
+/// called about once a second so quiet periods don't hold events back
+pub fn flush_events() {
+    if let Ok(mut pending) = PENDING_EVENTS.lock() {
+        if !pending.is_empty() {
+            send_pending(&mut pending);
+        }
+    }
+}
+
+/// Hand the pending batch to the publisher and start a new one, counting it as dropped if
+/// it can't be sent. The batch is moved into the channel straight from its static slot,
+/// not copied through the caller's stack (the WiFi task's, when called from the callback)
+fn send_pending(pending: &mut EventBatch) {
+    let count = pending.len() as u32;
+    let sent = match EVENT_SENDER.try_lock() {
+        // Use try_send to avoid blocking - drop the whole batch if channel full
+        Ok(guard) => guard
+            .as_ref()
+            .is_some_and(|sender| sender.try_send(core::mem::take(pending)).is_ok()),
+        // Sender being set or cleared, or another thread sending right now
+        Err(_) => false,
+    };
+    if sent {
+        SENT_COUNT.fetch_add(count, Ordering::Relaxed);
+    } else {
+        pending.clear();
+        DROPPED_COUNT.fetch_add(count, Ordering::Relaxed);
+    }
+}
+
+/// Add a reading to its device's accumulator, never blocks the WiFi task
+fn accumulate(
+    mac: MacAddress,
+    rssi: i8,
+    channel: u8,
+    retry: bool,
+    details: FrameDetails,
+    timestamp: u64,
+) {
+    let Ok(mut table) = AGGREGATES.try_lock() else {
+        // Being flushed right now, the reading is lost
+        DROPPED_COUNT.fetch_add(1, Ordering::Relaxed);
+        return;
+    };
+
+    if let Some(acc) = table.iter_mut().flatten().find(|acc| acc.mac == mac) {
+        acc.max_rssi = acc.max_rssi.max(rssi);
+        acc.rssi_sum += rssi as i32;
+        acc.count += 1;
+        acc.retries += retry as u32;
+        acc.channel = channel;
+        acc.details.update(details);
+        acc.timestamp = timestamp;
+    } else if let Some(slot) = table.iter_mut().find(|slot| slot.is_none()) {
+        *slot = Some(Accumulator {
+            mac,
+            max_rssi: rssi,
+            rssi_sum: rssi as i32,
+            count: 1,
+            retries: retry as u32,
+            channel,
+            details,
+            timestamp,
+        });
+    } else {
+        // Table full, this device waits for the next window
+        DROPPED_COUNT.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Publish one event per device seen in the window and start a new window
+fn flush_aggregates() {
+    let aggregation = match AGGREGATION.load(Ordering::Relaxed) {
+        1 => Aggregation::Max,
+        2 => Aggregation::Mean,
+        _ => return,
+    };
+
+    // Take out one accumulator at a time, copying the whole table wouldn't fit the thread's
+    // stack; the lock is released while hashing. A device that appears during the flush in
+    // a slot not yet visited goes out with this window
+    for slot in 0..AGGREGATE_TABLE_SIZE {
+        let acc = match AGGREGATES.lock() {
+            Ok(mut table) => table[slot].take(),
+            Err(_) => return,
+        };
+        let Some(acc) = acc else {
+            continue;
+        };
+        let rssi = match aggregation {
+            Aggregation::Max => acc.max_rssi,
+            Aggregation::Mean => (acc.rssi_sum / acc.count as i32) as i8,
+        };
+        send_event(DeviceEvent {
+            mac_hash: acc.mac.hash(),
+            rssi,
+            channel: acc.channel,
+            timestamp: acc.timestamp,
+            randomized: acc.mac.is_randomized(),
+            bandwidth: acc.details.bandwidth,
+            access_category: acc.details.access_category,
+            fingerprint: acc.details.fingerprint,
+            vendor_ies: acc.details.vendor_ies,
+            join: None,
+            presence: None,
+            frames: acc.count,
+            retries: acc.retries,
+        });
+    }
+}
+
+/// Switch from SEND_RATE sampling to publishing one aggregated reading per device per `window`
+pub fn start_aggregation(window: Duration, aggregation: Aggregation) -> anyhow::Result<()> {
+    let mode = match aggregation {
+        Aggregation::Max => 1,
+        Aggregation::Mean => 2,
+    };
+    thread::Builder::new()
+        .stack_size(AGGREGATE_STACK_SIZE)
+        .spawn(move || {
+            let mut lowest_free = u32::MAX;
+            loop {
+                thread::sleep(window);
+                flush_aggregates();
+                flush_events();
+
+                // Report the stack high-water mark whenever it drops, to size AGGREGATE_STACK_SIZE
+                let free = unsafe { uxTaskGetStackHighWaterMark(core::ptr::null_mut()) };
+                if free < lowest_free {
+                    lowest_free = free;
+                    if free < AGGREGATE_STACK_ALERT_BYTES {
+                        log::warn!(
+                            "Aggregation thread stack nearly full: {} of {} bytes free",
+                            free,
+                            AGGREGATE_STACK_SIZE
+                        );
+                    } else {
+                        log::debug!(
+                            "Aggregation thread stack: {} of {} bytes free",
+                            free,
+                            AGGREGATE_STACK_SIZE
+                        );
+                    }
+                }
+            }
+        })?;
+    AGGREGATION.store(mode, Ordering::Relaxed);
+    log::info!("Aggregating RSSI ({:?}) over {}ms windows", aggregation, window.as_millis());
+    Ok(())
+}
+
+/// Cheap 64-bit hash of a MAC for the sketch (SHA-256 per packet is too slow in the callback)
+fn mac_hash64(mac: &MacAddress) -> u64 {
+    let mut x = pack_mac(&mac.0);
+    // SplitMix64 finalizer spreads the 48 MAC bits over all 64
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// Add a source MAC to the unique device sketch
+fn record_unique(mac: &MacAddress) {
+    let hash = mac_hash64(mac);
+    // Top bits pick the register, the rank is 1 + leading zeros of the rest
+    let index = (hash >> (64 - HLL_PRECISION)) as usize;
+    let rank = ((hash << HLL_PRECISION) | (1 << (HLL_PRECISION - 1))).leading_zeros() + 1;
+    HLL[index].fetch_max(rank as u8, Ordering::Relaxed);
+}
+
+/// Estimated number of distinct source MACs since the last `reset_unique_devices`
+/// Randomizing devices count once per MAC they use
+pub fn estimated_unique_devices() -> u64 {
+    let m = HLL_REGISTERS as f64;
+    let (sum, zeros) = HLL.iter().fold((0.0, 0u32), |(sum, zeros), register| {
+        let rank = register.load(Ordering::Relaxed);
+        (sum + 2f64.powi(-(rank as i32)), zeros + (rank == 0) as u32)
+    });
+    let alpha = 0.7213 / (1.0 + 1.079 / m);
+    let raw = alpha * m * m / sum;
+    // Linear counting is more accurate while many registers are still empty
+    let estimate = if raw <= 2.5 * m && zeros > 0 {
+        m * (m / zeros as f64).ln()
+    } else {
+        raw
+    };
+    estimate.round() as u64
+}
+
+/// Start a new unique device counting window
+pub fn reset_unique_devices() {
+    for register in &HLL {
+        register.store(0, Ordering::Relaxed);
+    }
+}
+
 /// Initialize WiFi promiscuous mode sniffer
 /// Note: When connected to WiFi, sniffs on the AP's channel (cannot change)
-pub fn start_sniffer() -> anyhow::Result<()> {
+pub fn start_sniffer(config: &SnifferConfig) -> anyhow::Result<()> {
     log::info!("Starting promiscuous mode sniffer");
 
+    // Capture management and data frames, optionally control
+    let mut filter_mask = WIFI_PROMIS_FILTER_MASK_MGMT | WIFI_PROMIS_FILTER_MASK_DATA;
+    if config.capture_control_frames {
+        filter_mask |= WIFI_PROMIS_FILTER_MASK_CTRL;
+    }
+    start(config, filter_mask)
+}
+
+/// Start the sniffer for people counting: only probe requests (devices actively scanning)
+/// are forwarded and the driver only hands us management frames, which keeps both the
+/// callback load and the MQTT volume low. `capture_control_frames` is ignored.
+pub fn start_probe_sniffer(config: &SnifferConfig) -> anyhow::Result<()> {
+    log::info!("Starting promiscuous mode sniffer for probe requests only");
+    PROBES_ONLY.store(true, Ordering::Relaxed);
+    start(config, WIFI_PROMIS_FILTER_MASK_MGMT)
+}
+
+/// Apply the capture settings, then enable promiscuous mode with `filter_mask`
+fn start(config: &SnifferConfig, filter_mask: u32) -> anyhow::Result<()> {
+    MIN_PACKET_LEN.store(config.min_packet_len, Ordering::Relaxed);
+    BEACON_STATS.store(config.beacon_stats, Ordering::Relaxed);
+    ARRIVAL_STATS.store(config.arrival_stats, Ordering::Relaxed);
+    CAPTURE_JOIN_FRAMES.store(config.capture_join_frames, Ordering::Relaxed);
+    FIRST_SEEN_ONLY.store(config.first_seen_only, Ordering::Relaxed);
+    for (kind, rate) in FrameKind::ALL.into_iter().zip(config.send_rates) {
+        if rate.is_some() {
+            set_send_rate(kind, rate);
+        }
+    }
+    set_default_send_rate(config.default_send_rate);
+    let departure_us = config.departure_timeout.map_or(0, |timeout| timeout.as_micros() as u64);
+    DEPARTURE_TIMEOUT_US.store(departure_us, Ordering::Relaxed);
+    BUSY_SINCE.store(crate::power::timestamp_us(), Ordering::Relaxed);
+    FILTER_MASK.store(filter_mask, Ordering::Relaxed);
+
+    enable_promiscuous(filter_mask, true)
+}
+
+/// Set the filter, register the callback and, unless `enable` is false, enter promiscuous mode
+fn enable_promiscuous(filter_mask: u32, enable: bool) -> anyhow::Result<()> {
     unsafe {
         // Don't set channel - use whatever channel the AP is on
         // esp_wifi_set_channel fails when connected to an AP
 
-        // Configure promiscuous filter (capture management and data frames)
-        let filter = wifi_promiscuous_filter_t {
-            filter_mask: WIFI_PROMIS_FILTER_MASK_MGMT | WIFI_PROMIS_FILTER_MASK_DATA,
-        };
+        // Configure promiscuous filter
+        let filter = wifi_promiscuous_filter_t { filter_mask };
         let ret = esp_wifi_set_promiscuous_filter(&filter);
         if ret != ESP_OK {
             anyhow::bail!("Failed to set promiscuous filter: {}", ret);
@@ -190,6 +1267,10 @@ pub fn start_sniffer() -> anyhow::Result<()> {
         }
         log::info!("Promiscuous callback registered");
 
+        if !enable {
+            return Ok(());
+        }
+
         // Enable promiscuous mode
         let ret = esp_wifi_set_promiscuous(true);
         if ret != ESP_OK {
@@ -202,13 +1283,120 @@ pub fn start_sniffer() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Bring capture back after the WiFi driver was reconnected
+///
+/// A WiFi restart can leave promiscuous mode off, so the filter and callback are applied
+/// again and promiscuous mode re-enabled unless capture is paused. Counters and tables are
+/// kept, the stats continue where they were.
+pub fn restore_sniffer() -> anyhow::Result<()> {
+    let filter_mask = FILTER_MASK.load(Ordering::Relaxed);
+    if filter_mask == 0 {
+        // Never started, nothing to restore
+        return Ok(());
+    }
+    enable_promiscuous(filter_mask, !PAUSED.load(Ordering::Relaxed))?;
+    log::info!("Sniffer restored after WiFi reconnect, {} frames captured so far", get_packet_count());
+    Ok(())
+}
 
+/// Pause capture by leaving promiscuous mode, the callback stays registered
+pub fn pause_sniffer() -> anyhow::Result<()> {
+    let ret = unsafe { esp_wifi_set_promiscuous(false) };
+    if ret != ESP_OK {
+        anyhow::bail!("Failed to disable promiscuous mode: {}", ret);
+    }
+    PAUSED.store(true, Ordering::Relaxed);
+    log::info!("Sniffer paused");
+    Ok(())
+}
+
+/// Resume capture after `pause_sniffer`
+pub fn resume_sniffer() -> anyhow::Result<()> {
+    let ret = unsafe { esp_wifi_set_promiscuous(true) };
+    if ret != ESP_OK {
+        anyhow::bail!("Failed to enable promiscuous mode: {}", ret);
+    }
+    PAUSED.store(false, Ordering::Relaxed);
+    log::info!("Sniffer resumed");
+    Ok(())
+}
+
+/// Whether capture is paused
+pub fn is_paused() -> bool {
+    PAUSED.load(Ordering::Relaxed)
+}
+
+/// Stop the sniffer and release the event sender
+/// The callback is unregistered first so the driver can't call into it during teardown,
+/// and dropping the sender lets the MQTT publisher drain its queue and return
+pub fn stop_sniffer() -> anyhow::Result<()> {
+    log::info!("Stopping promiscuous mode sniffer");
+
+    unsafe {
+        let ret = esp_wifi_set_promiscuous(false);
+        if ret != ESP_OK {
+            anyhow::bail!("Failed to disable promiscuous mode: {}", ret);
+        }
+
+        let ret = esp_wifi_set_promiscuous_rx_cb(None);
+        if ret != ESP_OK {
+            anyhow::bail!("Failed to unregister promiscuous callback: {}", ret);
+        }
+    }
+
+    // Publish the partial window and batch before the sender goes away
+    flush_aggregates();
+    flush_events();
+
+    if let Ok(mut guard) = EVENT_SENDER.lock() {
+        *guard = None;
+    }
+
+    log::info!("Promiscuous mode disabled");
+    Ok(())
+}
 
 /// Get current packet count
 pub fn get_packet_count() -> u32 {
     PACKET_COUNT.load(Ordering::SeqCst)
 }
 
+/// Rough airtime of a frame: its duration field when set, else its length at the nominal rate
+fn airtime_us(header: &Ieee80211MacHeader, sig_len: u32) -> u32 {
+    header
+        .duration_us()
+        .unwrap_or(sig_len * 8 / NOMINAL_RATE_MBPS)
+}
+
+/// Rough share of airtime (0-100%) taken by the frames captured since the previous call
+/// Only counts what the promiscuous filter lets through on the current channel, so it
+/// underestimates a channel busy with filtered frame types or frames too weak to decode
+pub fn channel_utilization() -> f32 {
+    let now = crate::power::timestamp_us();
+    let since = BUSY_SINCE.swap(now, Ordering::Relaxed);
+    let busy = BUSY_US.swap(0, Ordering::Relaxed);
+    if since == 0 || now <= since {
+        return 0.0;
+    }
+    (busy as f32 * 100.0 / (now - since) as f32).min(100.0)
+}
+
+/// Get the captured frames per frame type, to tune the filter mask
+/// Unlike `get_packet_count` these include broadcasts and frames of our own BSS
+pub fn type_counts() -> FrameTypeCounts {
+    FrameTypeCounts {
+        mgmt: MGMT_COUNT.load(Ordering::Relaxed),
+        data: DATA_COUNT.load(Ordering::Relaxed),
+        ctrl: CTRL_COUNT.load(Ordering::Relaxed),
+    }
+}
+
+/// Get the count of captured frames that were retransmissions (retry bit set)
+/// High against `get_packet_count` points to a congested channel or weak links
+pub fn get_retry_count() -> u32 {
+    RETRY_COUNT.load(Ordering::Relaxed)
+}
+
 /// Get dropped event count (channel was full)
 pub fn get_dropped_count() -> u32 {
     DROPPED_COUNT.load(Ordering::Relaxed)
@@ -219,4 +1407,59 @@ pub fn get_sent_count() -> u32 {
     SENT_COUNT.load(Ordering::Relaxed)
 }
 
+/// Zero every counter and return the values just cleared
+///
+/// Each counter is swapped rather than loaded and stored, so a frame counted meanwhile
+/// lands in either the returned interval or the next one, never in neither. The counters
+/// are swapped one after another though, a frame can be in the returned `captured` while
+/// its `sent` goes to the next interval. The rate base is dropped as well, so the next
+/// `stats_snapshot` reports no rates instead of rates against the old totals.
+pub fn reset_all_counters() -> SnifferCounters {
+    let counters = SnifferCounters {
+        captured: PACKET_COUNT.swap(0, Ordering::SeqCst),
+        sent: SENT_COUNT.swap(0, Ordering::Relaxed),
+        dropped: DROPPED_COUNT.swap(0, Ordering::Relaxed),
+        retries: RETRY_COUNT.swap(0, Ordering::Relaxed),
+        types: FrameTypeCounts {
+            mgmt: MGMT_COUNT.swap(0, Ordering::Relaxed),
+            data: DATA_COUNT.swap(0, Ordering::Relaxed),
+            ctrl: CTRL_COUNT.swap(0, Ordering::Relaxed),
+        },
+    };
+    if let Ok(mut last) = LAST_SNAPSHOT.lock() {
+        *last = None;
+    }
+    counters
+}
+
+/// Read all counters at once and derive rates since the previous call
+/// The counters are loaded back-to-back so they describe (almost) the same moment, unlike
+/// separate getter calls spread over a log statement
+pub fn stats_snapshot() -> SnifferStats {
+    let now = Instant::now();
+    let mut stats = SnifferStats {
+        captured: get_packet_count(),
+        sent: get_sent_count(),
+        dropped: get_dropped_count(),
+        retries: get_retry_count(),
+        types: type_counts(),
+        channel_utilization: channel_utilization(),
+        captured_per_sec: 0.0,
+        sent_per_sec: 0.0,
+        dropped_per_sec: 0.0,
+    };
 
+    if let Ok(mut last) = LAST_SNAPSHOT.lock() {
+        if let Some((at, previous)) = *last {
+            let secs = now.duration_since(at).as_secs_f32();
+            if secs > 0.0 {
+                let rate = |count: u32, before: u32| count.wrapping_sub(before) as f32 / secs;
+                stats.captured_per_sec = rate(stats.captured, previous.captured);
+                stats.sent_per_sec = rate(stats.sent, previous.sent);
+                stats.dropped_per_sec = rate(stats.dropped, previous.dropped);
+            }
+        }
+        *last = Some((now, stats));
+    }
+    stats
+}
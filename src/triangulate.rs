@@ -2,33 +2,47 @@
 //!
 //! Implements multiple positioning algorithms:
 //! - **Trilateration**: Non-linear least squares optimization using RSSI-to-distance conversion
+//! - **Weighted Least Squares**: Closed-form linearized multilateration, weighted by signal strength
+//! - **3D Positioning**: Height is solved too when stations are mounted at different heights
+//! - **Circle Intersection**: Two-station fallback using the intersection of the RSSI circles
 //! - **Weighted Centroid**: Fallback when trilateration doesn't converge
 //! - **Position Smoothing**: Exponential moving average to reduce jitter
 //!
-//! The algorithm converts RSSI values to estimated distances using the log-distance
-//! path loss model, then uses gradient descent to find the position that minimizes
+//! The algorithm converts RSSI values to estimated distances using a `DistanceModel`
+//! (the log-distance path loss model by default), then uses gradient descent to find the position that minimizes
 //! the sum of squared distance errors.
 
+use crate::error::{Result, SnifferError};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 
-/// A calculated 2D position in meters
+/// A calculated position in meters, `z` is height and stays 0 for flat 2D setups
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq)]
 pub struct Position {
     pub x: f32,
     pub y: f32,
+    #[serde(default)]
+    pub z: f32,
 }
 
 impl Position {
     pub fn new(x: f32, y: f32) -> Self {
-        Self { x, y }
+        Self { x, y, z: 0.0 }
+    }
+
+    pub fn new_3d(x: f32, y: f32, z: f32) -> Self {
+        Self { x, y, z }
     }
 
     /// Calculate Euclidean distance to another position
     pub fn distance_to(&self, other: &Position) -> f32 {
         let dx = self.x - other.x;
         let dy = self.y - other.y;
-        (dx * dx + dy * dy).sqrt()
+        let dz = self.z - other.z;
+        (dx * dx + dy * dy + dz * dz).sqrt()
     }
 
     /// Linear interpolation between two positions
@@ -36,12 +50,116 @@ impl Position {
         Position {
             x: self.x + (other.x - self.x) * t,
             y: self.y + (other.y - self.y) * t,
+            z: self.z + (other.z - self.z) * t,
+        }
+    }
+}
+
+/// Result of a position solve, with fit quality
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct PositionEstimate {
+    /// Solved (and smoothed) position
+    pub position: Position,
+    /// Weighted RMS difference between solved and RSSI-estimated station distances, in meters
+    pub residual: f32,
+    /// Number of stations that contributed
+    pub station_count: usize,
+    /// Estimated velocity, set by `PositionTracker` once a device has moved between updates
+    pub velocity: Option<Velocity>,
+    /// Velocity exceeded `max_speed` and was clamped, usually a sign of a bad solve
+    pub implausible_speed: bool,
+    /// Newest reading timestamp used for this estimate, in milliseconds
+    pub timestamp: u64,
+    /// Fewer stations than needed for trilateration, the position is a rough fallback
+    pub low_confidence: bool,
+    /// Estimated position error radius in meters, from fit residual and station geometry
+    pub estimated_error: f32,
+    /// Geometric dilution of precision of the stations around the position, about 1 when they
+    /// surround it and growing as they bunch up on one side. `None` when their directions
+    /// can't fix a position at all (a single station, or all in line with the device)
+    pub gdop: Option<f32>,
+}
+
+/// Velocity vector in meters per second
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+pub struct Velocity {
+    pub vx: f32,
+    pub vy: f32,
+}
+
+impl Velocity {
+    pub fn speed(&self) -> f32 {
+        (self.vx * self.vx + self.vy * self.vy).sqrt()
+    }
+
+    /// Scale down to at most `max_speed`, returns true if it was too fast
+    fn clamp_speed(&mut self, max_speed: f32) -> bool {
+        let speed = self.speed();
+        if speed <= max_speed {
+            return false;
         }
+        self.vx *= max_speed / speed;
+        self.vy *= max_speed / speed;
+        true
     }
 }
 
+/// How successive position solves are combined
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SmoothingMode {
+    /// Exponential moving average using `smoothing_factor`
+    #[default]
+    Exponential,
+    /// Constant-velocity Kalman filter, tracks moving devices and estimates velocity
+    Kalman,
+    /// No smoothing: every update is the raw solve of the latest readings, for calibration.
+    /// Positions jitter by design; `rssi_aggregation` and reading count weights are ignored
+    /// and velocity is unsmoothed
+    Passthrough,
+}
+
+/// How the recent readings from one station are combined before solving
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RssiAggregation {
+    /// Use only the newest reading
+    #[default]
+    Latest,
+    /// Average the recent readings
+    Mean,
+    /// Median of the recent readings, robust to occasional strong reflections
+    Median,
+}
+
+/// Fallback for devices seen by too few stations to trilaterate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FewStationsMode {
+    /// Report no position
+    #[default]
+    None,
+    /// RSSI-weighted centroid of the one or two stations that see the device
+    Centroid,
+    /// Intersection of the RSSI circles when exactly two stations see the device
+    CircleIntersection,
+}
+
+/// Algorithm used to solve a position from three or more stations
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SolveMode {
+    /// Iterative gradient descent on the non-linear distance error
+    #[default]
+    GradientDescent,
+    /// Closed-form weighted least squares on the linearized system
+    WeightedLeastSquares,
+    /// Damped Gauss-Newton on the non-linear distance error, robust to poor station geometry
+    GaussNewton,
+}
+
 /// Per-station calibration parameters for RSSI-to-distance conversion
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CalibrationParams {
     /// Reference RSSI at 1 meter distance (typically -40 to -50 dBm)
     #[serde(default = "default_rssi_at_1m")]
@@ -50,6 +168,10 @@ pub struct CalibrationParams {
     /// Path loss exponent (2.0 = free space, 2.5-4.0 = indoor)
     #[serde(default = "default_path_loss_exponent")]
     pub path_loss_exponent: f32,
+
+    /// Added to every reading from this station to correct hardware bias (dB)
+    #[serde(default)]
+    pub rssi_offset: f32,
 }
 
 fn default_rssi_at_1m() -> f32 {
@@ -65,7 +187,88 @@ impl Default for CalibrationParams {
         Self {
             rssi_at_1m: default_rssi_at_1m(),
             path_loss_exponent: default_path_loss_exponent(),
+            rssi_offset: 0.0,
+        }
+    }
+}
+
+impl CalibrationParams {
+    /// Back-solve the path loss exponent from an RSSI observed at a known distance
+    ///
+    /// `rssi` is the raw reading, `rssi_offset` is applied first. Returns `None` when the distance is too close to 1m to tell exponents apart
+    /// or the result is outside the physically plausible 1.5-6.0 range.
+    pub fn fit_path_loss_exponent(&self, rssi: f32, distance: f32) -> Option<f32> {
+        let log_distance = distance.log10();
+        if log_distance.abs() < 0.1 {
+            return None;
+        }
+
+        let exponent = (self.rssi_at_1m - (rssi + self.rssi_offset)) / (10.0 * log_distance);
+        (1.5..=6.0).contains(&exponent).then_some(exponent)
+    }
+}
+
+/// Converts an RSSI reading into an estimated distance in meters
+pub trait DistanceModel: Debug + Send + Sync {
+    fn distance(&self, rssi: f32, calibration: &CalibrationParams) -> f32;
+}
+
+/// Log-distance path loss model, uses the per-station calibration
+///
+/// Formula: distance = 10^((rssi_at_1m - rssi) / (10 * path_loss_exponent))
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogDistanceModel;
+
+impl DistanceModel for LogDistanceModel {
+    fn distance(&self, rssi: f32, calibration: &CalibrationParams) -> f32 {
+        let exponent = (calibration.rssi_at_1m - rssi) / (10.0 * calibration.path_loss_exponent);
+        10.0_f32.powf(exponent)
+    }
+}
+
+/// Empirical model interpolating between measured (RSSI, distance) pairs
+///
+/// Interpolation is linear in log-distance, so RSSI outside the measured range
+/// extrapolates along the nearest segment like a local path loss fit.
+/// Per-station calibration is ignored.
+#[derive(Debug, Clone)]
+pub struct LookupTableModel {
+    /// (rssi, log10(distance)) sorted by descending RSSI
+    points: Vec<(f32, f32)>,
+}
+
+impl LookupTableModel {
+    /// Build from (rssi, distance) pairs, a `Config` error unless there are at least two
+    /// distinct RSSI values and all distances are positive
+    pub fn new(points: &[(f32, f32)]) -> Result<Self> {
+        if points.iter().any(|&(_, d)| d <= 0.0) {
+            return Err(SnifferError::Config("distance table distances must be positive".into()));
+        }
+
+        let mut points: Vec<(f32, f32)> = points.iter().map(|&(r, d)| (r, d.log10())).collect();
+        points.sort_by(|a, b| b.0.total_cmp(&a.0));
+        points.dedup_by(|a, b| a.0 == b.0);
+        if points.len() < 2 {
+            return Err(SnifferError::Config(
+                "distance table needs two distinct RSSI values".into(),
+            ));
         }
+        Ok(Self { points })
+    }
+}
+
+impl DistanceModel for LookupTableModel {
+    fn distance(&self, rssi: f32, _calibration: &CalibrationParams) -> f32 {
+        // Segment containing rssi, or the nearest end segment
+        let i = self
+            .points
+            .windows(2)
+            .position(|w| rssi >= w[1].0)
+            .unwrap_or(self.points.len() - 2);
+        let ((r0, l0), (r1, l1)) = (self.points[i], self.points[i + 1]);
+
+        let t = (rssi - r0) / (r1 - r0);
+        10.0_f32.powf(l0 + (l1 - l0) * t)
     }
 }
 
@@ -75,12 +278,14 @@ pub struct StationData {
     pub id: String,
     pub x: f32,
     pub y: f32,
+    /// Mounting height in meters
+    pub z: f32,
     pub calibration: CalibrationParams,
 }
 
 impl StationData {
     pub fn position(&self) -> Position {
-        Position::new(self.x, self.y)
+        Position::new_3d(self.x, self.y, self.z)
     }
 }
 
@@ -88,15 +293,77 @@ impl StationData {
 #[derive(Debug, Clone)]
 pub struct RssiReading {
     pub rssi: i8,
+    /// Time the reading was taken, in milliseconds (used for Kalman time steps)
     pub timestamp: u64,
 }
 
+/// Lower bound on ranging error as a fraction of the estimated distance
+const MIN_RANGE_ERROR_RATIO: f32 = 0.2;
+
+/// Station position variance (m²) below which stations are considered coincident
+const MIN_STATION_SPREAD: f32 = 1e-4;
+
+/// Smallest-to-largest spread ratio below which stations are considered collinear
+const MIN_GEOMETRY_CONDITION: f32 = 1e-3;
+
+/// Degenerate geometry is logged on the first occurrence and then every this many
+const DEGENERATE_LOG_EVERY: u64 = 100;
+
+static DEGENERATE_SOLVES: AtomicU64 = AtomicU64::new(0);
+
+/// Warn about an unsolvable station layout without flooding the log on every update
+fn warn_degenerate(reason: &str, measurements: &[DistanceMeasurement]) {
+    let count = DEGENERATE_SOLVES.fetch_add(1, Ordering::Relaxed);
+    if count % DEGENERATE_LOG_EVERY == 0 {
+        let stations: Vec<&str> = measurements.iter().map(|m| m.station_id.as_str()).collect();
+        log::warn!(
+            "Cannot trilaterate, {} ({}), no position reported ({} times so far)",
+            reason,
+            stations.join(", "),
+            count + 1
+        );
+    }
+}
+
+/// Levenberg-Marquardt damping, relative to the mean diagonal of the normal equations
+const GAUSS_NEWTON_DAMPING: f32 = 1e-3;
+
+/// Leaving an outlier out must shrink the residual below this fraction of the full fit
+const OUTLIER_IMPROVEMENT_RATIO: f32 = 0.5;
+
+/// Minimum spread in station heights (meters) before height is solved for
+const MIN_HEIGHT_SPREAD: f32 = 0.1;
+
+/// Floor id used for stations that don't specify one
+pub const DEFAULT_FLOOR: &str = "default";
+
 /// Trait to abstract over different station config types
 pub trait StationLike {
     fn id(&self) -> &str;
     fn x(&self) -> f32;
     fn y(&self) -> f32;
     fn calibration(&self) -> CalibrationParams;
+
+    /// Mounting height in meters, stations at different heights enable 3D solving
+    fn z(&self) -> f32 {
+        0.0
+    }
+
+    /// Floor (or room) the station is installed on, positions are solved per floor
+    fn floor(&self) -> &str {
+        DEFAULT_FLOOR
+    }
+}
+
+/// Convert any station config into the triangulator's internal representation
+fn to_station_data<S: StationLike>(s: &S) -> StationData {
+    StationData {
+        id: s.id().to_string(),
+        x: s.x(),
+        y: s.y(),
+        z: s.z(),
+        calibration: s.calibration(),
+    }
 }
 
 /// Configuration for the positioning algorithm
@@ -106,6 +373,21 @@ pub struct TriangulatorConfig {
     /// Recommended: 0.3-0.5 for smooth tracking
     pub smoothing_factor: f32,
 
+    /// Solver used when enough stations are available for trilateration
+    pub solve_mode: SolveMode,
+
+    /// Smoothing applied by `PositionTracker`
+    pub smoothing_mode: SmoothingMode,
+
+    /// Kalman process noise: expected acceleration standard deviation (m/s²)
+    pub kalman_process_noise: f32,
+
+    /// Kalman measurement noise: expected solve error standard deviation (m)
+    pub kalman_measurement_noise: f32,
+
+    /// Fastest plausible device speed in m/s, faster velocity estimates are clamped
+    pub max_speed: f32,
+
     /// Maximum iterations for gradient descent
     pub max_iterations: usize,
 
@@ -118,9 +400,31 @@ pub struct TriangulatorConfig {
     /// Minimum number of stations required for trilateration (fallback to centroid otherwise)
     pub min_stations_for_trilateration: usize,
 
-    /// Maximum RSSI age in seconds (older readings are ignored)
+    /// Minimum number of contributing stations before `PositionTracker` reports a position
+    pub min_stations_for_position: usize,
+
+    /// How devices seen by fewer than `min_stations_for_position` stations are located
+    pub few_stations_mode: FewStationsMode,
+
+    /// Maximum RSSI age in seconds relative to the newest reading (older readings are ignored)
     pub max_reading_age_secs: u64,
 
+    /// How `PositionTracker` combines recent readings per station
+    pub rssi_aggregation: RssiAggregation,
+
+    /// Readings kept per station and device for mean/median aggregation
+    pub rssi_history_len: usize,
+
+    /// Weight each station by how many recent readings back it, counting up to this many
+    /// (1 weighs every station the same)
+    pub reading_count_cap: usize,
+
+    /// Drop the single most inconsistent reading when more than three stations see a device
+    pub outlier_rejection: bool,
+
+    /// Only look for outliers when the fit residual exceeds this many meters
+    pub outlier_min_residual: f32,
+
     /// Minimum RSSI value to consider (weaker signals are ignored)
     pub min_rssi: i8,
 
@@ -132,11 +436,23 @@ impl Default for TriangulatorConfig {
     fn default() -> Self {
         Self {
             smoothing_factor: 0.4,
+            solve_mode: SolveMode::default(),
+            smoothing_mode: SmoothingMode::default(),
+            kalman_process_noise: 0.5,
+            kalman_measurement_noise: 1.5,
+            max_speed: 3.0,
             max_iterations: 50,
             convergence_threshold: 0.01,
             learning_rate: 0.5,
             min_stations_for_trilateration: 3,
+            min_stations_for_position: 3,
+            few_stations_mode: FewStationsMode::default(),
             max_reading_age_secs: 10,
+            rssi_aggregation: RssiAggregation::default(),
+            rssi_history_len: 5,
+            reading_count_cap: 5,
+            outlier_rejection: true,
+            outlier_min_residual: 1.0,
             min_rssi: -90,
             max_distance: 50.0,
         }
@@ -146,6 +462,7 @@ impl Default for TriangulatorConfig {
 /// Distance measurement from a station
 #[derive(Debug, Clone)]
 struct DistanceMeasurement {
+    station_id: String,
     station_pos: Position,
     estimated_distance: f32,
     weight: f32, // Higher weight for stronger signals (more reliable)
@@ -155,6 +472,8 @@ struct DistanceMeasurement {
 pub struct Triangulator {
     stations: HashMap<String, StationData>,
     config: TriangulatorConfig,
+    /// RSSI-to-distance conversion
+    distance_model: Arc<dyn DistanceModel>,
     /// Room bounds for clamping positions
     room_min: Position,
     room_max: Position,
@@ -174,23 +493,46 @@ impl Triangulator {
     where
         S: StationLike,
     {
+        Self::from_station_data(stations.iter().map(to_station_data).collect(), config)
+    }
+
+    /// Build a triangulator from already converted station data
+    fn from_station_data(stations: Vec<StationData>, config: TriangulatorConfig) -> Self {
         let station_map: HashMap<String, StationData> = stations
-            .iter()
-            .map(|s| {
-                (
-                    s.id().to_string(),
-                    StationData {
-                        id: s.id().to_string(),
-                        x: s.x(),
-                        y: s.y(),
-                        calibration: s.calibration(),
-                    },
-                )
-            })
+            .into_iter()
+            .map(|s| (s.id.clone(), s))
             .collect();
 
+        let mut triangulator = Self {
+            stations: station_map,
+            config,
+            distance_model: Arc::new(LogDistanceModel),
+            room_min: Position::default(),
+            room_max: Position::default(),
+        };
+        triangulator.update_room_bounds();
+        triangulator
+    }
+
+    /// Add a station or replace the one with the same id
+    fn insert_station(&mut self, station: StationData) {
+        self.stations.insert(station.id.clone(), station);
+        self.update_room_bounds();
+    }
+
+    /// Remove a station, returns false if it wasn't known
+    fn remove_station(&mut self, id: &str) -> bool {
+        let removed = self.stations.remove(id).is_some();
+        if removed {
+            self.update_room_bounds();
+        }
+        removed
+    }
+
+    /// Recompute the bounds positions are clamped to from the station positions
+    fn update_room_bounds(&mut self) {
         // Calculate room bounds from station positions (with some padding)
-        let (min_x, max_x, min_y, max_y) = station_map.values().fold(
+        let (min_x, max_x, min_y, max_y) = self.stations.values().fold(
             (f32::MAX, f32::MIN, f32::MAX, f32::MIN),
             |(min_x, max_x, min_y, max_y), s| {
                 (
@@ -201,18 +543,25 @@ impl Triangulator {
                 )
             },
         );
+        let (min_z, max_z) = self
+            .stations
+            .values()
+            .fold((f32::MAX, f32::MIN), |(min_z, max_z), s| (min_z.min(s.z), max_z.max(s.z)));
 
         // Add padding around the room bounds
         let padding = 1.0;
-        let room_min = Position::new((min_x - padding).max(0.0), (min_y - padding).max(0.0));
-        let room_max = Position::new(max_x + padding, max_y + padding);
+        self.room_min = Position::new_3d(
+            (min_x - padding).max(0.0),
+            (min_y - padding).max(0.0),
+            (min_z - padding).max(0.0),
+        );
+        self.room_max = Position::new_3d(max_x + padding, max_y + padding, max_z + padding);
+    }
 
-        Self {
-            stations: station_map,
-            config,
-            room_min,
-            room_max,
-        }
+    /// Use a different RSSI-to-distance model (log-distance by default)
+    pub fn with_distance_model(mut self, model: Arc<dyn DistanceModel>) -> Self {
+        self.distance_model = model;
+        self
     }
 
     /// Calculate position using trilateration with gradient descent optimization
@@ -232,32 +581,61 @@ impl Triangulator {
         self.calculate_position_internal(readings, previous_position)
     }
 
-    fn calculate_position_internal(
+    /// Calculate position with smoothing and return it with its fit quality
+    pub fn estimate_position(
         &self,
         readings: &HashMap<String, RssiReading>,
         previous_position: Option<Position>,
-    ) -> Option<Position> {
+    ) -> Option<PositionEstimate> {
+        self.estimate_weighted(readings, previous_position, &HashMap::new())
+            .map(|(estimate, _)| estimate)
+    }
+
+    /// `estimate_position` with each station's weight multiplied by its reading count
+    /// (stations missing from `counts` count once), also returning the measurements used
+    fn estimate_weighted(
+        &self,
+        readings: &HashMap<String, RssiReading>,
+        previous_position: Option<Position>,
+        counts: &HashMap<String, usize>,
+    ) -> Option<(PositionEstimate, Vec<DistanceMeasurement>)> {
         if readings.is_empty() {
             return None;
         }
 
         // Convert readings to distance measurements
-        let measurements = self.readings_to_measurements(readings);
+        let measurements = self.readings_to_measurements(readings, counts);
 
         if measurements.is_empty() {
             return None;
         }
 
+        let measurements = self.reject_outlier(measurements);
+
         // Calculate raw position
         let raw_position = if measurements.len() >= self.config.min_stations_for_trilateration {
-            // Use trilateration with gradient descent
-            self.trilaterate(&measurements)
+            if let Some(reason) = Self::degenerate_geometry(&measurements) {
+                warn_degenerate(reason, &measurements);
+                return None;
+            }
+            self.solve(&measurements)
+                .filter(|pos| pos.x.is_finite() && pos.y.is_finite() && pos.z.is_finite())
                 .unwrap_or_else(|| self.weighted_centroid(&measurements))
         } else {
-            // Fall back to weighted centroid for fewer stations
-            self.weighted_centroid(&measurements)
+            match measurements.as_slice() {
+                // Two stations: intersect the RSSI circles unless a centroid was asked for
+                [a, b] if self.config.few_stations_mode != FewStationsMode::Centroid => {
+                    self.circle_intersection(a, b, previous_position)
+                }
+                // Fall back to weighted centroid
+                _ => self.weighted_centroid(&measurements),
+            }
         };
 
+        let residual = Self::residual(&raw_position, &measurements);
+        let gdop = Self::dilution_of_precision(&raw_position, &measurements);
+        let estimated_error = self.estimated_error(&measurements, residual, gdop);
+
         // Clamp to room bounds
         let clamped = self.clamp_to_room(raw_position);
 
@@ -268,19 +646,59 @@ impl Triangulator {
             clamped
         };
 
-        Some(smoothed)
+        let estimate = PositionEstimate {
+            position: smoothed,
+            residual,
+            station_count: measurements.len(),
+            velocity: None,
+            implausible_speed: false,
+            timestamp: readings.values().map(|r| r.timestamp).max().unwrap_or(0),
+            low_confidence: measurements.len() < self.config.min_stations_for_trilateration,
+            estimated_error,
+            gdop,
+        };
+        Some((estimate, measurements))
+    }
+
+    fn calculate_position_internal(
+        &self,
+        readings: &HashMap<String, RssiReading>,
+        previous_position: Option<Position>,
+    ) -> Option<Position> {
+        self.estimate_position(readings, previous_position)
+            .map(|estimate| estimate.position)
+    }
+
+    /// Number of readings that pass the filters and would contribute to a position
+    pub fn contributing_stations(&self, readings: &HashMap<String, RssiReading>) -> usize {
+        self.readings_to_measurements(readings, &HashMap::new()).len()
     }
 
     /// Convert RSSI readings to distance measurements
     fn readings_to_measurements(
         &self,
         readings: &HashMap<String, RssiReading>,
+        counts: &HashMap<String, usize>,
     ) -> Vec<DistanceMeasurement> {
+        // Age is measured against the newest reading, timestamps are in milliseconds
+        let newest = readings.values().map(|r| r.timestamp).max().unwrap_or(0);
+        let max_age_ms = self.config.max_reading_age_secs.saturating_mul(1000);
+
         readings
             .iter()
             .filter_map(|(station_id, reading)| {
                 let station = self.stations.get(station_id)?;
 
+                // Filter out stale readings
+                if newest - reading.timestamp > max_age_ms {
+                    log::debug!(
+                        "Ignoring stale reading from {} ({}ms old)",
+                        station_id,
+                        newest - reading.timestamp
+                    );
+                    return None;
+                }
+
                 // Filter out weak signals
                 if reading.rssi < self.config.min_rssi {
                     return None;
@@ -294,10 +712,12 @@ impl Triangulator {
                 }
 
                 // Weight based on signal strength (stronger = more reliable)
-                // Using inverse of distance squared as weight
-                let weight = 1.0 / (distance * distance + 0.1);
+                // Using inverse of distance squared as weight, scaled by the readings behind it
+                let count = counts.get(station_id).copied().unwrap_or(1).max(1);
+                let weight = count as f32 / (distance * distance + 0.1);
 
                 Some(DistanceMeasurement {
+                    station_id: station_id.clone(),
                     station_pos: station.position(),
                     estimated_distance: distance,
                     weight,
@@ -306,6 +726,68 @@ impl Triangulator {
             .collect()
     }
 
+    /// Solve with the configured solver
+    fn solve(&self, measurements: &[DistanceMeasurement]) -> Option<Position> {
+        match self.config.solve_mode {
+            SolveMode::GradientDescent => self.trilaterate(measurements),
+            SolveMode::WeightedLeastSquares => self.weighted_least_squares(measurements),
+            SolveMode::GaussNewton => self.gauss_newton(measurements),
+        }
+    }
+
+    /// Drop the single measurement that is most inconsistent with the others
+    ///
+    /// With more than three stations, each one is left out in turn and the rest re-solved.
+    /// If the fit is poor and leaving one station out at least halves the residual, that
+    /// station's reading is treated as an outlier (e.g. a reflection) and dropped.
+    fn reject_outlier(&self, measurements: Vec<DistanceMeasurement>) -> Vec<DistanceMeasurement> {
+        if !self.config.outlier_rejection
+            || measurements.len() <= 3
+            || measurements.len() <= self.config.min_stations_for_trilateration
+        {
+            return measurements;
+        }
+
+        let Some(full_fit) = self.solve(&measurements) else {
+            return measurements;
+        };
+        let full_residual = Self::residual(&full_fit, &measurements);
+        if full_residual < self.config.outlier_min_residual {
+            return measurements;
+        }
+
+        let best = (0..measurements.len())
+            .filter_map(|skip| {
+                let subset: Vec<DistanceMeasurement> = measurements
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| *i != skip)
+                    .map(|(_, m)| m.clone())
+                    .collect();
+                if Self::degenerate_geometry(&subset).is_some() {
+                    return None;
+                }
+                let pos = self.solve(&subset)?;
+                Some((skip, Self::residual(&pos, &subset)))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+
+        match best {
+            Some((skip, residual)) if residual < full_residual * OUTLIER_IMPROVEMENT_RATIO => {
+                let mut measurements = measurements;
+                let rejected = measurements.remove(skip);
+                log::debug!(
+                    "Rejected outlier reading from {} (residual {:.2}m -> {:.2}m)",
+                    rejected.station_id,
+                    full_residual,
+                    residual
+                );
+                measurements
+            }
+            _ => measurements,
+        }
+    }
+
     /// Trilateration using gradient descent optimization
     ///
     /// Minimizes: sum_i(weight_i * (distance(pos, station_i) - estimated_distance_i)^2)
@@ -313,14 +795,22 @@ impl Triangulator {
         // Initialize position at weighted centroid
         let mut pos = self.weighted_centroid(measurements);
 
+        // Height stays at the centroid's (shared) station height unless stations differ
+        let solve_z = Self::is_3d(measurements);
+
         for _ in 0..self.config.max_iterations {
-            let (grad_x, grad_y) = self.compute_gradient(&pos, measurements);
+            let (grad_x, grad_y, grad_z) = self.compute_gradient(&pos, measurements);
 
             // Update position using gradient descent
             let new_x = pos.x - self.config.learning_rate * grad_x;
             let new_y = pos.y - self.config.learning_rate * grad_y;
+            let new_z = if solve_z {
+                pos.z - self.config.learning_rate * grad_z
+            } else {
+                pos.z
+            };
 
-            let new_pos = Position::new(new_x, new_y);
+            let new_pos = Position::new_3d(new_x, new_y, new_z);
 
             // Check for convergence
             if pos.distance_to(&new_pos) < self.config.convergence_threshold {
@@ -334,22 +824,327 @@ impl Triangulator {
         Some(pos)
     }
 
+    /// Weighted least squares multilateration
+    ///
+    /// Subtracting the sphere equation of a reference station from every other one gives
+    /// a linear system `A * p = b`, solved via the weighted normal equations
+    /// `(A^T W A) p = A^T W b`. The strongest station is used as the reference.
+    /// Height is only solved when stations differ in height and at least four see the
+    /// device, otherwise the system is solved in the plane at the stations' mean height.
+    fn weighted_least_squares(&self, measurements: &[DistanceMeasurement]) -> Option<Position> {
+        let reference = measurements
+            .iter()
+            .max_by(|a, b| a.weight.total_cmp(&b.weight))?;
+        let r = reference.station_pos;
+        let rows = measurements.iter().filter(|m| !std::ptr::eq(*m, reference));
+
+        if Self::is_3d(measurements) && measurements.len() >= 4 {
+            // Accumulate A^T W A (symmetric 3x3) and A^T W b
+            let mut ata = [[0.0f32; 3]; 3];
+            let mut atb = [0.0f32; 3];
+            let dr = reference.estimated_distance;
+
+            for m in rows {
+                let s = m.station_pos;
+                let a = [2.0 * (s.x - r.x), 2.0 * (s.y - r.y), 2.0 * (s.z - r.z)];
+                let b = dr * dr - m.estimated_distance.powi(2)
+                    + (s.x * s.x + s.y * s.y + s.z * s.z)
+                    - (r.x * r.x + r.y * r.y + r.z * r.z);
+
+                for (i, row) in ata.iter_mut().enumerate() {
+                    for (j, cell) in row.iter_mut().enumerate() {
+                        *cell += m.weight * a[i] * a[j];
+                    }
+                    atb[i] += m.weight * a[i] * b;
+                }
+            }
+
+            let [x, y, z] = solve_3x3(ata, atb)?;
+            return Some(Position::new_3d(x, y, z));
+        }
+
+        // Project the distances onto the plane at the weighted mean station height
+        let z0 = self.weighted_centroid(measurements).z;
+        let horizontal_sq = |m: &DistanceMeasurement| {
+            (m.estimated_distance.powi(2) - (m.station_pos.z - z0).powi(2)).max(0.0)
+        };
+        let dr_sq = horizontal_sq(reference);
+
+        // Accumulate A^T W A (symmetric 2x2) and A^T W b
+        let (mut a11, mut a12, mut a22) = (0.0f32, 0.0f32, 0.0f32);
+        let (mut b1, mut b2) = (0.0f32, 0.0f32);
+
+        for m in rows {
+            let (xi, yi) = (m.station_pos.x, m.station_pos.y);
+
+            let ax = 2.0 * (xi - r.x);
+            let ay = 2.0 * (yi - r.y);
+            let b = dr_sq - horizontal_sq(m) + xi * xi - r.x * r.x + yi * yi - r.y * r.y;
+
+            a11 += m.weight * ax * ax;
+            a12 += m.weight * ax * ay;
+            a22 += m.weight * ay * ay;
+            b1 += m.weight * ax * b;
+            b2 += m.weight * ay * b;
+        }
+
+        let det = a11 * a22 - a12 * a12;
+        if det.abs() < f32::EPSILON {
+            return None;
+        }
+
+        Some(Position::new_3d(
+            (a22 * b1 - a12 * b2) / det,
+            (a11 * b2 - a12 * b1) / det,
+            z0,
+        ))
+    }
+
+    /// Weighted non-linear least squares with damped Gauss-Newton steps
+    ///
+    /// Starts at the plain centroid of the contributing stations and keeps the
+    /// lowest-residual position seen, so a diverging step can't make things worse.
+    /// A small Levenberg-Marquardt damping term keeps the normal equations solvable
+    /// when the stations are nearly collinear.
+    fn gauss_newton(&self, measurements: &[DistanceMeasurement]) -> Option<Position> {
+        let n = measurements.len() as f32;
+        let mut pos = measurements.iter().fold(Position::default(), |acc, m| Position {
+            x: acc.x + m.station_pos.x / n,
+            y: acc.y + m.station_pos.y / n,
+            z: acc.z + m.station_pos.z / n,
+        });
+        let solve_z = Self::is_3d(measurements);
+
+        let mut best = (pos, Self::residual(&pos, measurements));
+        for _ in 0..self.config.max_iterations {
+            // Accumulate J^T W J and J^T W r, J rows are unit vectors from station to pos
+            let mut jtj = [[0.0f32; 3]; 3];
+            let mut jtr = [0.0f32; 3];
+            for m in measurements {
+                let dist = pos.distance_to(&m.station_pos).max(0.001);
+                let j = [
+                    (pos.x - m.station_pos.x) / dist,
+                    (pos.y - m.station_pos.y) / dist,
+                    if solve_z { (pos.z - m.station_pos.z) / dist } else { 0.0 },
+                ];
+                let r = dist - m.estimated_distance;
+
+                for (a, row) in jtj.iter_mut().enumerate() {
+                    for (b, cell) in row.iter_mut().enumerate() {
+                        *cell += m.weight * j[a] * j[b];
+                    }
+                    jtr[a] += m.weight * j[a] * r;
+                }
+            }
+
+            let trace = jtj[0][0] + jtj[1][1] + jtj[2][2];
+            let damping = GAUSS_NEWTON_DAMPING * trace.max(f32::EPSILON) / 3.0;
+            for (i, row) in jtj.iter_mut().enumerate() {
+                row[i] += damping;
+            }
+            if !solve_z {
+                // Pin height: the z row becomes `1 * dz = 0`
+                jtj[2] = [0.0, 0.0, 1.0];
+                jtr[2] = 0.0;
+            }
+
+            let [dx, dy, dz] = solve_3x3(jtj, jtr.map(|v| -v))?;
+            pos = Position::new_3d(pos.x + dx, pos.y + dy, pos.z + dz);
+
+            let residual = Self::residual(&pos, measurements);
+            if residual < best.1 {
+                best = (pos, residual);
+            }
+            if (dx * dx + dy * dy + dz * dz).sqrt() < self.config.convergence_threshold {
+                break;
+            }
+        }
+
+        Some(best.0)
+    }
+
+    /// Check whether the stations can locate a device in the plane at all
+    ///
+    /// Looks at the covariance of the station positions: if it has (almost) no spread
+    /// the stations coincide, and if its eigenvalues are very lopsided the stations lie
+    /// on a line, leaving the position mirror-ambiguous and the solvers ill-conditioned.
+    fn degenerate_geometry(measurements: &[DistanceMeasurement]) -> Option<&'static str> {
+        let n = measurements.len() as f32;
+        let (mean_x, mean_y) = measurements.iter().fold((0.0, 0.0), |(x, y), m| {
+            (x + m.station_pos.x / n, y + m.station_pos.y / n)
+        });
+
+        let (mut cxx, mut cxy, mut cyy) = (0.0f32, 0.0f32, 0.0f32);
+        for m in measurements {
+            let dx = m.station_pos.x - mean_x;
+            let dy = m.station_pos.y - mean_y;
+            cxx += dx * dx / n;
+            cxy += dx * dy / n;
+            cyy += dy * dy / n;
+        }
+
+        // Eigenvalues of the 2x2 covariance
+        let trace = cxx + cyy;
+        let det = cxx * cyy - cxy * cxy;
+        let spread = (trace * trace / 4.0 - det).max(0.0).sqrt();
+        let (largest, smallest) = (trace / 2.0 + spread, trace / 2.0 - spread);
+
+        if largest < MIN_STATION_SPREAD {
+            Some("stations are at the same position")
+        } else if smallest / largest < MIN_GEOMETRY_CONDITION {
+            Some("stations are collinear")
+        } else {
+            None
+        }
+    }
+
+    /// True if the contributing stations are mounted at noticeably different heights
+    fn is_3d(measurements: &[DistanceMeasurement]) -> bool {
+        let (min_z, max_z) = measurements.iter().fold((f32::MAX, f32::MIN), |(lo, hi), m| {
+            (lo.min(m.station_pos.z), hi.max(m.station_pos.z))
+        });
+        max_z - min_z > MIN_HEIGHT_SPREAD
+    }
+
+    /// Intersect the distance circles of two stations
+    ///
+    /// Of the two intersection points the one inside the room is used, ties go to the one
+    /// nearest `hint` (or the room center). If the circles don't intersect, the midpoint of
+    /// the gap between them along the line joining the stations is returned.
+    fn circle_intersection(
+        &self,
+        a: &DistanceMeasurement,
+        b: &DistanceMeasurement,
+        hint: Option<Position>,
+    ) -> Position {
+        let (r0, r1) = (a.estimated_distance, b.estimated_distance);
+        let d = a.station_pos.distance_to(&b.station_pos);
+        if d < 1e-3 {
+            return a.station_pos.lerp(&b.station_pos, 0.5);
+        }
+
+        // Solved in the horizontal plane, height follows the line between the stations
+        let ux = (b.station_pos.x - a.station_pos.x) / d;
+        let uy = (b.station_pos.y - a.station_pos.y) / d;
+        let along = |t: f32| a.station_pos.lerp(&b.station_pos, t / d);
+
+        if d > r0 + r1 || d < (r0 - r1).abs() {
+            return along((r0 + d - r1) / 2.0);
+        }
+
+        let t = (r0 * r0 - r1 * r1 + d * d) / (2.0 * d);
+        let h = (r0 * r0 - t * t).max(0.0).sqrt();
+        let base = along(t);
+        let candidates = [
+            Position::new_3d(base.x - uy * h, base.y + ux * h, base.z),
+            Position::new_3d(base.x + uy * h, base.y - ux * h, base.z),
+        ];
+
+        let inside = |p: &Position| {
+            (self.room_min.x..=self.room_max.x).contains(&p.x)
+                && (self.room_min.y..=self.room_max.y).contains(&p.y)
+        };
+        let hint = hint.unwrap_or_else(|| self.room_min.lerp(&self.room_max, 0.5));
+
+        candidates
+            .into_iter()
+            .min_by(|p, q| {
+                inside(q)
+                    .cmp(&inside(p))
+                    .then(p.distance_to(&hint).total_cmp(&q.distance_to(&hint)))
+            })
+            .unwrap_or(base)
+    }
+
+    /// Rough error radius for a solved position
+    ///
+    /// The ranging error is taken as the fit residual, but never less than
+    /// `MIN_RANGE_ERROR_RATIO` of the mean estimated distance since RSSI ranging is noisy
+    /// even when the circles happen to agree. It is scaled by the dilution of precision
+    /// of the station geometry and capped at `max_distance`.
+    fn estimated_error(
+        &self,
+        measurements: &[DistanceMeasurement],
+        residual: f32,
+        gdop: Option<f32>,
+    ) -> f32 {
+        let mean_distance = measurements.iter().map(|m| m.estimated_distance).sum::<f32>()
+            / measurements.len().max(1) as f32;
+        let range_error = residual.max(MIN_RANGE_ERROR_RATIO * mean_distance);
+
+        match gdop {
+            Some(dop) => (range_error * dop).min(self.config.max_distance),
+            // A single station or collinear geometry only constrains the range
+            None => mean_distance.max(range_error).min(self.config.max_distance),
+        }
+    }
+
+    /// Dilution of precision for the station directions as seen from `pos`
+    ///
+    /// `sqrt(trace((H^T H)^-1))` where `H` holds the unit vectors from `pos` to each station.
+    /// Returns `None` when the geometry is singular (fewer than two distinct directions).
+    fn dilution_of_precision(pos: &Position, measurements: &[DistanceMeasurement]) -> Option<f32> {
+        let (mut hxx, mut hxy, mut hyy) = (0.0f32, 0.0f32, 0.0f32);
+        for m in measurements {
+            let d = pos.distance_to(&m.station_pos);
+            if d < 1e-3 {
+                continue;
+            }
+            let ux = (m.station_pos.x - pos.x) / d;
+            let uy = (m.station_pos.y - pos.y) / d;
+            hxx += ux * ux;
+            hxy += ux * uy;
+            hyy += uy * uy;
+        }
+
+        let det = hxx * hyy - hxy * hxy;
+        if det < 1e-3 {
+            return None;
+        }
+        Some(((hxx + hyy) / det).sqrt())
+    }
+
+    /// Weighted RMS error between the distances implied by `pos` and the RSSI estimates
+    fn residual(pos: &Position, measurements: &[DistanceMeasurement]) -> f32 {
+        let total_weight: f32 = measurements.iter().map(|m| m.weight).sum();
+        if total_weight <= 0.0 {
+            return 0.0;
+        }
+
+        let weighted_sq_error: f32 = measurements
+            .iter()
+            .map(|m| {
+                let error = pos.distance_to(&m.station_pos) - m.estimated_distance;
+                m.weight * error * error
+            })
+            .sum();
+
+        (weighted_sq_error / total_weight).sqrt()
+    }
+
     /// Compute gradient of the cost function
-    fn compute_gradient(&self, pos: &Position, measurements: &[DistanceMeasurement]) -> (f32, f32) {
+    fn compute_gradient(
+        &self,
+        pos: &Position,
+        measurements: &[DistanceMeasurement],
+    ) -> (f32, f32, f32) {
         let mut grad_x = 0.0f32;
         let mut grad_y = 0.0f32;
+        let mut grad_z = 0.0f32;
 
         for m in measurements {
             let dx = pos.x - m.station_pos.x;
             let dy = pos.y - m.station_pos.y;
-            let actual_dist = (dx * dx + dy * dy).sqrt().max(0.001); // Avoid division by zero
+            let dz = pos.z - m.station_pos.z;
+            let actual_dist = pos.distance_to(&m.station_pos).max(0.001); // Avoid division by zero
 
             let error = actual_dist - m.estimated_distance;
 
             // Gradient of squared error with respect to position
-            // d/dx [(sqrt((x-sx)^2 + (y-sy)^2) - d)^2] = 2 * error * (x-sx) / actual_dist
+            // d/dx [(sqrt((x-sx)^2 + (y-sy)^2 + (z-sz)^2) - d)^2] = 2 * error * (x-sx) / actual_dist
             grad_x += m.weight * 2.0 * error * dx / actual_dist;
             grad_y += m.weight * 2.0 * error * dy / actual_dist;
+            grad_z += m.weight * 2.0 * error * dz / actual_dist;
         }
 
         // Normalize by total weight
@@ -357,9 +1152,10 @@ impl Triangulator {
         if total_weight > 0.0 {
             grad_x /= total_weight;
             grad_y /= total_weight;
+            grad_z /= total_weight;
         }
 
-        (grad_x, grad_y)
+        (grad_x, grad_y, grad_z)
     }
 
     /// Weighted centroid calculation (fallback method)
@@ -367,88 +1163,588 @@ impl Triangulator {
         let mut total_weight = 0.0f32;
         let mut weighted_x = 0.0f32;
         let mut weighted_y = 0.0f32;
+        let mut weighted_z = 0.0f32;
 
         for m in measurements {
             weighted_x += m.weight * m.station_pos.x;
             weighted_y += m.weight * m.station_pos.y;
+            weighted_z += m.weight * m.station_pos.z;
             total_weight += m.weight;
         }
 
         if total_weight > 0.0 {
-            Position::new(weighted_x / total_weight, weighted_y / total_weight)
+            Position::new_3d(
+                weighted_x / total_weight,
+                weighted_y / total_weight,
+                weighted_z / total_weight,
+            )
         } else {
             Position::default()
         }
     }
 
-    /// Convert RSSI to estimated distance using log-distance path loss model
+    /// Convert RSSI to estimated distance using the configured distance model
     ///
-    /// Formula: distance = 10^((rssi_at_1m - rssi) / (10 * path_loss_exponent))
+    /// The station's `rssi_offset` is applied before conversion.
     fn rssi_to_distance(&self, rssi: i8, calibration: &CalibrationParams) -> f32 {
-        let exponent =
-            (calibration.rssi_at_1m - rssi as f32) / (10.0 * calibration.path_loss_exponent);
-        10.0_f32.powf(exponent)
+        self.distance_model
+            .distance(rssi as f32 + calibration.rssi_offset, calibration)
     }
 
     /// Clamp position to room bounds
     fn clamp_to_room(&self, pos: Position) -> Position {
-        Position::new(
+        Position::new_3d(
             pos.x.clamp(self.room_min.x, self.room_max.x),
             pos.y.clamp(self.room_min.y, self.room_max.y),
+            pos.z.clamp(self.room_min.z, self.room_max.z),
         )
     }
 }
 
-/// Position tracker that maintains smoothed positions for multiple devices
-pub struct PositionTracker {
-    triangulator: Triangulator,
-    /// Smoothed positions for each device (by MAC address)
-    positions: HashMap<String, Position>,
-}
+/// Solve a 3x3 linear system with Cramer's rule, `None` if it is singular
+fn solve_3x3(m: [[f32; 3]; 3], b: [f32; 3]) -> Option<[f32; 3]> {
+    let det = |m: [[f32; 3]; 3]| {
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    };
+
+    let d = det(m);
+    if d.abs() < f32::EPSILON {
+        return None;
+    }
 
-impl PositionTracker {
-    pub fn new<S>(stations: &[S]) -> Self
-    where
-        S: StationLike,
-    {
-        Self {
-            triangulator: Triangulator::new(stations),
-            positions: HashMap::new(),
+    let mut solution = [0.0f32; 3];
+    for (col, value) in solution.iter_mut().enumerate() {
+        let mut replaced = m;
+        for (row, b) in b.iter().enumerate() {
+            replaced[row][col] = *b;
         }
+        *value = det(replaced) / d;
     }
+    Some(solution)
+}
 
-    pub fn with_config<S>(stations: &[S], config: TriangulatorConfig) -> Self
-    where
-        S: StationLike,
-    {
+/// One axis of a constant-velocity Kalman filter, state is `[position, velocity]`
+#[derive(Debug, Clone, Copy)]
+struct KalmanAxis {
+    pos: f32,
+    vel: f32,
+    /// State covariance
+    p: [[f32; 2]; 2],
+}
+
+impl KalmanAxis {
+    fn new(pos: f32, measurement_var: f32) -> Self {
         Self {
-            triangulator: Triangulator::with_config(stations, config),
-            positions: HashMap::new(),
+            pos,
+            vel: 0.0,
+            p: [[measurement_var, 0.0], [0.0, 1.0]],
         }
     }
 
-    /// Update position for a device, applying smoothing
-    pub fn update_position(
-        &mut self,
-        device_id: &str,
-        readings: &HashMap<String, RssiReading>,
-    ) -> Option<Position> {
-        let previous = self.positions.get(device_id).copied();
-        let new_pos = self
-            .triangulator
-            .calculate_position_smoothed(readings, previous)?;
-        self.positions.insert(device_id.to_string(), new_pos);
-        Some(new_pos)
+    /// Advance the state by `dt` seconds with white acceleration noise of variance `q`
+    fn predict(&mut self, dt: f32, q: f32) {
+        self.pos += self.vel * dt;
+
+        let [[p00, p01], [p10, p11]] = self.p;
+        let dt2 = dt * dt;
+        self.p = [
+            [
+                p00 + dt * (p10 + p01) + dt2 * p11 + q * dt2 * dt2 / 4.0,
+                p01 + dt * p11 + q * dt2 * dt / 2.0,
+            ],
+            [
+                p10 + dt * p11 + q * dt2 * dt / 2.0,
+                p11 + q * dt2,
+            ],
+        ];
     }
 
-    /// Get the current smoothed position for a device
-    pub fn get_position(&self, device_id: &str) -> Option<Position> {
-        self.positions.get(device_id).copied()
+    /// Correct the state with a position measurement of variance `r`
+    fn update(&mut self, measured: f32, r: f32) {
+        let [[p00, p01], [p10, p11]] = self.p;
+        let s = p00 + r;
+        let (k0, k1) = (p00 / s, p10 / s);
+        let innovation = measured - self.pos;
+
+        self.pos += k0 * innovation;
+        self.vel += k1 * innovation;
+        self.p = [
+            [(1.0 - k0) * p00, (1.0 - k0) * p01],
+            [p10 - k1 * p00, p11 - k1 * p01],
+        ];
     }
+}
 
-    /// Remove a device from tracking
-    pub fn remove_device(&mut self, device_id: &str) {
-        self.positions.remove(device_id);
+/// Per-device Kalman filter state
+#[derive(Debug, Clone, Copy)]
+struct KalmanState {
+    x: KalmanAxis,
+    y: KalmanAxis,
+    /// Timestamp of the last update in milliseconds
+    timestamp: u64,
+}
+
+impl KalmanState {
+    fn new(pos: Position, timestamp: u64, config: &TriangulatorConfig) -> Self {
+        let r = config.kalman_measurement_noise.powi(2);
+        Self {
+            x: KalmanAxis::new(pos.x, r),
+            y: KalmanAxis::new(pos.y, r),
+            timestamp,
+        }
+    }
+
+    fn step(&mut self, measured: Position, timestamp: u64, config: &TriangulatorConfig) {
+        let dt = timestamp.saturating_sub(self.timestamp) as f32 / 1000.0;
+        if dt > 0.0 {
+            let q = config.kalman_process_noise.powi(2);
+            self.x.predict(dt, q);
+            self.y.predict(dt, q);
+            self.timestamp = timestamp;
+        }
+
+        let r = config.kalman_measurement_noise.powi(2);
+        self.x.update(measured.x, r);
+        self.y.update(measured.y, r);
+    }
+
+    fn position(&self) -> Position {
+        Position::new(self.x.pos, self.y.pos)
+    }
+
+    fn velocity(&self) -> Velocity {
+        Velocity {
+            vx: self.x.vel,
+            vy: self.y.vel,
+        }
+    }
+}
+
+/// Position tracker that maintains smoothed positions for multiple devices
+///
+/// Stations are grouped by floor, each with its own triangulator. A device is
+/// located on the floor whose stations see it best.
+pub struct PositionTracker {
+    /// One triangulator per floor id
+    floors: HashMap<String, Triangulator>,
+    config: TriangulatorConfig,
+    /// Latest estimate for each device (by MAC address), including the smoothed position
+    estimates: HashMap<String, PositionEstimate>,
+    /// Floor each device was last located on
+    device_floors: HashMap<String, String>,
+    /// Kalman filter state per device, only used in `SmoothingMode::Kalman`
+    filters: HashMap<String, KalmanState>,
+    /// RSSI-to-distance conversion shared by all floors
+    distance_model: Arc<dyn DistanceModel>,
+    /// Recent readings per device and station, kept for mean/median aggregation and
+    /// reading count weights
+    reading_history: HashMap<String, HashMap<String, VecDeque<RssiReading>>>,
+    /// Share of each station in the latest solve per device, for debugging
+    station_weights: HashMap<String, HashMap<String, f32>>,
+}
+
+impl PositionTracker {
+    pub fn new<S>(stations: &[S]) -> Self
+    where
+        S: StationLike,
+    {
+        Self::with_config(stations, TriangulatorConfig::default())
+    }
+
+    pub fn with_config<S>(stations: &[S], config: TriangulatorConfig) -> Self
+    where
+        S: StationLike,
+    {
+        let mut by_floor: HashMap<String, Vec<StationData>> = HashMap::new();
+        for station in stations {
+            by_floor
+                .entry(station.floor().to_string())
+                .or_default()
+                .push(to_station_data(station));
+        }
+
+        let floors = by_floor
+            .into_iter()
+            .map(|(floor, stations)| (floor, Triangulator::from_station_data(stations, config.clone())))
+            .collect();
+
+        Self {
+            floors,
+            config,
+            estimates: HashMap::new(),
+            device_floors: HashMap::new(),
+            filters: HashMap::new(),
+            distance_model: Arc::new(LogDistanceModel),
+            reading_history: HashMap::new(),
+            station_weights: HashMap::new(),
+        }
+    }
+
+    /// Use a different RSSI-to-distance model on every floor (log-distance by default)
+    pub fn with_distance_model(mut self, model: Arc<dyn DistanceModel>) -> Self {
+        self.floors = self
+            .floors
+            .into_iter()
+            .map(|(floor, t)| (floor, t.with_distance_model(model.clone())))
+            .collect();
+        self.distance_model = model;
+        self
+    }
+
+    /// Pick the floor with the most contributing stations, ties go to the strongest reading
+    pub fn select_floor(&self, readings: &HashMap<String, RssiReading>) -> Option<&str> {
+        self.floors
+            .iter()
+            .map(|(floor, triangulator)| {
+                let count = triangulator.contributing_stations(readings);
+                let strongest = readings
+                    .iter()
+                    .filter(|(id, _)| triangulator.stations.contains_key(*id))
+                    .map(|(_, r)| r.rssi)
+                    .max()
+                    .unwrap_or(i8::MIN);
+                (floor, count, strongest)
+            })
+            .filter(|(_, count, _)| *count > 0)
+            .max_by_key(|(_, count, strongest)| (*count, *strongest))
+            .map(|(floor, _, _)| floor.as_str())
+    }
+
+    /// Update position for a device, applying smoothing (none in `Passthrough` mode)
+    ///
+    /// Returns `None` when fewer than `min_stations_for_position` stations contribute,
+    /// unless `few_stations_mode` allows a fallback for this many.
+    pub fn update_position(
+        &mut self,
+        device_id: &str,
+        readings: &HashMap<String, RssiReading>,
+    ) -> Option<Position> {
+        // Passthrough solves the latest readings alone, no history feeds into it
+        let passthrough = self.config.smoothing_mode == SmoothingMode::Passthrough;
+        let (aggregated, counts) = if passthrough {
+            (None, HashMap::new())
+        } else {
+            self.record_readings(device_id, readings);
+            (self.aggregate_readings(device_id, readings), self.reading_counts(device_id))
+        };
+        let readings = aggregated.as_ref().unwrap_or(readings);
+
+        let count = self.contributing_stations(readings);
+        let fallback_ok = match self.config.few_stations_mode {
+            FewStationsMode::None => false,
+            FewStationsMode::Centroid => count >= 1,
+            FewStationsMode::CircleIntersection => count == 2,
+        };
+        if count < self.config.min_stations_for_position && !fallback_ok {
+            return None;
+        }
+
+        let floor = self.select_floor(readings)?.to_string();
+
+        // Don't smooth across floors, the previous position is in another floor's geometry
+        let same_floor = self.device_floors.get(device_id) == Some(&floor);
+        let previous_estimate = if same_floor {
+            self.get_estimate(device_id)
+        } else {
+            None
+        };
+        let previous = previous_estimate.map(|e| e.position);
+
+        let triangulator = &self.floors[&floor];
+        let (mut estimate, measurements) = match self.config.smoothing_mode {
+            SmoothingMode::Exponential => {
+                triangulator.estimate_weighted(readings, previous, &counts)?
+            }
+            SmoothingMode::Passthrough => triangulator.estimate_weighted(readings, None, &counts)?,
+            SmoothingMode::Kalman => {
+                let (raw, measurements) = triangulator.estimate_weighted(readings, None, &counts)?;
+
+                let filter = match self.filters.get_mut(device_id) {
+                    Some(filter) if same_floor => {
+                        filter.step(raw.position, raw.timestamp, &self.config);
+                        *filter
+                    }
+                    _ => {
+                        let filter = KalmanState::new(raw.position, raw.timestamp, &self.config);
+                        self.filters.insert(device_id.to_string(), filter);
+                        filter
+                    }
+                };
+
+                // Height isn't filtered, only the horizontal motion is tracked
+                let filtered = Position {
+                    z: raw.position.z,
+                    ..filter.position()
+                };
+                let estimate = PositionEstimate {
+                    position: triangulator.clamp_to_room(filtered),
+                    velocity: Some(filter.velocity()),
+                    ..raw
+                };
+                (estimate, measurements)
+            }
+        };
+
+        if estimate.velocity.is_none() {
+            estimate.velocity =
+                previous_estimate.and_then(|prev| self.finite_difference(&prev, &estimate));
+        }
+        if let Some(velocity) = &mut estimate.velocity {
+            estimate.implausible_speed = velocity.clamp_speed(self.config.max_speed);
+        }
+
+        let total_weight: f32 = measurements.iter().map(|m| m.weight).sum();
+        let weights = measurements
+            .iter()
+            .map(|m| (m.station_id.clone(), m.weight / total_weight.max(f32::EPSILON)))
+            .collect();
+        self.station_weights.insert(device_id.to_string(), weights);
+
+        self.estimates.insert(device_id.to_string(), estimate);
+        self.device_floors.insert(device_id.to_string(), floor);
+        Some(estimate.position)
+    }
+
+    /// Add new readings to each station's history, dropping readings that are too old
+    ///
+    /// A reading is only added if it is newer than the last one seen from that station,
+    /// so callers can keep passing a station's latest reading until it changes. Nothing
+    /// is kept when neither aggregation nor reading count weights need a history.
+    fn record_readings(&mut self, device_id: &str, readings: &HashMap<String, RssiReading>) {
+        if self.config.rssi_aggregation == RssiAggregation::Latest
+            && self.config.reading_count_cap <= 1
+        {
+            return;
+        }
+
+        let newest = readings.values().map(|r| r.timestamp).max().unwrap_or(0);
+        let max_age_ms = self.config.max_reading_age_secs.saturating_mul(1000);
+        let history_len = self.config.rssi_history_len.max(self.config.reading_count_cap).max(1);
+        let device_history = self.reading_history.entry(device_id.to_string()).or_default();
+
+        for (station_id, reading) in readings {
+            let history = device_history.entry(station_id.clone()).or_default();
+            if !history.back().is_some_and(|last| reading.timestamp <= last.timestamp) {
+                history.push_back(reading.clone());
+            }
+            while history.len() > history_len
+                || history
+                    .front()
+                    .is_some_and(|r| newest.saturating_sub(r.timestamp) > max_age_ms)
+            {
+                history.pop_front();
+            }
+        }
+
+        // Stations that no longer report for this device
+        device_history.retain(|station_id, _| readings.contains_key(station_id));
+    }
+
+    /// Combine each station's last `rssi_history_len` readings into one reading
+    ///
+    /// Returns `None` in `Latest` mode, where readings are used as-is.
+    fn aggregate_readings(
+        &self,
+        device_id: &str,
+        readings: &HashMap<String, RssiReading>,
+    ) -> Option<HashMap<String, RssiReading>> {
+        if self.config.rssi_aggregation == RssiAggregation::Latest {
+            return None;
+        }
+
+        let history_len = self.config.rssi_history_len.max(1);
+        let device_history = self.reading_history.get(device_id);
+
+        let mut aggregated = HashMap::new();
+        for (station_id, reading) in readings {
+            let mut values: Vec<i8> = device_history
+                .and_then(|history| history.get(station_id))
+                .map(|history| history.iter().rev().take(history_len).map(|r| r.rssi).collect())
+                .unwrap_or_default();
+            let rssi = match self.config.rssi_aggregation {
+                RssiAggregation::Latest => reading.rssi,
+                RssiAggregation::Mean if !values.is_empty() => {
+                    let sum: i32 = values.iter().map(|&v| v as i32).sum();
+                    (sum as f32 / values.len() as f32).round() as i8
+                }
+                RssiAggregation::Median if !values.is_empty() => {
+                    values.sort_unstable();
+                    let mid = values.len() / 2;
+                    if values.len() % 2 == 0 {
+                        ((values[mid - 1] as i16 + values[mid] as i16) / 2) as i8
+                    } else {
+                        values[mid]
+                    }
+                }
+                _ => reading.rssi,
+            };
+            aggregated.insert(
+                station_id.clone(),
+                RssiReading {
+                    rssi,
+                    timestamp: reading.timestamp,
+                },
+            );
+        }
+
+        Some(aggregated)
+    }
+
+    /// Recent readings behind each station's value, capped at `reading_count_cap`
+    fn reading_counts(&self, device_id: &str) -> HashMap<String, usize> {
+        let cap = self.config.reading_count_cap;
+        if cap <= 1 {
+            return HashMap::new();
+        }
+        self.reading_history
+            .get(device_id)
+            .map(|history| {
+                history
+                    .iter()
+                    .map(|(station_id, readings)| (station_id.clone(), readings.len().min(cap)))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Velocity from the change between two estimates, smoothed with the previous velocity
+    fn finite_difference(
+        &self,
+        prev: &PositionEstimate,
+        next: &PositionEstimate,
+    ) -> Option<Velocity> {
+        let dt = next.timestamp.saturating_sub(prev.timestamp) as f32 / 1000.0;
+        if dt <= 0.0 {
+            return prev.velocity;
+        }
+
+        let instant = Velocity {
+            vx: (next.position.x - prev.position.x) / dt,
+            vy: (next.position.y - prev.position.y) / dt,
+        };
+        let alpha = match self.config.smoothing_mode {
+            SmoothingMode::Passthrough => 1.0,
+            _ => 1.0 - self.config.smoothing_factor,
+        };
+        Some(match prev.velocity {
+            Some(v) => Velocity {
+                vx: v.vx + (instant.vx - v.vx) * alpha,
+                vy: v.vy + (instant.vy - v.vy) * alpha,
+            },
+            None => instant,
+        })
+    }
+
+    /// Number of stations whose readings would contribute to a position
+    ///
+    /// Only stations on the floor the device would be located on are counted.
+    pub fn contributing_stations(&self, readings: &HashMap<String, RssiReading>) -> usize {
+        self.select_floor(readings)
+            .map(|floor| self.floors[floor].contributing_stations(readings))
+            .unwrap_or(0)
+    }
+
+    /// Get the current smoothed position for a device
+    pub fn get_position(&self, device_id: &str) -> Option<Position> {
+        self.estimates.get(device_id).map(|e| e.position)
+    }
+
+    /// Get the latest estimate (position and fit quality) for a device
+    pub fn get_estimate(&self, device_id: &str) -> Option<PositionEstimate> {
+        self.estimates.get(device_id).copied()
+    }
+
+    /// Share of each contributing station in a device's latest solve, summing to 1
+    ///
+    /// Combines the signal strength weight with the reading count weight, after outlier
+    /// rejection. Stations that didn't contribute are missing.
+    pub fn get_station_weights(&self, device_id: &str) -> Option<&HashMap<String, f32>> {
+        self.station_weights.get(device_id)
+    }
+
+    /// Get the floor a device was last located on
+    pub fn get_floor(&self, device_id: &str) -> Option<&str> {
+        self.device_floors.get(device_id).map(String::as_str)
+    }
+
+    /// Remove a device from tracking
+    pub fn remove_device(&mut self, device_id: &str) {
+        self.estimates.remove(device_id);
+        self.device_floors.remove(device_id);
+        self.filters.remove(device_id);
+        self.reading_history.remove(device_id);
+        self.station_weights.remove(device_id);
+    }
+
+    /// Add a station, or update its position, calibration or floor if the id is already known
+    ///
+    /// Tracked devices keep their estimates and smoothing state, the new geometry applies
+    /// from their next update.
+    pub fn add_station(&mut self, station: &impl StationLike) {
+        let data = to_station_data(station);
+        let floor = station.floor();
+        let moved_from: Vec<String> = self
+            .floors
+            .iter()
+            .filter(|(id, t)| id.as_str() != floor && t.stations.contains_key(&data.id))
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in moved_from {
+            self.detach_station(&id, &data.id);
+        }
+
+        match self.floors.get_mut(floor) {
+            Some(triangulator) => triangulator.insert_station(data),
+            None => {
+                let triangulator = Triangulator::from_station_data(vec![data], self.config.clone())
+                    .with_distance_model(self.distance_model.clone());
+                self.floors.insert(floor.to_string(), triangulator);
+            }
+        }
+    }
+
+    /// Remove a station and drop the readings devices have kept from it
+    ///
+    /// Tracked devices keep their estimates; a device whose floor lost its last station
+    /// is relocated on its next update.
+    pub fn remove_station(&mut self, id: &str) {
+        let floors: Vec<String> = self
+            .floors
+            .iter()
+            .filter(|(_, t)| t.stations.contains_key(id))
+            .map(|(floor, _)| floor.clone())
+            .collect();
+        for floor in floors {
+            self.detach_station(&floor, id);
+        }
+        for history in self.reading_history.values_mut() {
+            history.remove(id);
+        }
+        for weights in self.station_weights.values_mut() {
+            weights.remove(id);
+        }
+    }
+
+    /// Take a station off one floor, dropping the floor once it has no stations left
+    fn detach_station(&mut self, floor: &str, id: &str) {
+        let Some(triangulator) = self.floors.get_mut(floor) else {
+            return;
+        };
+        if triangulator.remove_station(id) && triangulator.stations.is_empty() {
+            self.floors.remove(floor);
+            self.device_floors.retain(|_, device_floor| device_floor != floor);
+        }
+    }
+
+    /// Forget all tracked devices
+    pub fn clear(&mut self) {
+        self.estimates.clear();
+        self.device_floors.clear();
+        self.filters.clear();
+        self.reading_history.clear();
+        self.station_weights.clear();
     }
 }
 
@@ -463,6 +1759,59 @@ mod tests {
         calibration: Option<CalibrationParams>,
     }
 
+    struct HeightStation {
+        id: &'static str,
+        pos: Position,
+        rssi_at_1m: f32,
+    }
+
+    impl StationLike for HeightStation {
+        fn id(&self) -> &str {
+            self.id
+        }
+        fn x(&self) -> f32 {
+            self.pos.x
+        }
+        fn y(&self) -> f32 {
+            self.pos.y
+        }
+        fn z(&self) -> f32 {
+            self.pos.z
+        }
+        fn calibration(&self) -> CalibrationParams {
+            CalibrationParams {
+                rssi_at_1m: self.rssi_at_1m,
+                path_loss_exponent: 2.0,
+                ..Default::default()
+            }
+        }
+    }
+
+    struct FloorStation {
+        id: &'static str,
+        floor: &'static str,
+        x: f32,
+        y: f32,
+    }
+
+    impl StationLike for FloorStation {
+        fn id(&self) -> &str {
+            self.id
+        }
+        fn x(&self) -> f32 {
+            self.x
+        }
+        fn y(&self) -> f32 {
+            self.y
+        }
+        fn calibration(&self) -> CalibrationParams {
+            CalibrationParams::default()
+        }
+        fn floor(&self) -> &str {
+            self.floor
+        }
+    }
+
     impl StationLike for TestStation {
         fn id(&self) -> &str {
             &self.id
@@ -501,6 +1850,64 @@ mod tests {
         ]
     }
 
+    #[test]
+    fn test_rssi_offset_shifts_distance() {
+        let stations = make_stations();
+        let triangulator = Triangulator::new(&stations);
+        let cal = CalibrationParams {
+            rssi_at_1m: -40.0,
+            path_loss_exponent: 2.0,
+            ..Default::default()
+        };
+        let biased = CalibrationParams {
+            rssi_offset: 6.0,
+            ..cal.clone()
+        };
+
+        // A board reading 6 dB low at -66 matches an unbiased -60
+        let expected = triangulator.rssi_to_distance(-60, &cal);
+        assert!((triangulator.rssi_to_distance(-66, &biased) - expected).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_fit_path_loss_exponent() {
+        let calibration = CalibrationParams {
+            rssi_at_1m: -40.0,
+            path_loss_exponent: 2.5,
+            ..Default::default()
+        };
+
+        // 10m away at -70 dBm -> exponent 3.0
+        let exponent = calibration.fit_path_loss_exponent(-70.0, 10.0).unwrap();
+        assert!((exponent - 3.0).abs() < 0.01);
+
+        // Too close to the 1m reference to be meaningful
+        assert!(calibration.fit_path_loss_exponent(-41.0, 1.1).is_none());
+    }
+
+    #[test]
+    fn test_lookup_table_model_interpolates() {
+        let model = LookupTableModel::new(&[(-40.0, 1.0), (-60.0, 10.0), (-50.0, 2.0)]).unwrap();
+        let cal = CalibrationParams::default();
+
+        // Exact table points
+        assert!((model.distance(-40.0, &cal) - 1.0).abs() < 1e-3);
+        assert!((model.distance(-60.0, &cal) - 10.0).abs() < 1e-3);
+
+        // Between -50 (2m) and -60 (10m), monotonic and in range
+        let mid = model.distance(-55.0, &cal);
+        assert!(mid > 2.0 && mid < 10.0);
+
+        // Extrapolates beyond the table instead of flattening out
+        assert!(model.distance(-70.0, &cal) > 10.0);
+
+        assert!(matches!(
+            LookupTableModel::new(&[(-40.0, 1.0)]),
+            Err(SnifferError::Config(_))
+        ));
+        assert!(LookupTableModel::new(&[(-40.0, 1.0), (-60.0, 0.0)]).is_err());
+    }
+
     #[test]
     fn test_rssi_to_distance() {
         let stations = make_stations();
@@ -695,6 +2102,587 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_passthrough_returns_raw_solve() {
+        let stations = make_stations();
+        let config = TriangulatorConfig {
+            smoothing_factor: 0.9,
+            smoothing_mode: SmoothingMode::Passthrough,
+            rssi_aggregation: RssiAggregation::Mean,
+            ..Default::default()
+        };
+        let mut tracker = PositionTracker::with_config(&stations, config.clone());
+        let raw = Triangulator::with_config(&stations, config);
+
+        for (t, strong) in [(0, "1"), (1, "2")] {
+            let readings: HashMap<String, RssiReading> = ["1", "2", "3"]
+                .into_iter()
+                .map(|id| {
+                    let rssi = if id == strong { -30 } else { -70 };
+                    (id.to_string(), RssiReading { rssi, timestamp: t })
+                })
+                .collect();
+            let tracked = tracker.update_position("device1", &readings).unwrap();
+            let expected = raw.calculate_position(&readings).unwrap();
+            assert!((tracked.x - expected.x).abs() < 1e-4);
+            assert!((tracked.y - expected.y).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_passthrough_ignores_reading_counts() {
+        let stations = make_stations();
+        let config = TriangulatorConfig {
+            smoothing_mode: SmoothingMode::Passthrough,
+            reading_count_cap: 5,
+            ..Default::default()
+        };
+        let mut tracker = PositionTracker::with_config(&stations, config.clone());
+        let raw = Triangulator::with_config(&stations, config);
+
+        // Station 1 keeps reporting, stations 2 and 3 only had one reading each
+        let mut readings = HashMap::new();
+        for t in 1..=4 {
+            readings.insert("1".to_string(), RssiReading { rssi: -40, timestamp: t });
+            readings.insert("2".to_string(), RssiReading { rssi: -70, timestamp: 1 });
+            readings.insert("3".to_string(), RssiReading { rssi: -60, timestamp: 1 });
+            tracker.update_position("device1", &readings);
+        }
+
+        let tracked = tracker.get_position("device1").unwrap();
+        let expected = raw.calculate_position(&readings).unwrap();
+        assert!((tracked.x - expected.x).abs() < 1e-4);
+        assert!((tracked.y - expected.y).abs() < 1e-4);
+
+        // Weighted as if every station had reported once
+        let mut once = PositionTracker::with_config(
+            &stations,
+            TriangulatorConfig {
+                smoothing_mode: SmoothingMode::Passthrough,
+                reading_count_cap: 1,
+                ..Default::default()
+            },
+        );
+        once.update_position("device1", &readings);
+        assert_eq!(
+            tracker.get_station_weights("device1"),
+            once.get_station_weights("device1")
+        );
+    }
+
+    #[test]
+    fn test_tracker_requires_min_stations() {
+        let stations = make_stations();
+        let mut readings = HashMap::new();
+        for id in ["1", "2"] {
+            readings.insert(
+                id.to_string(),
+                RssiReading {
+                    rssi: -50,
+                    timestamp: 0,
+                },
+            );
+        }
+
+        let mut tracker = PositionTracker::new(&stations);
+        assert_eq!(tracker.contributing_stations(&readings), 2);
+        assert!(tracker.update_position("device1", &readings).is_none());
+
+        let mut tracker = PositionTracker::with_config(
+            &stations,
+            TriangulatorConfig {
+                min_stations_for_position: 2,
+                ..Default::default()
+            },
+        );
+        assert!(tracker.update_position("device1", &readings).is_some());
+    }
+
+    #[test]
+    fn test_solves_height_with_stations_at_different_heights() {
+        let device = Position::new_3d(2.0, 3.0, 1.0);
+        let rssi = -60i8;
+
+        // Calibrate each station so an RSSI of -60 maps to its exact distance from the device
+        let stations: Vec<HeightStation> = [
+            ("1", Position::new_3d(0.0, 0.0, 0.0)),
+            ("2", Position::new_3d(6.0, 0.0, 2.5)),
+            ("3", Position::new_3d(0.0, 6.0, 2.5)),
+            ("4", Position::new_3d(6.0, 6.0, 0.0)),
+            ("5", Position::new_3d(3.0, 0.0, 2.0)),
+        ]
+        .into_iter()
+        .map(|(id, pos)| HeightStation {
+            id,
+            pos,
+            rssi_at_1m: rssi as f32 + 20.0 * pos.distance_to(&device).log10(),
+        })
+        .collect();
+
+        let triangulator = Triangulator::with_config(
+            &stations,
+            TriangulatorConfig {
+                solve_mode: SolveMode::WeightedLeastSquares,
+                ..Default::default()
+            },
+        );
+
+        let readings: HashMap<String, RssiReading> = stations
+            .iter()
+            .map(|s| (s.id.to_string(), RssiReading { rssi, timestamp: 0 }))
+            .collect();
+
+        let pos = triangulator.calculate_position(&readings).unwrap();
+        assert!(
+            pos.distance_to(&device) < 0.1,
+            "Position ({}, {}, {}) should be near (2, 3, 1)",
+            pos.x,
+            pos.y,
+            pos.z
+        );
+    }
+
+    #[test]
+    fn test_two_station_circle_intersection() {
+        let stations: Vec<TestStation> = [("1", 0.0, 0.0), ("2", 6.0, 0.0), ("3", 3.0, 8.0)]
+            .into_iter()
+            .map(|(id, x, y)| TestStation {
+                id: id.to_string(),
+                x,
+                y,
+                calibration: Some(CalibrationParams {
+                    rssi_at_1m: -40.0,
+                    path_loss_exponent: 2.0,
+                    ..Default::default()
+                }),
+            })
+            .collect();
+        let mut tracker = PositionTracker::with_config(
+            &stations,
+            TriangulatorConfig {
+                few_stations_mode: FewStationsMode::CircleIntersection,
+                ..Default::default()
+            },
+        );
+
+        // Device at (3, 4): 5m from both stations -> RSSI -54
+        let mut readings = HashMap::new();
+        for id in ["1", "2"] {
+            readings.insert(id.to_string(), RssiReading { rssi: -54, timestamp: 0 });
+        }
+
+        // The mirror solution at (3, -4) is outside the room
+        let pos = tracker.update_position("device1", &readings).unwrap();
+        assert!(pos.distance_to(&Position::new(3.0, 4.0)) < 0.3, "got ({}, {})", pos.x, pos.y);
+        assert!(tracker.get_estimate("device1").unwrap().low_confidence);
+    }
+
+    #[test]
+    fn test_single_station_centroid_fallback() {
+        let stations = make_stations();
+        let mut readings = HashMap::new();
+        readings.insert("2".to_string(), RssiReading { rssi: -50, timestamp: 0 });
+
+        let mut tracker = PositionTracker::new(&stations);
+        assert!(tracker.update_position("device1", &readings).is_none());
+
+        let mut tracker = PositionTracker::with_config(
+            &stations,
+            TriangulatorConfig {
+                few_stations_mode: FewStationsMode::Centroid,
+                ..Default::default()
+            },
+        );
+        let pos = tracker.update_position("device1", &readings).unwrap();
+        assert_eq!(pos, Position::new(5.0, 0.0), "placed on the only station");
+        assert!(tracker.get_estimate("device1").unwrap().low_confidence);
+    }
+
+    #[test]
+    fn test_kalman_tracks_constant_velocity() {
+        let config = TriangulatorConfig::default();
+        let mut filter = KalmanState::new(Position::new(0.0, 2.0), 0, &config);
+
+        // Device walks along x at 1 m/s, measured every 500ms
+        for step in 1..=20u64 {
+            let t = step as f32 * 0.5;
+            filter.step(Position::new(t, 2.0), step * 500, &config);
+        }
+
+        let velocity = filter.velocity();
+        assert!((velocity.vx - 1.0).abs() < 0.2, "vx={} should be near 1 m/s", velocity.vx);
+        assert!(velocity.vy.abs() < 0.1, "vy={} should be near 0", velocity.vy);
+        assert!(filter.position().distance_to(&Position::new(10.0, 2.0)) < 0.5);
+    }
+
+    #[test]
+    fn test_tracker_estimates_and_clamps_velocity() {
+        let stations = make_stations();
+        let mut tracker = PositionTracker::with_config(
+            &stations,
+            TriangulatorConfig {
+                smoothing_factor: 0.0,
+                max_speed: 1.0,
+                ..Default::default()
+            },
+        );
+
+        let mut readings = HashMap::new();
+        for (id, rssi) in [("1", -50), ("2", -60), ("3", -60)] {
+            readings.insert(id.to_string(), RssiReading { rssi, timestamp: 0 });
+        }
+        let first = tracker.update_position("device1", &readings).unwrap();
+        assert!(tracker.get_estimate("device1").unwrap().velocity.is_none());
+
+        // Jump towards station 2 within 100ms, far faster than 1 m/s
+        for (id, rssi) in [("1", -60), ("2", -50), ("3", -60)] {
+            readings.insert(id.to_string(), RssiReading { rssi, timestamp: 100 });
+        }
+        let second = tracker.update_position("device1", &readings).unwrap();
+        assert!(second.x > first.x);
+
+        let estimate = tracker.get_estimate("device1").unwrap();
+        let velocity = estimate.velocity.unwrap();
+        assert!(velocity.vx > 0.0);
+        assert!((velocity.speed() - 1.0).abs() < 1e-3, "speed should be clamped to max_speed");
+        assert!(estimate.implausible_speed);
+    }
+
+    #[test]
+    fn test_median_aggregation_ignores_spike() {
+        let stations = make_stations();
+        let config = |rssi_aggregation| TriangulatorConfig {
+            rssi_aggregation,
+            smoothing_factor: 0.0,
+            ..Default::default()
+        };
+        let mut median = PositionTracker::with_config(&stations, config(RssiAggregation::Median));
+        let mut latest = PositionTracker::with_config(&stations, config(RssiAggregation::Latest));
+
+        // Station 1 reads -60 twice, then one reflection spike at -35
+        let mut readings = HashMap::new();
+        for (t, spike) in [(1, -60), (2, -60), (3, -35)] {
+            for (id, rssi) in [("1", spike), ("2", -60), ("3", -60)] {
+                readings.insert(id.to_string(), RssiReading { rssi, timestamp: t });
+            }
+            median.update_position("device1", &readings);
+            latest.update_position("device1", &readings);
+        }
+
+        // Median stays where all three stations agree, latest is pulled onto station 1
+        let station1 = Position::new(0.0, 0.0);
+        let median_pos = median.get_position("device1").unwrap();
+        let latest_pos = latest.get_position("device1").unwrap();
+        assert!(median_pos.distance_to(&station1) > latest_pos.distance_to(&station1) + 0.5);
+    }
+
+    #[test]
+    fn test_station_weights_follow_reading_count() {
+        let stations = make_stations();
+        let config = |reading_count_cap| TriangulatorConfig {
+            reading_count_cap,
+            ..Default::default()
+        };
+        let mut counted = PositionTracker::with_config(&stations, config(5));
+        let mut flat = PositionTracker::with_config(&stations, config(1));
+
+        // Station 1 keeps reporting, stations 2 and 3 only had one reading each
+        let mut readings = HashMap::new();
+        for t in 1..=4 {
+            readings.insert("1".to_string(), RssiReading { rssi: -60, timestamp: t });
+            readings.insert("2".to_string(), RssiReading { rssi: -60, timestamp: 1 });
+            readings.insert("3".to_string(), RssiReading { rssi: -60, timestamp: 1 });
+            counted.update_position("device1", &readings);
+            flat.update_position("device1", &readings);
+        }
+
+        let counted = counted.get_station_weights("device1").unwrap();
+        let flat = flat.get_station_weights("device1").unwrap();
+        assert!((counted["1"] - 4.0 / 6.0).abs() < 1e-4);
+        assert!((counted["2"] - 1.0 / 6.0).abs() < 1e-4);
+        assert!((flat["1"] - 1.0 / 3.0).abs() < 1e-4);
+        assert!((counted.values().sum::<f32>() - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_tracker_routes_to_floor_that_saw_device() {
+        let stations = vec![
+            FloorStation { id: "g1", floor: "ground", x: 0.0, y: 0.0 },
+            FloorStation { id: "g2", floor: "ground", x: 5.0, y: 0.0 },
+            FloorStation { id: "g3", floor: "ground", x: 2.5, y: 5.0 },
+            FloorStation { id: "u1", floor: "upper", x: 10.0, y: 10.0 },
+        ];
+        let mut tracker = PositionTracker::with_config(
+            &stations,
+            TriangulatorConfig {
+                min_stations_for_position: 1,
+                ..Default::default()
+            },
+        );
+
+        // Three ground-floor readings outweigh one stronger upper-floor reading
+        let mut readings = HashMap::new();
+        for (id, rssi) in [("g1", -60), ("g2", -60), ("g3", -60), ("u1", -40)] {
+            readings.insert(id.to_string(), RssiReading { rssi, timestamp: 0 });
+        }
+
+        let pos = tracker.update_position("device1", &readings).unwrap();
+        assert_eq!(tracker.get_floor("device1"), Some("ground"));
+        assert_eq!(tracker.contributing_stations(&readings), 3);
+        assert!(pos.x <= 6.0 && pos.y <= 6.0, "Position should stay in ground-floor geometry");
+
+        // Only the upper floor sees the device now
+        readings.clear();
+        readings.insert("u1".to_string(), RssiReading { rssi: -40, timestamp: 1 });
+        tracker.update_position("device1", &readings).unwrap();
+        assert_eq!(tracker.get_floor("device1"), Some("upper"));
+    }
+
+    #[test]
+    fn test_add_and_remove_stations_keeps_devices() {
+        let stations = vec![
+            FloorStation { id: "g1", floor: "ground", x: 0.0, y: 0.0 },
+            FloorStation { id: "g2", floor: "ground", x: 5.0, y: 0.0 },
+            FloorStation { id: "g3", floor: "ground", x: 2.5, y: 5.0 },
+        ];
+        let mut tracker = PositionTracker::with_config(
+            &stations,
+            TriangulatorConfig {
+                min_stations_for_position: 1,
+                rssi_aggregation: RssiAggregation::Median,
+                ..Default::default()
+            },
+        );
+
+        let mut readings = HashMap::new();
+        for (id, rssi) in [("g1", -60), ("g2", -60), ("g3", -60)] {
+            readings.insert(id.to_string(), RssiReading { rssi, timestamp: 0 });
+        }
+        tracker.update_position("device1", &readings).unwrap();
+
+        // A fourth station far out widens the room, the device keeps its estimate
+        tracker.add_station(&FloorStation { id: "g4", floor: "ground", x: 20.0, y: 20.0 });
+        assert!(tracker.get_estimate("device1").is_some());
+        readings.insert("g4".to_string(), RssiReading { rssi: -60, timestamp: 0 });
+        assert_eq!(tracker.contributing_stations(&readings), 4);
+
+        // Removing it drops its readings from the device history
+        tracker.update_position("device1", &readings).unwrap();
+        tracker.remove_station("g4");
+        assert!(!tracker.reading_history["device1"].contains_key("g4"));
+        assert_eq!(tracker.contributing_stations(&readings), 3);
+        assert_eq!(tracker.get_floor("device1"), Some("ground"));
+
+        // Moving a station to a new floor creates it, emptying a floor forgets it
+        tracker.add_station(&FloorStation { id: "u1", floor: "upper", x: 10.0, y: 10.0 });
+        tracker.add_station(&FloorStation { id: "u1", floor: "attic", x: 10.0, y: 10.0 });
+        assert!(!tracker.floors.contains_key("upper"));
+        assert!(tracker.floors["attic"].stations.contains_key("u1"));
+
+        for id in ["g1", "g2", "g3"] {
+            tracker.remove_station(id);
+        }
+        assert!(!tracker.floors.contains_key("ground"));
+        assert_eq!(tracker.get_floor("device1"), None);
+        assert!(tracker.get_position("device1").is_some());
+    }
+
+    #[test]
+    fn test_weighted_least_squares_accuracy() {
+        let stations: Vec<TestStation> = [("1", 0.0, 0.0), ("2", 6.0, 0.0), ("3", 0.0, 6.0), ("4", 6.0, 6.0)]
+            .into_iter()
+            .map(|(id, x, y)| TestStation {
+                id: id.to_string(),
+                x,
+                y,
+                calibration: Some(CalibrationParams {
+                    rssi_at_1m: -40.0,
+                    path_loss_exponent: 2.0,
+                    ..Default::default()
+                }),
+            })
+            .collect();
+
+        let triangulator = Triangulator::with_config(
+            &stations,
+            TriangulatorConfig {
+                solve_mode: SolveMode::WeightedLeastSquares,
+                ..Default::default()
+            },
+        );
+
+        // Device at (2, 2): distances 2.83, 4.47, 4.47, 5.66 -> RSSI -49, -53, -53, -55
+        let mut readings = HashMap::new();
+        for (id, rssi) in [("1", -49), ("2", -53), ("3", -53), ("4", -55)] {
+            readings.insert(id.to_string(), RssiReading { rssi, timestamp: 0 });
+        }
+
+        let estimate = triangulator.estimate_position(&readings, None).unwrap();
+        let error = estimate.position.distance_to(&Position::new(2.0, 2.0));
+        assert!(
+            error < 0.5,
+            "Position ({}, {}) should be within 0.5m of (2, 2)",
+            estimate.position.x,
+            estimate.position.y
+        );
+        assert_eq!(estimate.station_count, 4);
+        assert!(estimate.residual < 0.5, "residual={} should be small", estimate.residual);
+        assert!(
+            estimate.estimated_error > 0.0 && estimate.estimated_error < 3.0,
+            "estimated_error={} should be a few meters at most",
+            estimate.estimated_error
+        );
+    }
+
+    fn stations_at(positions: &[(f32, f32)]) -> Vec<TestStation> {
+        positions
+            .iter()
+            .enumerate()
+            .map(|(i, &(x, y))| TestStation {
+                id: (i + 1).to_string(),
+                x,
+                y,
+                calibration: None,
+            })
+            .collect()
+    }
+
+    fn equal_readings(count: usize) -> HashMap<String, RssiReading> {
+        (1..=count)
+            .map(|i| (i.to_string(), RssiReading { rssi: -50, timestamp: 0 }))
+            .collect()
+    }
+
+    #[test]
+    fn test_gdop_grows_when_stations_bunch_up() {
+        let device = Position::new(5.0, 5.0);
+        let measurements = |positions: &[(f32, f32)]| -> Vec<DistanceMeasurement> {
+            positions
+                .iter()
+                .enumerate()
+                .map(|(i, &(x, y))| DistanceMeasurement {
+                    station_id: i.to_string(),
+                    station_pos: Position::new(x, y),
+                    estimated_distance: device.distance_to(&Position::new(x, y)),
+                    weight: 1.0,
+                })
+                .collect()
+        };
+
+        // Four stations around the device at right angles: the best case
+        let surrounding = measurements(&[(0.0, 5.0), (10.0, 5.0), (5.0, 0.0), (5.0, 10.0)]);
+        let gdop = Triangulator::dilution_of_precision(&device, &surrounding).unwrap();
+        assert!((gdop - 1.0).abs() < 1e-3, "gdop={} should be 1", gdop);
+
+        // Same count of stations, all on one side
+        let one_side = measurements(&[(10.0, 4.0), (10.0, 4.5), (10.0, 5.5), (10.0, 6.0)]);
+        let bunched = Triangulator::dilution_of_precision(&device, &one_side).unwrap();
+        assert!(bunched > 3.0 * gdop, "gdop={} should be far worse than {}", bunched, gdop);
+
+        // In line with the device: no fix at all
+        let in_line = measurements(&[(0.0, 5.0), (10.0, 5.0), (20.0, 5.0)]);
+        assert_eq!(Triangulator::dilution_of_precision(&device, &in_line), None);
+    }
+
+    #[test]
+    fn test_collinear_stations_give_no_position() {
+        let stations = stations_at(&[(0.0, 0.0), (2.5, 0.0), (5.0, 0.0)]);
+        let readings = equal_readings(3);
+
+        let modes = [
+            SolveMode::GradientDescent,
+            SolveMode::WeightedLeastSquares,
+            SolveMode::GaussNewton,
+        ];
+        for solve_mode in modes {
+            let triangulator = Triangulator::with_config(
+                &stations,
+                TriangulatorConfig {
+                    solve_mode,
+                    ..Default::default()
+                },
+            );
+            assert!(triangulator.calculate_position(&readings).is_none(), "{:?}", solve_mode);
+        }
+    }
+
+    #[test]
+    fn test_identical_stations_give_no_position() {
+        let stations = stations_at(&[(1.0, 1.0), (1.0, 1.0), (1.0, 1.0)]);
+        let mut tracker = PositionTracker::new(&stations);
+
+        assert!(tracker.update_position("device1", &equal_readings(3)).is_none());
+        assert!(tracker.get_position("device1").is_none());
+    }
+
+    #[test]
+    fn test_gauss_newton_accuracy() {
+        let stations = make_stations();
+        let triangulator = Triangulator::with_config(
+            &stations,
+            TriangulatorConfig {
+                solve_mode: SolveMode::GaussNewton,
+                ..Default::default()
+            },
+        );
+
+        // Equal readings from the two bottom stations put the device on the x = 2.5 axis
+        let mut readings = HashMap::new();
+        for (id, rssi) in [("1", -50), ("2", -50), ("3", -52)] {
+            readings.insert(id.to_string(), RssiReading { rssi, timestamp: 0 });
+        }
+
+        let gauss_newton = triangulator.estimate_position(&readings, None).unwrap();
+        let gradient_descent = Triangulator::new(&stations)
+            .estimate_position(&readings, None)
+            .unwrap();
+        assert!((gauss_newton.position.x - 2.5).abs() < 0.1);
+        assert!(
+            gauss_newton.residual <= gradient_descent.residual + 1e-3,
+            "Gauss-Newton residual {} should be no worse than gradient descent {}",
+            gauss_newton.residual,
+            gradient_descent.residual
+        );
+    }
+
+    #[test]
+    fn test_rejects_stale_and_outlier_readings() {
+        let stations: Vec<TestStation> = [("1", 0.0, 0.0), ("2", 6.0, 0.0), ("3", 0.0, 6.0), ("4", 6.0, 6.0)]
+            .into_iter()
+            .map(|(id, x, y)| TestStation {
+                id: id.to_string(),
+                x,
+                y,
+                calibration: Some(CalibrationParams {
+                    rssi_at_1m: -40.0,
+                    path_loss_exponent: 2.0,
+                    ..Default::default()
+                }),
+            })
+            .collect();
+        let triangulator = Triangulator::with_config(
+            &stations,
+            TriangulatorConfig {
+                solve_mode: SolveMode::WeightedLeastSquares,
+                ..Default::default()
+            },
+        );
+
+        // Device at (2, 2), but station 4 reports a reflection as if it were 1m away
+        let mut readings = HashMap::new();
+        for (id, rssi) in [("1", -49), ("2", -53), ("3", -53), ("4", -40)] {
+            readings.insert(id.to_string(), RssiReading { rssi, timestamp: 60_000 });
+        }
+
+        let estimate = triangulator.estimate_position(&readings, None).unwrap();
+        assert_eq!(estimate.station_count, 3, "the inconsistent reading should be dropped");
+        assert!(estimate.position.distance_to(&Position::new(2.0, 2.0)) < 0.5);
+
+        // A minute-old reading is ignored entirely
+        readings.get_mut("4").unwrap().timestamp = 0;
+        assert_eq!(triangulator.contributing_stations(&readings), 3);
+    }
+
     #[test]
     fn test_trilateration_accuracy() {
         // Create stations at known positions
@@ -706,6 +2694,7 @@ mod tests {
                 calibration: Some(CalibrationParams {
                     rssi_at_1m: -40.0,
                     path_loss_exponent: 2.0,
+                    ..Default::default()
                 }),
             },
             TestStation {
@@ -715,6 +2704,7 @@ mod tests {
                 calibration: Some(CalibrationParams {
                     rssi_at_1m: -40.0,
                     path_loss_exponent: 2.0,
+                    ..Default::default()
                 }),
             },
             TestStation {
@@ -724,6 +2714,7 @@ mod tests {
                 calibration: Some(CalibrationParams {
                     rssi_at_1m: -40.0,
                     path_loss_exponent: 2.0,
+                    ..Default::default()
                 }),
             },
         ];
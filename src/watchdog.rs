@@ -0,0 +1,108 @@
+use anyhow::{bail, Result};
+use esp_idf_svc::sys::{
+    esp_reset_reason,
+    esp_reset_reason_t,
+    esp_reset_reason_t_ESP_RST_BROWNOUT,
+    esp_reset_reason_t_ESP_RST_DEEPSLEEP,
+    esp_reset_reason_t_ESP_RST_EXT,
+    esp_reset_reason_t_ESP_RST_INT_WDT,
+    esp_reset_reason_t_ESP_RST_PANIC,
+    esp_reset_reason_t_ESP_RST_POWERON,
+    esp_reset_reason_t_ESP_RST_SW,
+    esp_reset_reason_t_ESP_RST_TASK_WDT,
+    esp_reset_reason_t_ESP_RST_WDT,
+    esp_task_wdt_add,
+    esp_task_wdt_config_t,
+    esp_task_wdt_delete,
+    esp_task_wdt_reconfigure,
+    esp_task_wdt_reset,
+    ESP_OK,
+};
+use std::time::Duration;
+
+/// Default task watchdog timeout, override with WATCHDOG_TIMEOUT_SECS at build time
+/// Must stay above the OTA download timeout, the main loop can wait that long for a chunk
+const DEFAULT_TIMEOUT_SECS: u64 = 60;
+
+/// Watch the idle tasks of both ESP32 cores too (the ESP-IDF default)
+const IDLE_CORE_MASK: u32 = 0b11;
+
+/// Configured task watchdog timeout
+pub fn timeout() -> Duration {
+    let secs = option_env!("WATCHDOG_TIMEOUT_SECS")
+        .and_then(|secs| secs.parse().ok())
+        .filter(|&secs| secs > 0)
+        .unwrap_or(DEFAULT_TIMEOUT_SECS);
+    Duration::from_secs(secs)
+}
+
+/// Apply the configured timeout, a task that isn't fed within it panics and reboots the station
+pub fn init() -> Result<()> {
+    let timeout = timeout();
+    let config = esp_task_wdt_config_t {
+        timeout_ms: timeout.as_millis() as u32,
+        idle_core_mask: IDLE_CORE_MASK,
+        trigger_panic: true,
+    };
+    let ret = unsafe { esp_task_wdt_reconfigure(&config) };
+    if ret != ESP_OK {
+        bail!("Failed to configure task watchdog: {}", ret);
+    }
+    log::info!("Task watchdog timeout: {}s", timeout.as_secs());
+    Ok(())
+}
+
+/// Subscribe the calling task, it must then call `feed` regularly
+pub fn subscribe() -> Result<()> {
+    let ret = unsafe { esp_task_wdt_add(core::ptr::null_mut()) };
+    if ret != ESP_OK {
+        bail!("Failed to subscribe task to watchdog: {}", ret);
+    }
+    Ok(())
+}
+
+/// Unsubscribe the calling task, required before a subscribed thread exits
+pub fn unsubscribe() {
+    unsafe {
+        esp_task_wdt_delete(core::ptr::null_mut());
+    }
+}
+
+/// Feed the watchdog for the calling task
+pub fn feed() {
+    unsafe {
+        esp_task_wdt_reset();
+    }
+}
+
+/// Log why the station last reset, watchdog resets point to a hung task
+pub fn log_reset_reason() {
+    let reason = unsafe { esp_reset_reason() };
+    let name = reset_reason_name(reason);
+    let watchdog = matches!(
+        reason,
+        esp_reset_reason_t_ESP_RST_TASK_WDT
+            | esp_reset_reason_t_ESP_RST_INT_WDT
+            | esp_reset_reason_t_ESP_RST_WDT
+    );
+    if watchdog {
+        log::warn!("Reset reason: {} - the previous run hung", name);
+    } else {
+        log::info!("Reset reason: {}", name);
+    }
+}
+
+fn reset_reason_name(reason: esp_reset_reason_t) -> &'static str {
+    match reason {
+        esp_reset_reason_t_ESP_RST_POWERON => "power on",
+        esp_reset_reason_t_ESP_RST_EXT => "external pin",
+        esp_reset_reason_t_ESP_RST_SW => "software restart",
+        esp_reset_reason_t_ESP_RST_PANIC => "panic",
+        esp_reset_reason_t_ESP_RST_INT_WDT => "interrupt watchdog",
+        esp_reset_reason_t_ESP_RST_TASK_WDT => "task watchdog",
+        esp_reset_reason_t_ESP_RST_WDT => "other watchdog",
+        esp_reset_reason_t_ESP_RST_DEEPSLEEP => "deep sleep wakeup",
+        esp_reset_reason_t_ESP_RST_BROWNOUT => "brownout",
+        _ => "unknown",
+    }
+}
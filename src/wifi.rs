@@ -5,14 +5,296 @@ use esp_idf_svc::{
     eventloop::EspSystemEventLoop,
     hal::modem::Modem,
     nvs::EspDefaultNvsPartition,
+    sys::{
+        esp_event_base_t, esp_event_handler_register, esp_wifi_get_country,
+        esp_wifi_set_bandwidth, esp_wifi_set_country, ip_event_got_ip_t,
+        ip_event_t_IP_EVENT_STA_GOT_IP, ip_event_t_IP_EVENT_STA_LOST_IP,
+        wifi_bandwidth_t_WIFI_BW_HT20, wifi_bandwidth_t_WIFI_BW_HT40,
+        wifi_country_policy_t_WIFI_COUNTRY_POLICY_AUTO,
+        wifi_country_policy_t_WIFI_COUNTRY_POLICY_MANUAL, wifi_country_t,
+        wifi_event_sta_disconnected_t, wifi_event_t_WIFI_EVENT_STA_CONNECTED,
+        wifi_event_t_WIFI_EVENT_STA_DISCONNECTED, wifi_event_t_WIFI_EVENT_STA_START,
+        wifi_interface_t_WIFI_IF_STA, ESP_EVENT_ANY_ID, ESP_OK, IP_EVENT, WIFI_EVENT,
+    },
     wifi::{BlockingWifi, EspWifi},
 };
-use log::info;
+use log::{info, warn};
+use std::ffi::c_void;
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicI8, AtomicU16, AtomicU32, AtomicU8, Ordering};
+use crate::credentials;
 
-const SSID: &str = env!("WIFI_SSID");
-const PASSWORD: &str = env!("WIFI_PASS");
+/// Worldwide safe regulatory domain: channels 1-11, adopting the AP's country (802.11d)
+const WORLD_SAFE_COUNTRY: &str = "01";
 
+/// Connection state as seen by the driver events
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WifiState {
+    /// Not associated, before the driver starts or after the link dropped
+    Disconnected,
+    /// Associating with the AP or waiting for DHCP
+    Connecting,
+    /// Associated and holding an IP address
+    Connected {
+        ip: Ipv4Addr,
+        /// Signal of the AP, refreshed by the connection check
+        rssi: i8,
+        ssid: &'static str,
+    },
+}
+
+const STATE_DISCONNECTED: u8 = 0;
+const STATE_CONNECTING: u8 = 1;
+const STATE_CONNECTED: u8 = 2;
+
+/// `WifiState` split into atomics so any thread can read it without locking, written only
+/// by the event handlers and `refresh_rssi`
+static STATE: AtomicU8 = AtomicU8::new(STATE_DISCONNECTED);
+/// IPv4 address as the driver stores it (network byte order in memory)
+static IP_ADDR: AtomicU32 = AtomicU32::new(0);
+static RSSI: AtomicI8 = AtomicI8::new(0);
+
+/// Reason code of the last disconnect reported by the driver, 0 before the first
+static DISCONNECT_REASON: AtomicU16 = AtomicU16::new(0);
+
+/// Common disconnect reason codes (IEEE 802.11 and ESP-IDF specific) and what they mean
+const DISCONNECT_REASONS: &[(u16, &str)] = &[
+    (1, "unspecified"),
+    (2, "authentication expired"),
+    (3, "deauthenticated, AP is leaving or restarting"),
+    (4, "disassociated due to inactivity"),
+    (5, "AP has too many stations"),
+    (6, "frame from an unauthenticated station"),
+    (7, "frame from an unassociated station"),
+    (8, "disassociated, AP is leaving"),
+    (15, "4-way handshake timeout, wrong password?"),
+    (16, "group key update timeout"),
+    (23, "802.1X authentication failed"),
+    (34, "too many frames not acked, weak link"),
+    (200, "beacon timeout, AP out of range or gone"),
+    (201, "AP not found"),
+    (202, "authentication failed"),
+    (203, "association failed"),
+    (204, "handshake timeout, wrong password?"),
+    (205, "connection failed"),
+    (206, "AP reset its timer"),
+    (207, "roaming"),
+];
+
+/// Human-readable description of a disconnect reason code
+pub fn describe_disconnect_reason(reason: u16) -> &'static str {
+    DISCONNECT_REASONS
+        .iter()
+        .find(|(code, _)| *code == reason)
+        .map_or("unknown reason", |(_, description)| description)
+}
+
+/// Reason code of the last disconnect, `None` if the link never dropped
+pub fn last_disconnect_reason() -> Option<u16> {
+    Some(DISCONNECT_REASON.load(Ordering::Relaxed)).filter(|&reason| reason != 0)
+}
+
+/// WiFi driver event handler, tracks the connection state and the reason of each disconnect
+unsafe extern "C" fn on_wifi_event(
+    _arg: *mut c_void,
+    _base: esp_event_base_t,
+    id: i32,
+    data: *mut c_void,
+) {
+    match id as u32 {
+        wifi_event_t_WIFI_EVENT_STA_START | wifi_event_t_WIFI_EVENT_STA_CONNECTED => {
+            STATE.store(STATE_CONNECTING, Ordering::Release);
+        }
+        wifi_event_t_WIFI_EVENT_STA_DISCONNECTED => {
+            if let Some(event) = (data as *const wifi_event_sta_disconnected_t).as_ref() {
+                DISCONNECT_REASON.store(event.reason as u16, Ordering::Relaxed);
+            }
+            STATE.store(STATE_DISCONNECTED, Ordering::Release);
+        }
+        _ => {}
+    }
+}
+
+/// IP event handler, the station counts as connected once DHCP assigned an address
+unsafe extern "C" fn on_ip_event(
+    _arg: *mut c_void,
+    _base: esp_event_base_t,
+    id: i32,
+    data: *mut c_void,
+) {
+    match id as u32 {
+        ip_event_t_IP_EVENT_STA_GOT_IP => {
+            if let Some(event) = (data as *const ip_event_got_ip_t).as_ref() {
+                IP_ADDR.store(event.ip_info.ip.addr, Ordering::Relaxed);
+            }
+            refresh_rssi();
+            STATE.store(STATE_CONNECTED, Ordering::Release);
+        }
+        ip_event_t_IP_EVENT_STA_LOST_IP => {
+            // Still associated, DHCP will try again
+            STATE.store(STATE_CONNECTING, Ordering::Release);
+        }
+        _ => {}
+    }
+}
+
+/// Register the state handlers, before the driver starts so no event is missed
+fn register_event_handlers() -> Result<()> {
+    let ret = unsafe {
+        esp_event_handler_register(
+            WIFI_EVENT,
+            ESP_EVENT_ANY_ID,
+            Some(on_wifi_event),
+            core::ptr::null_mut(),
+        )
+    };
+    if ret != ESP_OK {
+        anyhow::bail!("Failed to register the WiFi event handler: {}", ret);
+    }
+    let ret = unsafe {
+        esp_event_handler_register(
+            IP_EVENT,
+            ESP_EVENT_ANY_ID,
+            Some(on_ip_event),
+            core::ptr::null_mut(),
+        )
+    };
+    if ret != ESP_OK {
+        anyhow::bail!("Failed to register the IP event handler: {}", ret);
+    }
+    Ok(())
+}
+
+/// Current connection state, a few atomic loads
+pub fn connection_state() -> WifiState {
+    match STATE.load(Ordering::Acquire) {
+        STATE_CONNECTED => WifiState::Connected {
+            ip: Ipv4Addr::from(IP_ADDR.load(Ordering::Relaxed).to_le_bytes()),
+            rssi: RSSI.load(Ordering::Relaxed),
+            ssid: &credentials::get().wifi_ssid,
+        },
+        STATE_CONNECTING => WifiState::Connecting,
+        _ => WifiState::Disconnected,
+    }
+}
+
+/// Whether WiFi is connected
+pub fn is_connected() -> bool {
+    STATE.load(Ordering::Acquire) == STATE_CONNECTED
+}
+
+/// Read the AP's signal into the connection state, no events report RSSI changes
+fn refresh_rssi() {
+    let mut ap_info = esp_idf_svc::sys::wifi_ap_record_t::default();
+    let ret = unsafe { esp_idf_svc::sys::esp_wifi_sta_get_ap_info(&mut ap_info) };
+    if ret == ESP_OK {
+        RSSI.store(ap_info.rssi, Ordering::Relaxed);
+    }
+}
+
+/// BSSID of the AP we're connected to
+pub fn connected_bssid() -> Option<[u8; 6]> {
+    let mut ap_info = esp_idf_svc::sys::wifi_ap_record_t::default();
+    let ret = unsafe { esp_idf_svc::sys::esp_wifi_sta_get_ap_info(&mut ap_info) };
+    (ret == esp_idf_svc::sys::ESP_OK).then_some(ap_info.bssid)
+}
+
+/// Reconnect if the link to the AP was lost, returns true when it had to reconnect
+///
+/// Blocks until the network interface is up again or the attempt fails.
+pub fn ensure_connected(wifi: &mut BlockingWifi<EspWifi<'static>>) -> Result<bool> {
+    match connection_state() {
+        WifiState::Connected { .. } => {
+            refresh_rssi();
+            return Ok(false);
+        }
+        // A connect() of ours is still running or DHCP is pending, don't start another
+        WifiState::Connecting if wifi.is_connected()? => return Ok(false),
+        _ => {}
+    }
+    let reason = DISCONNECT_REASON.load(Ordering::Relaxed);
+    warn!(
+        "WiFi connection lost (reason {}: {}), reconnecting",
+        reason,
+        describe_disconnect_reason(reason)
+    );
+    wifi.connect()?;
+    wifi.wait_netif_up()?;
+
+    let ip_info = wifi.wifi().sta_netif().get_ip_info()?;
+    info!("WiFi reconnected - IP: {}", ip_info.ip);
+    Ok(true)
+}
+
+/// Channels 1..=n allowed in a 2.4 GHz regulatory domain
+fn channel_count(country: &str) -> u8 {
+    match country {
+        "01" | "US" | "CA" | "TW" => 11,
+        "JP" => 14,
+        _ => 13,
+    }
+}
+
+/// Apply the regulatory domain from WIFI_COUNTRY (ISO 3166 code, e.g. `DK`), must run before
+/// the driver starts
+///
+/// Without WIFI_COUNTRY the worldwide safe domain is used and the country advertised by
+/// the AP is adopted once connected. A configured country is fixed and wins over the AP's.
+fn apply_country() -> Result<()> {
+    let configured = option_env!("WIFI_COUNTRY").filter(|cc| !cc.is_empty());
+    let code = configured.unwrap_or(WORLD_SAFE_COUNTRY).to_ascii_uppercase();
+    let &[a, b] = code.as_bytes() else {
+        anyhow::bail!("WIFI_COUNTRY must be a two-letter country code, got '{}'", code);
+    };
 
+    let country = wifi_country_t {
+        cc: [a as _, b as _, 0],
+        schan: 1,
+        nchan: channel_count(&code),
+        max_tx_power: 20, // dBm, the driver clamps it to the domain's limit
+        policy: if configured.is_some() {
+            wifi_country_policy_t_WIFI_COUNTRY_POLICY_MANUAL
+        } else {
+            wifi_country_policy_t_WIFI_COUNTRY_POLICY_AUTO
+        },
+    };
+    let ret = unsafe { esp_wifi_set_country(&country) };
+    if ret != ESP_OK {
+        anyhow::bail!("Failed to set WiFi country {}: {}", code, ret);
+    }
+
+    let mut active = wifi_country_t::default();
+    let ret = unsafe { esp_wifi_get_country(&mut active) };
+    if ret != ESP_OK {
+        anyhow::bail!("Failed to read back WiFi country: {}", ret);
+    }
+    info!(
+        "WiFi regulatory domain {}: channels {}-{}{}",
+        code,
+        active.schan,
+        active.schan + active.nchan - 1,
+        if configured.is_some() { "" } else { " (follows the AP's country once connected)" }
+    );
+    Ok(())
+}
+
+/// Apply the station's channel width from WIFI_BANDWIDTH (`ht20` by default, or `ht40`)
+///
+/// HT40 doubles the possible uplink rate if the AP runs 40 MHz, but occupies a secondary
+/// channel next to the AP's and is more exposed to interference; HT20 is the stable choice.
+fn apply_bandwidth() -> Result<()> {
+    let (name, bandwidth) = match option_env!("WIFI_BANDWIDTH") {
+        None | Some("") | Some("ht20") => ("HT20", wifi_bandwidth_t_WIFI_BW_HT20),
+        Some("ht40") => ("HT40", wifi_bandwidth_t_WIFI_BW_HT40),
+        Some(other) => anyhow::bail!("WIFI_BANDWIDTH must be ht20 or ht40, got '{}'", other),
+    };
+    let ret = unsafe { esp_wifi_set_bandwidth(wifi_interface_t_WIFI_IF_STA, bandwidth) };
+    if ret != ESP_OK {
+        anyhow::bail!("Failed to set WiFi bandwidth {}: {}", name, ret);
+    }
+    info!("WiFi bandwidth {}", name);
+    Ok(())
+}
 
 /// Initialize WiFi and connect to the configured network
 pub fn initialize_wifi_connected(
@@ -20,7 +302,8 @@ pub fn initialize_wifi_connected(
     sys_loop: EspSystemEventLoop,
     nvs: EspDefaultNvsPartition,
 ) -> Result<BlockingWifi<EspWifi<'static>>> {
-    info!("Initializing WiFi - SSID: '{}'", SSID);
+    let credentials = credentials::get();
+    info!("Initializing WiFi - SSID: '{}'", credentials.wifi_ssid);
 
     let mut wifi = BlockingWifi::wrap(
         EspWifi::new(modem, sys_loop.clone(), Some(nvs))?,
@@ -28,14 +311,29 @@ pub fn initialize_wifi_connected(
     )?;
 
     let wifi_configuration = Configuration::Client(ClientConfiguration {
-        ssid: SSID.try_into().unwrap(),
-        password: PASSWORD.try_into().unwrap(),
-        auth_method: AuthMethod::WPA2Personal,
+        ssid: credentials
+            .wifi_ssid
+            .as_str()
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("WiFi SSID longer than 32 bytes"))?,
+        password: credentials
+            .wifi_pass
+            .as_str()
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("WiFi password longer than 64 bytes"))?,
+        auth_method: if credentials.wifi_pass.is_empty() {
+            AuthMethod::None
+        } else {
+            AuthMethod::WPA2Personal
+        },
         ..Default::default()
     });
 
     wifi.set_configuration(&wifi_configuration)?;
+    register_event_handlers()?;
+    apply_country()?;
     wifi.start()?;
+    apply_bandwidth()?;
     wifi.connect()?;
     wifi.wait_netif_up()?;
 